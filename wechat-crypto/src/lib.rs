@@ -185,9 +185,16 @@ pub fn verify_url(
 /// 移除前16位随机数，返回消息体和消息的 receiver_id
 pub fn parse_plain_text(plaintext: &[u8]) -> Result<(String, String)> {
     // let random = &plaintext[..16];
+    if plaintext.len() < 20 {
+        return Err(anyhow!("明文长度 {} 不正确", plaintext.len()));
+    }
     let msg_len = u32::from_be_bytes([plaintext[16], plaintext[17], plaintext[18], plaintext[19]]);
-    let msg = &plaintext[20..(20 + msg_len as usize)];
-    let receiver_id = &plaintext[(20 + msg_len as usize)..];
+    let end = 20usize
+        .checked_add(msg_len as usize)
+        .filter(|end| *end <= plaintext.len())
+        .ok_or_else(|| anyhow!("消息长度 {} 超出明文范围", msg_len))?;
+    let msg = &plaintext[20..end];
+    let receiver_id = &plaintext[end..];
     Ok((
         String::from_utf8_lossy(msg).to_string(),
         String::from_utf8_lossy(receiver_id).to_string(),
@@ -227,8 +234,14 @@ pub fn decrypt(aes_key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
     let r = cipher
         .decrypt_padded_b2b_mut::<NoPadding>(data, &mut buffer)
         .map_err(|e| anyhow!("解密失败 {}", e))?;
-    let end = r.len() - (r[r.len() - 1] as usize);
-    Ok(r[..end].to_vec())
+    let pad = match r.last() {
+        Some(p) => *p as usize,
+        None => return Err(anyhow!("密文为空")),
+    };
+    if pad == 0 || pad > r.len() {
+        return Err(anyhow!("填充长度 {} 不正确", pad));
+    }
+    Ok(r[..r.len() - pad].to_vec())
 }
 
 /// 使用 AES256 CBC 按照微信文档数据格式进行加密
@@ -260,7 +273,7 @@ pub fn encrypt(aes_key: &[u8], plaintext: &str, corp_id: &str) -> Result<Vec<u8>
     let cipher = Aes256CbcEnc::new_from_slices(key, iv)
         .map_err(|e| anyhow::Error::new(e).context("初始化加密函数失败"))?;
 
-    let mut buffer = vec![0u8; (wtr.len() + 15) / 16 * 16];
+    let mut buffer = vec![0u8; wtr.len().div_ceil(16) * 16];
     let r = cipher
         .encrypt_padded_b2b_mut::<Pkcs7>(wtr.as_slice(), &mut buffer)
         .map_err(|e| anyhow!("解密失败 {}", e))?;
@@ -366,6 +379,18 @@ mod test {
         assert_eq!("test", &t);
     }
 
+    #[test]
+    fn test_decrypt_malformed() -> Result<()> {
+        let aes_key = decode_aes_key("kWxPEV2UEDyxWpmPdKC3F4dgPDmOvfKX1HGnEUDS1aQ")?;
+        assert!(decrypt(&aes_key, &[]).is_err());
+        assert!(decrypt(&aes_key, &[0u8; 15]).is_err());
+        assert!(parse_plain_text(&[0u8; 19]).is_err());
+        let mut b = vec![0u8; 24];
+        b[19] = 5;
+        assert!(parse_plain_text(&b).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_encrypt() -> Result<()> {
         let encoded_aes_key = "kWxPEV2UEDyxWpmPdKC3F4dgPDmOvfKX1HGnEUDS1aQ";
@@ -0,0 +1,283 @@
+use crate::backend::error::{Error, Result};
+use crate::backend::mp::MP;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Extension, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// ```toml
+/// [answer]
+/// base_url = "https://wp.example.com"
+/// min_bytes = 1500
+/// oauth = true
+/// ```
+#[derive(Debug, Deserialize, Clone)]
+pub struct AnswerConfig {
+    /// 本服务对外的地址，未配置时不发布网页
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// 回答超过多少字节时发布为网页
+    #[serde(default = "default_min_bytes")]
+    pub min_bytes: usize,
+    /// 含有表格或代码块的回答也发布为网页
+    #[serde(default = "default_rich")]
+    pub rich: bool,
+    /// 网页保留的时长
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: i64,
+    /// 通过企业微信网页授权确认只有提问的成员能打开
+    #[serde(default)]
+    pub oauth: bool,
+}
+
+fn default_min_bytes() -> usize {
+    1500
+}
+
+fn default_rich() -> bool {
+    true
+}
+
+fn default_ttl_secs() -> i64 {
+    7 * 24 * 3600
+}
+
+impl Default for AnswerConfig {
+    fn default() -> Self {
+        Self {
+            base_url: None,
+            min_bytes: default_min_bytes(),
+            rich: default_rich(),
+            ttl_secs: default_ttl_secs(),
+            oauth: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Answer {
+    pub user: String,
+    pub question: String,
+    pub content: String,
+    pub created: i64,
+}
+
+/// 发布为网页的回答，只保存在内存中，过期后删除
+pub struct AnswerStore {
+    conf: AnswerConfig,
+    answers: Mutex<HashMap<String, Answer>>,
+    /// 签名授权 cookie 的密钥，重启后之前的 cookie 失效
+    key: String,
+}
+
+const COOKIE_PREFIX: &str = "wp_answer_";
+
+impl AnswerStore {
+    pub fn new(conf: &AnswerConfig) -> Self {
+        Self {
+            conf: conf.clone(),
+            answers: Default::default(),
+            key: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// 回答是否需要发布为网页
+    pub fn should_publish(&self, content: &str) -> bool {
+        self.conf.base_url.is_some()
+            && (content.len() >= self.conf.min_bytes || (self.conf.rich && is_rich(content)))
+    }
+
+    /// 保存回答，返回 id
+    pub fn insert(&self, user: &str, question: &str, content: &str, now: i64) -> String {
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let mut answers = self.answers.lock().unwrap();
+        let before = now - self.conf.ttl_secs;
+        answers.retain(|_, a| a.created >= before);
+        answers.insert(
+            id.clone(),
+            Answer {
+                user: user.to_string(),
+                question: question.to_string(),
+                content: content.to_string(),
+                created: now,
+            },
+        );
+        id
+    }
+
+    pub fn get(&self, id: &str, now: i64) -> Option<Answer> {
+        self.answers
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|a| a.created >= now - self.conf.ttl_secs)
+            .cloned()
+    }
+
+    /// 发给成员的网页地址，开启网页授权时先跳转到企业微信授权
+    pub fn link(&self, mp: &MP, id: &str) -> String {
+        let base = self
+            .conf
+            .base_url
+            .as_deref()
+            .unwrap_or_default()
+            .trim_end_matches('/');
+        if self.conf.oauth {
+            mp.oauth_url(&format!("{}/answer/auth", base), id)
+        } else {
+            format!("{}/answer/{}", base, id)
+        }
+    }
+
+    /// 未开启网页授权，或者请求带有授权后写入的 cookie 时可以查看
+    pub fn allowed(&self, id: &str, answer: &Answer, headers: &HeaderMap) -> bool {
+        if !self.conf.oauth {
+            return true;
+        }
+        let name = format!("{}{}", COOKIE_PREFIX, id);
+        let expected = self.token(id, &answer.user);
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .filter_map(|c| c.trim().split_once('='))
+            .any(|(k, v)| k == name && v == expected)
+    }
+
+    fn token(&self, id: &str, user: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.key.as_bytes()).expect("HMAC 支持任意长度的密钥");
+        mac.update(id.as_bytes());
+        mac.update(b".");
+        mac.update(user.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// 含有代码块或表格
+fn is_rich(content: &str) -> bool {
+    let lines = content.lines().map(str::trim).collect::<Vec<_>>();
+    lines.iter().any(|l| l.starts_with("```"))
+        || lines
+            .windows(2)
+            .any(|w| w[0].starts_with('|') && w[1].starts_with('|') && w[1].contains("--"))
+}
+
+/// 网页中展示的 HTML，代码块带有 `language-xx` 的 class 用于高亮。
+/// 回答中的 HTML 按文本转义，不能在页面里执行
+pub fn to_html(content: &str) -> String {
+    use pulldown_cmark::{html, Event, Options, Parser};
+    let parser = Parser::new_ext(
+        content,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS,
+    )
+    .map(|e| match e {
+        Event::Html(s) => Event::Text(s),
+        e => e,
+    });
+    let mut r = String::with_capacity(content.len() * 2);
+    html::push_html(&mut r, parser);
+    r
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthQuery {
+    code: String,
+    state: String,
+}
+
+/// 网页授权的回调，确认是提问的成员后写入 cookie 并跳转到回答
+pub async fn oauth_callback(
+    Extension(store): Extension<Arc<AnswerStore>>,
+    Extension(mp): Extension<Arc<MP>>,
+    q: std::result::Result<Query<OAuthQuery>, QueryRejection>,
+) -> Result<Response> {
+    let Query(q) = q?;
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let answer = store
+        .get(&q.state, now)
+        .ok_or_else(|| Error::BadRequest("回答不存在或已过期".to_string()))?;
+    let user = mp.oauth_user(&q.code).await?;
+    if user != answer.user {
+        info!(u = user, id = q.state, "answer page denied");
+        return Err(Error::Unauthorized("只有提问的成员可以查看".to_string()));
+    }
+    let cookie = format!(
+        "{}{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        COOKIE_PREFIX,
+        q.state,
+        store.token(&q.state, &user),
+        store.conf.ttl_secs
+    );
+    Ok((
+        StatusCode::FOUND,
+        [
+            (header::SET_COOKIE, cookie),
+            (header::LOCATION, format!("/answer/{}", q.state)),
+        ],
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_store() {
+        let store = AnswerStore::new(&AnswerConfig {
+            base_url: Some("https://wp.example.com/".to_string()),
+            ttl_secs: 100,
+            oauth: true,
+            ..Default::default()
+        });
+        assert!(!store.should_publish("短回答"));
+        assert!(store.should_publish("| a | b |\n| --- | --- |\n| 1 | 2 |"));
+        assert!(store.should_publish("```\ncode\n```"));
+        assert!(store.should_publish(&"长".repeat(600)));
+
+        let id = store.insert("u", "q", "a", 0);
+        assert_eq!(store.get(&id, 100).unwrap().content, "a");
+        assert_eq!(store.get(&id, 101), None);
+        // 插入时删除过期的回答
+        store.insert("u", "q", "a", 200);
+        assert_eq!(store.answers.lock().unwrap().len(), 1);
+
+        let id = store.insert("u", "q", "a", 200);
+        let answer = store.get(&id, 200).unwrap();
+        let mut headers = HeaderMap::new();
+        assert!(!store.allowed(&id, &answer, &headers));
+        headers.insert(
+            header::COOKIE,
+            format!("a=b; {}{}={}", COOKIE_PREFIX, id, store.token(&id, "u"))
+                .parse()
+                .unwrap(),
+        );
+        assert!(store.allowed(&id, &answer, &headers));
+        // 其他成员的 cookie 无效
+        let other = Answer {
+            user: "other".to_string(),
+            ..answer
+        };
+        assert!(!store.allowed(&id, &other, &headers));
+    }
+
+    #[test]
+    fn test_to_html() {
+        let html = to_html("| a |\n| --- |\n| 1 |\n\n```rust\nfn main() {}\n```");
+        assert!(html.contains("<table>"), "{}", html);
+        assert!(html.contains(r#"<code class="language-rust">"#), "{}", html);
+
+        let html = to_html("<script>alert(1)</script>\n\n图 <img src=x onerror=alert(1)>");
+        assert!(!html.contains("<script"), "{}", html);
+        assert!(!html.contains("<img"), "{}", html);
+        assert!(html.contains("&lt;script&gt;"), "{}", html);
+    }
+}
@@ -1,29 +1,32 @@
-use crate::backend::chatglm::GLM;
-use crate::backend::mp::callback::CallbackMessage::Text;
-use crate::backend::mp::MP;
+use crate::backend::dispatch::{dispatch, CallbackState};
+use crate::backend::error::{Error, Result};
+use crate::backend::metrics::Metrics;
+use crate::backend::mp::callback::CallbackError;
+use crate::backend::mp::{SendResult, MP};
+use crate::backend::recorder::Recorder;
 
-use axum::body::{Body, Bytes};
-use axum::extract::{Path, Query};
+use axum::body::Bytes;
+use axum::extract::rejection::{JsonRejection, QueryRejection, StringRejection};
+use axum::extract::{Query, RawQuery};
 use axum::http::header::HeaderMap;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
 
-use crate::backend::context::ChatMgr;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
-use tracing::{trace, warn};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+use tracing::{debug, trace, warn};
 use wechat_crypto::VerifyInfo;
 
-pub async fn message_send(Extension(mp): Extension<Arc<MP>>, b: Bytes) -> impl IntoResponse {
-    let msg = String::from_utf8(b.to_vec()).unwrap();
-    match mp.proxy_message_send(&msg).await {
-        Ok(msg_id) => Json(json!({"errcode" : 0, "errmsg" : "ok", "msgid" : msg_id})),
-        Err(e) => Json(json!({"errcode" : -1, "errmsg" : e.to_string()})),
-    }
+pub async fn message_send(Extension(mp): Extension<Arc<MP>>, b: Bytes) -> Result<Json<SendResult>> {
+    let msg = String::from_utf8(b.to_vec())
+        .map_err(|e| Error::BadRequest(format!("请求体不是合法的 UTF-8: {}", e)))?;
+    let req = serde_json::from_str(&msg).map_err(|e| Error::BadRequest(e.to_string()))?;
+    let res = mp.proxy_message_send(req).await?;
+    Ok(Json(res))
 }
 #[derive(Deserialize, Serialize, Debug)]
 pub struct RecallMsg {
@@ -33,19 +36,19 @@ pub struct RecallMsg {
 
 pub async fn message_recall(
     Extension(mp): Extension<Arc<MP>>,
-    Json(q): Json<RecallMsg>,
-) -> impl IntoResponse {
-    match mp.message_recall(&q.msg_id).await {
-        Ok(_) => Json(json!({"errcode" : 0, "errmsg" : "ok"})),
-        Err(e) => Json(json!({"errcode" : -1, "errmsg" : e.to_string()})),
-    }
+    q: std::result::Result<Json<RecallMsg>, JsonRejection>,
+) -> Result<impl IntoResponse> {
+    let Json(q) = q?;
+    mp.message_recall(&q.msg_id).await?;
+    Ok(Json(json!({"errcode" : 0, "errmsg" : "ok"})))
 }
 pub async fn media_upload(
     Extension(mp): Extension<Arc<MP>>,
-    Query(params): Query<HashMap<String, String>>,
+    params: std::result::Result<Query<HashMap<String, String>>, QueryRejection>,
     headers: HeaderMap,
     b: Bytes,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse> {
+    let Query(params) = params?;
     let mut qs = qstring::QString::new::<&str, &str>(Vec::new());
     for p in params.iter() {
         qs.add_pair((p.0, p.1));
@@ -53,19 +56,28 @@ pub async fn media_upload(
 
     let (c, s) = mp
         .proxy(
-            &format!("https://123/cgi-bin/media/upload?{}", qs.to_string()),
+            &format!("https://123/cgi-bin/media/upload?{}", qs),
             headers,
             b,
         )
-        .await
-        .unwrap();
-    (
+        .await?;
+    Ok((
         c,
         [(
             http::header::CONTENT_TYPE,
             http::HeaderValue::from_static("application/json"),
         )],
         s,
+    ))
+}
+
+pub async fn metrics(Extension(metrics): Extension<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(
+            http::header::CONTENT_TYPE,
+            http::HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        metrics.render(),
     )
 }
 
@@ -78,82 +90,397 @@ pub struct ValidateQuery {
     echo_str: String, //是	加密的字符串。需要解密得到消息内容明文，解密后有random、msg_len、msg、receiveid四个字段，其中msg即为消息内容明文
 }
 
+impl ValidateQuery {
+    fn verify_info(&self) -> VerifyInfo {
+        VerifyInfo {
+            signature: self.msg_signature.clone(),
+            timestamp: self.timestamp,
+            nonce: self.nonce,
+        }
+    }
+}
+
+/// 企业微信 5 秒内收不到响应会断开并重试，留出网络传输的余量
+const REPLY_DEADLINE: Duration = Duration::from_secs(3);
+
+/// 校验失败时返回 4xx，企业微信后台能直接看到回调配置有误
+fn reject(metrics: &Metrics, handler: &str, q: &ValidateQuery, e: CallbackError) -> Error {
+    warn!(
+        handler,
+        kind = e.kind(),
+        timestamp = q.timestamp,
+        nonce = q.nonce,
+        "reject callback: {}",
+        e
+    );
+    metrics.inc(
+        "wp_callback_rejected_total",
+        &[("handler", handler), ("kind", e.kind())],
+    );
+    e.into()
+}
+
 pub async fn validate_url(
     Extension(mp): Extension<Arc<MP>>,
-    Query(q): Query<ValidateQuery>,
-) -> impl IntoResponse {
+    Extension(metrics): Extension<Arc<Metrics>>,
+    q: std::result::Result<Query<ValidateQuery>, QueryRejection>,
+) -> Result<String> {
+    let Query(q) = q?;
     trace!("validate_url: {:?}", q);
-
-    match mp.verify_url(
-        &VerifyInfo {
-            signature: q.msg_signature,
-            timestamp: q.timestamp,
-            nonce: q.nonce,
-        },
-        &q.echo_str,
-    ) {
-        Ok(echo) => echo,
-        Err(e) => {
-            warn!("url 验证失败: {:?}", e);
-            "error".to_string()
-        }
-    }
+    mp.verify_url(&q.verify_info(), &q.echo_str)
+        .map_err(|e| reject(&metrics, "validate_url", &q, e))
 }
 
+/// 解密后立即把消息交给后台任务处理，`REPLY_DEADLINE` 内给出的被动回复随响应返回，
+/// 超时则只回复空包确认收到
 pub async fn on_message(
-    Extension(mp): Extension<Arc<MP>>,
-    Extension(glm): Extension<Arc<GLM>>,
-    Extension(chat_mgr): Extension<Arc<Mutex<ChatMgr>>>,
-    Query(q): Query<ValidateQuery>,
-    b: String,
-) -> impl IntoResponse {
+    Extension(state): Extension<CallbackState>,
+    Extension(recorder): Extension<Arc<Recorder>>,
+    RawQuery(raw_query): RawQuery,
+    q: std::result::Result<Query<ValidateQuery>, QueryRejection>,
+    b: std::result::Result<String, StringRejection>,
+) -> Result<String> {
+    let (Query(q), b) = (q?, b?);
+    let started = Instant::now();
     trace!("on_message: q = {:?}", q);
     trace!("on_message: body = {:?}", b);
-    match mp.handle_msg(
-        &VerifyInfo {
-            signature: q.msg_signature,
-            timestamp: q.timestamp,
-            nonce: q.nonce,
-        },
-        b.as_ref(),
-    ) {
-        Ok(xml) => {
-            trace!("on_message: msg = {:?}", xml);
-            if let Text(xml) = xml {
-                if xml.content == "/clean" {
-                    let mut m = chat_mgr.lock().await;
-                    m.clear(&xml.from_user_name);
-                    drop(m);
-                    if let Err(e) = mp
-                        .proxy_message_send(
-                            &json!({
-                                "touser": xml.from_user_name,
-                                "msgtype": "text",
-                                "agentid": 1,
-                                "text": {
-                                    "content": "让我们开始新的对话吧"
-                                }
-                            })
-                            .to_string(),
-                        )
-                        .await
-                    {
-                        warn!(e = ?e, "proxy message send failed");
-                    }
-                } else {
-                    glm.async_chat(
-                        &xml.from_user_name,
-                        &xml.content,
-                        chat_mgr,
-                        mp,
-                        Some(Duration::from_secs(120)),
-                    )
-                    .await
+    {
+        let body = b.clone();
+        tokio::spawn(async move {
+            recorder
+                .record(raw_query.as_deref().unwrap_or_default(), &body)
+                .await
+        });
+    }
+    let msg = state
+        .mp
+        .handle_msg(&q.verify_info(), &b)
+        .map_err(|e| reject(&state.metrics, "on_message", &q, e))?;
+    let msg_type = msg.msg_type();
+    let to_user = msg.from_user_name().unwrap_or_default().to_string();
+
+    let (tx, rx) = oneshot::channel();
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Some(reply) = dispatch(&state, msg).await {
+                if let Err(reply) = tx.send(reply) {
+                    warn!(
+                        msg_type,
+                        reply = reply.msg_type(),
+                        "passive reply dropped after deadline"
+                    );
                 }
             }
+        });
+    }
+    let reply = match tokio::time::timeout(REPLY_DEADLINE, rx).await {
+        Ok(r) => r.ok(),
+        Err(_) => {
+            warn!(
+                msg_type,
+                "callback handler exceeded deadline, ack without reply"
+            );
+            state
+                .metrics
+                .inc("wp_callback_reply_timeout_total", &[("msg_type", msg_type)]);
+            None
         }
-        Err(e) => {
-            warn!("on_message 验证失败: {:?}", e);
+    };
+    // 被动回复失败时返回空包，企业微信不会重试
+    let body = match reply {
+        None => String::new(),
+        Some(reply) => match state.mp.encrypt_reply(&reply, &to_user, q.nonce) {
+            Ok(s) => s,
+            Err(e) => {
+                warn!(
+                    msg_type = reply.msg_type(),
+                    "encrypt passive reply failed: {:?}", e
+                );
+                state.metrics.inc(
+                    "wp_callback_reply_failed_total",
+                    &[("msg_type", reply.msg_type())],
+                );
+                String::new()
+            }
+        },
+    };
+    debug!(
+        msg_type,
+        elapsed_ms = started.elapsed().as_millis() as u64,
+        "ack callback"
+    );
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::chatglm::GLM;
+    use crate::backend::command::chat::{self, ChatState};
+    use crate::backend::command::Commands;
+    use crate::backend::context::ChatMgr;
+    use crate::backend::dedupe::Dedupe;
+    use crate::backend::handler::{ChatBot, Router};
+    use crate::backend::llm::Mock;
+    use crate::backend::mp::test_util::{unreachable_mp, AES_KEY, CORP_ID, TOKEN};
+    use crate::backend::stream::StreamHub;
+    use axum::http::StatusCode;
+    use axum::response::Response;
+    use base64::Engine;
+    use wechat_crypto::{calc_signature, decode_aes_key, decrypt, encrypt, parse_plain_text};
+
+    fn callback_state(mp: Arc<MP>) -> CallbackState {
+        let metrics = Arc::new(Metrics::default());
+        let glm = Arc::new(GLM::new(Arc::new(Mock::new(None)), &Default::default()));
+        let chat_mgr: Arc<tokio::sync::Mutex<ChatMgr>> = Default::default();
+        let commands = Commands::new(
+            &Default::default(),
+            chat::commands(ChatState {
+                mp: mp.clone(),
+                glm: glm.clone(),
+                chat_mgr: chat_mgr.clone(),
+            }),
+        )
+        .unwrap();
+        let chat_bot = ChatBot::new(mp.clone(), glm, chat_mgr, Arc::new(commands));
+        let router = Router::new(&Default::default(), vec![Arc::new(chat_bot)]).unwrap();
+        CallbackState {
+            mp,
+            dedupe: Arc::new(Dedupe::default()),
+            metrics,
+            hub: Arc::new(StreamHub::new(10)),
+            router: Arc::new(router),
         }
     }
+
+    /// 模拟企业微信加密并签名的回调请求
+    fn encrypted_request(plaintext: &str) -> (ValidateQuery, String) {
+        let key = decode_aes_key(AES_KEY).unwrap();
+        let encrypted = base64::engine::general_purpose::STANDARD
+            .encode(encrypt(&key, plaintext, CORP_ID).unwrap());
+        let q = ValidateQuery {
+            msg_signature: calc_signature(TOKEN, "1411525903", "461056294", &encrypted),
+            timestamp: 1411525903,
+            nonce: 461056294,
+            echo_str: "".to_string(),
+        };
+        let body = format!(
+            "<xml><ToUserName><![CDATA[wx49f0ab532d5d035a]]></ToUserName>\
+             <Encrypt><![CDATA[{}]]></Encrypt><AgentID><![CDATA[1]]></AgentID></xml>",
+            encrypted
+        );
+        (q, body)
+    }
+
+    async fn into_string(r: Response) -> (StatusCode, String) {
+        let status = r.status();
+        let b = hyper::body::to_bytes(r.into_body()).await.unwrap();
+        (status, String::from_utf8(b.to_vec()).unwrap())
+    }
+
+    async fn into_json(r: Response) -> (StatusCode, serde_json::Value) {
+        let status = r.status();
+        let b = hyper::body::to_bytes(r.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&b).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_message_send_non_utf8() {
+        let r = message_send(
+            Extension(unreachable_mp()),
+            Bytes::from_static(&[0xff, 0xfe, 0xfd]),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errcode"], 40058);
+    }
+
+    #[tokio::test]
+    async fn test_message_send_unknown_msgtype() {
+        let r = message_send(
+            Extension(unreachable_mp()),
+            Bytes::from_static(br#"{"touser":"a","msgtype":"unknown"}"#),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errcode"], 40058);
+        assert!(body["errmsg"].as_str().unwrap().contains("msgtype=unknown"));
+    }
+
+    #[tokio::test]
+    async fn test_message_send_upstream_unreachable() {
+        let r = message_send(
+            Extension(unreachable_mp()),
+            Bytes::from_static(br#"{"touser":"a","msgtype":"text","text":{"content":"c"}}"#),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["errcode"], -1);
+    }
+
+    #[tokio::test]
+    async fn test_media_upload_upstream_unreachable() {
+        let r = media_upload(
+            Extension(unreachable_mp()),
+            Ok(Query(HashMap::from([(
+                "type".to_string(),
+                "image".to_string(),
+            )]))),
+            HeaderMap::new(),
+            Bytes::new(),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["errcode"], -1);
+    }
+
+    #[tokio::test]
+    async fn test_on_message_passive_reply() {
+        let (q, body) = encrypted_request(
+            "<xml><ToUserName><![CDATA[wx49f0ab532d5d035a]]></ToUserName>\
+             <FromUserName><![CDATA[zhangsan]]></FromUserName><CreateTime>1411525903</CreateTime>\
+             <MsgType><![CDATA[text]]></MsgType><Content><![CDATA[/clean]]></Content>\
+             <MsgId>1</MsgId><AgentID>1</AgentID></xml>",
+        );
+        let r = on_message(
+            Extension(callback_state(unreachable_mp())),
+            Extension(Arc::new(Recorder::new(&Default::default()))),
+            RawQuery(None),
+            Ok(Query(q)),
+            Ok(body),
+        )
+        .await
+        .into_response();
+        let (status, envelope) = into_string(r).await;
+        assert_eq!(status, StatusCode::OK);
+
+        #[derive(Deserialize)]
+        struct Envelope {
+            #[serde(rename = "Encrypt")]
+            encrypted: String,
+        }
+        let env: Envelope = quick_xml::de::from_str(&envelope).unwrap();
+        let key = decode_aes_key(AES_KEY).unwrap();
+        let b = base64::engine::general_purpose::STANDARD
+            .decode(env.encrypted)
+            .unwrap();
+        let (reply, _) = parse_plain_text(&decrypt(&key, &b).unwrap()).unwrap();
+        assert!(reply.contains("<ToUserName><![CDATA[zhangsan]]></ToUserName>"));
+        assert!(reply.contains("<Content><![CDATA[让我们开始新的对话吧]]></Content>"));
+    }
+
+    #[tokio::test]
+    async fn test_on_message_rejected() {
+        let state = callback_state(unreachable_mp());
+        let (mut q, body) = encrypted_request("<xml></xml>");
+        q.msg_signature = "0".repeat(40);
+        let r = on_message(
+            Extension(state.clone()),
+            Extension(Arc::new(Recorder::new(&Default::default()))),
+            RawQuery(None),
+            Ok(Query(q)),
+            Ok(body),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["errcode"], -40001);
+
+        let (q, _) = encrypted_request("<xml></xml>");
+        let r = on_message(
+            Extension(state.clone()),
+            Extension(Arc::new(Recorder::new(&Default::default()))),
+            RawQuery(None),
+            Ok(Query(q)),
+            Ok("not xml".to_string()),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errcode"], -40002);
+
+        let labels = |kind| [("handler", "on_message"), ("kind", kind)];
+        let m = &state.metrics;
+        assert_eq!(m.get("wp_callback_rejected_total", &labels("signature")), 1);
+        assert_eq!(m.get("wp_callback_rejected_total", &labels("malformed")), 1);
+    }
+
+    #[tokio::test]
+    async fn test_validate_url_rejected() {
+        let metrics = Arc::new(Metrics::default());
+        let q = ValidateQuery {
+            msg_signature: "0".repeat(40),
+            timestamp: 1411525903,
+            nonce: 461056294,
+            echo_str: "AAAA".to_string(),
+        };
+        let r = validate_url(
+            Extension(unreachable_mp()),
+            Extension(metrics.clone()),
+            Ok(Query(q)),
+        )
+        .await
+        .into_response();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["errcode"], -40001);
+        assert_eq!(
+            metrics.get(
+                "wp_callback_rejected_total",
+                &[("handler", "validate_url"), ("kind", "signature")]
+            ),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extractor_rejection() {
+        use axum::body::Body;
+        use axum::routing::get;
+        use tower::ServiceExt;
+
+        let app = axum::Router::new()
+            .route("/wccb", get(validate_url).post(on_message))
+            .layer(Extension(unreachable_mp()))
+            .layer(Extension(Arc::new(Metrics::default())))
+            .layer(Extension(callback_state(unreachable_mp())))
+            .layer(Extension(Arc::new(Recorder::new(&Default::default()))));
+        let req = |method: &str, uri: &str, body: Body| {
+            http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(body)
+                .unwrap()
+        };
+
+        // 缺少参数和请求体不是 UTF-8 都返回企业微信格式的错误
+        let r = app
+            .clone()
+            .oneshot(req("GET", "/wccb?timestamp=x", Body::empty()))
+            .await
+            .unwrap();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errcode"], 40058);
+
+        let uri = "/wccb?msg_signature=a&timestamp=1&nonce=2";
+        let r = app
+            .oneshot(req("POST", uri, Body::from(vec![0xff, 0xfe])))
+            .await
+            .unwrap();
+        let (status, body) = into_json(r).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errcode"], 40058);
+    }
 }
@@ -0,0 +1,50 @@
+use crate::backend::error::{Error, Result};
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// 校验管理接口的访问令牌，令牌通过 `Authorization: Bearer` 或 `?token=` 传入。
+/// `expected` 为空表示未配置，此时接口不开放
+pub fn check_token(
+    expected: Option<&str>,
+    conf_name: &str,
+    headers: &HeaderMap,
+    query_token: Option<&str>,
+) -> Result<()> {
+    let expected =
+        expected.ok_or_else(|| Error::Unauthorized(format!("未配置 {}，接口未开放", conf_name)))?;
+    let got = headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or(query_token);
+    match got {
+        Some(got) if token_eq(got, expected) => Ok(()),
+        _ => Err(Error::Unauthorized("token 不正确".to_string())),
+    }
+}
+
+/// 比较两者的 HMAC，`verify_slice` 是常数时间的，不会从响应时间泄露令牌
+fn token_eq(got: &str, expected: &str) -> bool {
+    let mac = |s: &str| {
+        let mut m = Hmac::<Sha256>::new_from_slice(b"wp-token").expect("HMAC 支持任意长度的密钥");
+        m.update(s.as_bytes());
+        m
+    };
+    mac(got)
+        .verify_slice(&mac(expected).finalize().into_bytes())
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq("secret", "secret"));
+        assert!(!token_eq("secret", "secreT"));
+        assert!(!token_eq("secret", "secret2"));
+        assert!(!token_eq("", "secret"));
+    }
+}
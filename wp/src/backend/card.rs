@@ -0,0 +1,102 @@
+use crate::backend::mp::callback::TemplateCardEventMessage;
+use crate::backend::mp::{CardUpdate, MP};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CardConfig {
+    /// 按钮 key 与点击后替换的按钮文案，例如 `ack = "已确认"`
+    #[serde(default)]
+    pub replace_names: HashMap<String, String>,
+}
+
+/// 决定模板卡片被点击后的新状态，返回 None 表示不更新卡片
+#[async_trait]
+pub trait TemplateCardHandler: Send + Sync {
+    async fn on_event(&self, event: &TemplateCardEventMessage) -> Option<CardUpdate>;
+}
+
+/// 按配置把被点击的按钮替换为对应的文案，用于告警消息上的确认、忽略按钮
+pub struct ReplaceNameHandler {
+    replace_names: HashMap<String, String>,
+}
+
+impl ReplaceNameHandler {
+    pub fn new(conf: &CardConfig) -> Self {
+        Self {
+            replace_names: conf.replace_names.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl TemplateCardHandler for ReplaceNameHandler {
+    async fn on_event(&self, event: &TemplateCardEventMessage) -> Option<CardUpdate> {
+        self.replace_names
+            .get(&event.event_key)
+            .map(|name| CardUpdate::Button {
+                replace_name: name.clone(),
+            })
+    }
+}
+
+pub async fn handle_event(
+    mp: Arc<MP>,
+    handler: Arc<dyn TemplateCardHandler>,
+    event: TemplateCardEventMessage,
+) {
+    let update = match handler.on_event(&event).await {
+        Some(u) => u,
+        None => return,
+    };
+    info!(
+        u = event.from_user_name,
+        task_id = event.task_id,
+        key = event.event_key,
+        "update template card"
+    );
+    if let Err(e) = mp.update_template_card(&event.response_code, &update).await {
+        warn!(
+            task_id = event.task_id,
+            "update template card failed: {:?}", e
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn event(key: &str) -> TemplateCardEventMessage {
+        TemplateCardEventMessage {
+            to_user_name: "toUser".to_string(),
+            from_user_name: "fromUser".to_string(),
+            create_time: 123456789,
+            msg_type: "event".to_string(),
+            event: "template_card_event".to_string(),
+            event_key: key.to_string(),
+            task_id: "task_id".to_string(),
+            card_type: "button_interaction".to_string(),
+            response_code: "response_code".to_string(),
+            agent_id: "1".to_string(),
+            selected_items: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replace_name() {
+        let h = ReplaceNameHandler::new(&CardConfig {
+            replace_names: HashMap::from([("ack".to_string(), "已确认".to_string())]),
+        });
+        assert_eq!(
+            h.on_event(&event("ack")).await,
+            Some(CardUpdate::Button {
+                replace_name: "已确认".to_string()
+            })
+        );
+        assert_eq!(h.on_event(&event("other")).await, None);
+    }
+}
@@ -1,46 +1,79 @@
-use crate::backend::context::ChatMgr;
-use crate::backend::mp::MP;
+mod pool;
+mod progressive;
+mod reminder;
+
+pub use pool::{Permit, Pool, Status, Ticket};
+pub use progressive::Progressive;
+pub use reminder::{ProgressConfig, Reminder};
+
+use crate::backend::answer::AnswerStore;
+use crate::backend::context::{ChatConfig, ChatMgr, Settings, StreamMode};
+use crate::backend::llm::{ChatMessage, LlmBackend, Role};
+use crate::backend::mp::{Message, MP};
+use crate::backend::render;
 use anyhow::{anyhow, Result};
-use openai_api_rust::chat::ChatApi;
-use openai_api_rust::completions::Completion;
-use openai_api_rust::OpenAI;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio::task::AbortHandle;
 use tracing::{info, trace, warn};
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct Msg {
-    from_user: String,
-    query: String,
-}
-#[derive(Deserialize, Serialize, Debug)]
-struct GLMResponse {
-    response: String,
-    history: Vec<Vec<String>>,
-    status: u16,
-}
+/// 成员提问时等待大模型回答的最长时间，不包括排队
+pub const ANSWER_TIMEOUT: Duration = Duration::from_secs(120);
 
 #[derive(Clone)]
 pub struct GLM {
-    m: Arc<Mutex<u32>>,
-    c: reqwest::Client,
-    // api: String,
-    openai: OpenAI,
+    pool: Arc<Pool>,
+    llm: Arc<dyn LlmBackend>,
+    stream: StreamMode,
+    stream_interval: Duration,
+    plain_users: Arc<HashSet<String>>,
+    answers: Option<Arc<AnswerStore>>,
+    /// `/model` 可以切换的其他模型
+    models: Arc<HashMap<String, Arc<dyn LlmBackend>>>,
+    /// 每个成员排队中和正在回答的问题，用于 `/stop`
+    tasks: Arc<std::sync::Mutex<Tasks>>,
+    next_task: Arc<AtomicU64>,
+    /// 成员提出新问题时停止还没有回答完的问题
+    stop_previous: bool,
+    progress: Arc<ProgressConfig>,
 }
 
+/// 成员到问题 id 和对应任务
+type Tasks = HashMap<String, Vec<(u64, AbortHandle)>>;
+
 impl GLM {
-    pub fn new(api: &str) -> Self {
-        let auth = openai_api_rust::Auth::new("none");
-        let openai = OpenAI::new(auth, api);
+    pub fn new(llm: Arc<dyn LlmBackend>, conf: &ChatConfig) -> Self {
         Self {
-            m: Arc::new(Mutex::new(0)),
-            c: reqwest::Client::new(),
-            openai,
+            pool: Arc::new(Pool::new(conf.concurrency)),
+            llm,
+            stream: conf.stream,
+            stream_interval: Duration::from_secs(conf.stream_interval_secs),
+            plain_users: Arc::new(conf.plain_users.iter().cloned().collect()),
+            answers: None,
+            models: Default::default(),
+            tasks: Default::default(),
+            next_task: Default::default(),
+            stop_previous: conf.stop_previous,
+            progress: Default::default(),
         }
     }
+    pub fn with_models(mut self, models: HashMap<String, Arc<dyn LlmBackend>>) -> Self {
+        self.models = Arc::new(models);
+        self
+    }
+    /// 等待回答期间的进度提醒
+    pub fn with_progress(mut self, progress: &ProgressConfig) -> Self {
+        self.progress = Arc::new(progress.clone());
+        self
+    }
+    /// 较长的回答发布为网页，只发送链接
+    pub fn with_answers(mut self, answers: Arc<AnswerStore>) -> Self {
+        self.answers = Some(answers);
+        self
+    }
     pub async fn async_chat(
         &self,
         from_user: &str,
@@ -49,16 +82,52 @@ impl GLM {
         mp: Arc<MP>,
         timeout: Option<Duration>,
     ) {
+        if self.stop_previous && self.stop(from_user) > 0 {
+            let _ = mp
+                .send(Message::text("已停止回答上一个问题").to_users([from_user]))
+                .await;
+        }
         let glm = self.clone();
         let from_user = from_user.to_string();
         let query = query.to_string();
-        tokio::spawn(async move {
+        let id = self.next_task.fetch_add(1, Ordering::Relaxed);
+        // 先拿到锁再启动任务，保证任务结束时已经登记
+        let mut tasks = self.tasks.lock().unwrap();
+        let user = from_user.clone();
+        let h = tokio::spawn(async move {
             glm.chat(&from_user, &query, chat_mgr, mp, timeout)
                 .await
                 .unwrap_or_else(|e| {
                     warn!(q = query, u = from_user, "glm chat error: {:?}", e);
-                })
+                });
+            glm.finish_task(&from_user, id);
         });
+        tasks.entry(user).or_default().push((id, h.abort_handle()));
+    }
+
+    /// 停止成员排队中和正在回答的问题，返回停止的个数。
+    /// 正在进行的大模型请求和进度提醒随任务一起取消
+    pub fn stop(&self, user: &str) -> usize {
+        let tasks = self.tasks.lock().unwrap().remove(user).unwrap_or_default();
+        // 立即移出队列，不等被停止的任务退出
+        self.pool.cancel_user(user);
+        for (_, h) in &tasks {
+            h.abort();
+        }
+        if !tasks.is_empty() {
+            info!(u = user, n = tasks.len(), "glm chat stopped");
+        }
+        tasks.len()
+    }
+
+    fn finish_task(&self, user: &str, id: u64) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if let Some(t) = tasks.get_mut(user) {
+            t.retain(|(i, _)| *i != id);
+            if t.is_empty() {
+                tasks.remove(user);
+            }
+        }
     }
 
     pub async fn chat(
@@ -70,115 +139,89 @@ impl GLM {
         timeout: Option<Duration>,
     ) -> Result<()> {
         info!(q = query, u = from_user, "glm chat");
-        // process
-        // 2 等待获取锁
-        // 1 拿到锁了，正在请求
-        // 0 结束了
-        let processing = Arc::new(Mutex::new(2));
-        {
-            let processing = processing.clone();
-            let from_user = from_user.to_string();
-            let mp = mp.clone();
-            tokio::spawn(async move {
-                let d = Duration::from_secs(13);
-                tokio::time::sleep(d).await;
-                loop {
-                    let p = processing.lock().await;
-                    let resp_msg = match *p {
-                        1 => "小秘书😣正在燃烧为数不多[求赞助]的脑细胞帮你回答问题，莫急莫急",
-                        2 => "小秘书😣忙得焦头烂额，多等一会儿，忙完马上回复你",
-                        _ => return,
-                    };
-                    let _ = mp
-                        .proxy_message_send(
-                            &json!({
-                                "touser": from_user,
-                                "msgtype": "text",
-                                "agentid": 1,
-                                "text": {
-                                    "content": resp_msg
-                                }
-                            })
-                            .to_string(),
-                        )
-                        .await;
+        let ticket = self.pool.enqueue(from_user);
+        let mut progress = Reminder::start(
+            self.progress.clone(),
+            self.pool.clone(),
+            ticket.id(),
+            mp.clone(),
+            from_user,
+        );
+        let _permit = match ticket.ready().await {
+            Some(p) => p,
+            None => {
+                info!(q = query, u = from_user, "glm chat cancelled");
+                return Ok(());
+            }
+        };
+        let settings = chat_mgr.lock().await.settings(from_user);
 
-                    tokio::time::sleep(d * 3).await;
-                }
-            });
-        }
-        let _m = self.m.lock().await;
-        {
-            let mut p = processing.lock().await;
-            *p = 1;
-        }
-        // *m += 1u32;
+        let plain = self.plain_users.contains(from_user);
+        let mut streamed = (self.stream != StreamMode::Off).then(|| {
+            Progressive::new(
+                self.stream,
+                self.stream_interval,
+                mp.clone(),
+                from_user,
+                plain,
+            )
+        });
 
         let begin = time::OffsetDateTime::now_utc();
-        let m_handler = {
-            let from_user = from_user.to_string();
-            let chat_mgr = chat_mgr.clone();
-            let glm = self.clone();
-            let query = query.to_string();
-            tokio::spawn(tokio::time::timeout(
-                timeout.unwrap_or(Duration::from_secs(60)),
-                async move {
-                    let m = chat_mgr.lock().await;
-                    let history = m.get(&from_user).map(|c| c.history()).unwrap_or(vec![]);
-                    drop(m);
-                    glm._chat(&query, history).await
-                },
-            ))
-        };
-
-        let m_ret = m_handler.await;
-        {
-            let mut p = processing.lock().await;
-            *p = 0;
-        }
+        // 不单独启动任务，`stop` 时随当前任务一起取消请求
+        let m_ret = tokio::time::timeout(timeout.unwrap_or(Duration::from_secs(60)), async {
+            let history = chat_mgr.lock().await.history(from_user);
+            self.answer(query, history, &settings, streamed.as_mut())
+                .await
+        })
+        .await;
+        progress.stop();
         let cost_during = time::OffsetDateTime::now_utc() - begin;
         let m_ret = match m_ret {
             Err(e) => {
-                warn!(q = query, u = from_user, "glm thread error: {:?}", e);
+                warn!(q = query, u = from_user, "glm timeout: {:?}", e);
                 Err(anyhow!("{:?}", e))
             }
-            Ok(r) => match r {
-                Err(e) => {
-                    warn!(q = query, u = from_user, "glm timeout: {:?}", e);
-                    Err(anyhow!("{:?}", e))
-                }
-                Ok(r) => r,
-            },
+            Ok(r) => r,
         };
 
         let resp_msg = match m_ret {
-            Ok(resp) => {
+            Ok((resp, rest)) => {
                 info!(
                     q = query,
                     u = from_user,
                     t = "glm",
                     c = cost_during.whole_seconds(),
+                    llm = self.backend(&settings).name(),
                     "glm response"
                 );
-                // let mut chat_mgr = chat_mgr.lock().await;
-                // chat_mgr.add(
-                //     from_user,
-                //     query,
-                //     &resp.response,
-                //     time::OffsetDateTime::now_utc().unix_timestamp(),
-                // );
-                let r = resp
-                    .choices
-                    .get(0)
-                    .ok_or(anyhow!("glm response error"))?
-                    .message
-                    .clone()
-                    .ok_or(anyhow!("glm response error"))?;
-                format!(
-                    "{}\n\n> 对话耗时：{}s\n> /clean 重新开始聊天",
-                    r.content,
-                    cost_during.whole_seconds()
-                )
+                let now = time::OffsetDateTime::now_utc().unix_timestamp();
+                chat_mgr.lock().await.add(from_user, query, &resp, now);
+                // 已经逐段发出的回答不再发布为网页，避免成员收到两遍
+                let delivered = streamed.as_ref().is_some_and(|p| p.delivered());
+                match self
+                    .answers
+                    .as_ref()
+                    .filter(|a| !delivered && a.should_publish(&resp))
+                {
+                    Some(answers) => {
+                        let id = answers.insert(from_user, query, &resp, now);
+                        answer_card(
+                            query,
+                            &resp,
+                            cost_during.whole_seconds(),
+                            answers.link(&mp, &id),
+                        )
+                    }
+                    None => render::message(
+                        &format!(
+                            "{}\n\n> 对话耗时：{}s\n> /clean 重新开始聊天",
+                            rest,
+                            cost_during.whole_seconds()
+                        ),
+                        plain,
+                    ),
+                }
             }
             Err(e) => {
                 warn!(
@@ -188,65 +231,83 @@ impl GLM {
                     "glm error: {:?}",
                     e
                 );
-                "ChatGLM 回答失败，请稍后再试试".to_string()
+                render::message("ChatGLM 回答失败，请稍后再试试", plain)
             }
         };
-        match mp
-            .proxy_message_send(
-                &json!({
-                    "touser": from_user,
-                    "msgtype": "markdown",
-                    "agentid": 1,
-                    "markdown": {
-                        "content": resp_msg
-                    }
-                })
-                .to_string(),
-            )
-            .await
-        {
+        let sent = match mp.send_split(resp_msg.to_users([from_user])).await {
             Ok(_) => {
                 trace!("glm response sent");
+                true
             }
             Err(e) => {
                 warn!("glm response send error: {:?}", e);
+                false
             }
         };
+        if let Some(p) = streamed {
+            if sent {
+                p.recall_partial().await;
+            } else {
+                p.keep_partial();
+            }
+        }
+        progress.recall().await;
         Ok(())
     }
 
-    async fn _chat(&self, query: &str, history: Vec<Vec<String>>) -> Result<Completion> {
-        let body = openai_api_rust::chat::ChatBody {
-            model: "chatglm2-6b".to_string(),
-            max_tokens: None,
-            temperature: None,
-            top_p: None,
-            n: Some(2),
-            stream: Some(false),
-            stop: None,
-            presence_penalty: None,
-            frequency_penalty: None,
-            logit_bias: None,
-            user: None,
-            messages: vec![openai_api_rust::Message {
-                role: openai_api_rust::Role::User,
-                content: query.to_string(),
-            }],
+    /// 可以通过 `/model` 切换的模型，按名字排序
+    pub fn models(&self) -> Vec<&str> {
+        let mut r = self.models.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+        r.sort();
+        r
+    }
+
+    /// 成员选择的模型，未选择或者模型已经从配置中删除时使用默认模型
+    fn backend(&self, settings: &Settings) -> &Arc<dyn LlmBackend> {
+        settings
+            .model
+            .as_ref()
+            .and_then(|m| self.models.get(m))
+            .unwrap_or(&self.llm)
+    }
+
+    async fn _chat(
+        &self,
+        query: &str,
+        history: Vec<(String, String)>,
+        settings: &Settings,
+    ) -> Result<String> {
+        self.backend(settings)
+            .chat(&messages(settings.system.as_deref(), query, history))
+            .await
+    }
+
+    /// 返回完整的回答和还没有发给成员的部分
+    async fn answer(
+        &self,
+        query: &str,
+        history: Vec<(String, String)>,
+        settings: &Settings,
+        streamed: Option<&mut Progressive>,
+    ) -> Result<(String, String)> {
+        let p = match streamed {
+            Some(p) => p,
+            None => {
+                let r = self._chat(query, history, settings).await?;
+                return Ok((r.clone(), r));
+            }
         };
-        self.openai
-            .chat_completion_create(&body)
-            .map_err(|e| anyhow::Error::msg("openai chat completion create error").context(e))
-        // let message = &choice[0].message.as_ref().unwrap();
-        // dbg!(message);
-        // Ok(self
-        //     .c
-        //     .post(&self.api)
-        //     .header(CONTENT_TYPE, "application/json")
-        //     .json(&json!({"prompt": query, "history": history}))
-        //     .send()
-        //     .await?
-        //     .json::<GLMResponse>()
-        //     .await?)
+        let messages = messages(settings.system.as_deref(), query, history);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let llm = self.backend(settings);
+        let (r, _) = tokio::join!(llm.chat_stream(&messages, tx), async {
+            while let Some(delta) = rx.recv().await {
+                p.push(&delta).await;
+            }
+        });
+        let r = r?;
+        let rest = p.finish();
+        Ok((r, rest))
     }
 
     // pub fn queue_consumer(&mut self, mp: Arc<MP>, mut chat_mgr: ChatMgr) {
@@ -365,18 +426,165 @@ impl GLM {
     // }
 }
 
+/// 发布为网页的回答只发送摘要和链接
+fn answer_card(query: &str, answer: &str, cost: i64, url: String) -> Message {
+    let summary = render::truncate(&render::plain_text(answer), 120)
+        .replace('<', "&lt;")
+        .replace('\n', " ");
+    Message::textcard(
+        render::truncate(query, 40),
+        format!(
+            "<div class=\"gray\">对话耗时：{}s</div><div class=\"normal\">{}</div>",
+            cost, summary
+        ),
+        url,
+    )
+    .btn_txt("查看完整回答")
+}
+
+/// 多轮对话交替排列问题和回答，最后是本次的问题，设置了系统提示词时放在最前面
+fn messages(system: Option<&str>, query: &str, history: Vec<(String, String)>) -> Vec<ChatMessage> {
+    let mut r = Vec::with_capacity(history.len() * 2 + 2);
+    if let Some(s) = system {
+        r.push(ChatMessage::new(Role::System, s));
+    }
+    for (q, a) in history {
+        r.push(ChatMessage::new(Role::User, q));
+        r.push(ChatMessage::new(Role::Assistant, a));
+    }
+    r.push(ChatMessage::new(Role::User, query));
+    r
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::backend::llm::Mock;
+    use crate::backend::mp::test_util::{fake_wecom, unreachable_mp};
     use tracing::error;
     #[tokio::test]
     async fn test_chat() -> Result<()> {
-        let glm = GLM::new("");
-        glm._chat("你好", vec![vec!["他好".to_string(), "我也好".to_string()]])
-            .await?;
+        let glm = GLM::new(Arc::new(Mock::new(None)), &Default::default());
+        assert_eq!(
+            glm._chat(
+                "你好",
+                vec![("他好".to_string(), "我也好".to_string())],
+                &Settings::default()
+            )
+            .await?,
+            "你好"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_paragraph_not_published() -> Result<()> {
+        let (mp, calls) = fake_wecom();
+        let answers = Arc::new(AnswerStore::new(&crate::backend::answer::AnswerConfig {
+            base_url: Some("https://wp.example.com".to_string()),
+            min_bytes: 10,
+            ..Default::default()
+        }));
+        let answer = "第一段比较长的回答\n\n第二段\n\n最后一段";
+        let chat = |stream| {
+            let glm = GLM::new(
+                Arc::new(Mock::new(Some(answer.to_string()))),
+                &ChatConfig {
+                    stream,
+                    ..Default::default()
+                },
+            )
+            .with_answers(answers.clone());
+            let mp = mp.clone();
+            let calls = &calls;
+            async move {
+                glm.chat("u", "q", Default::default(), mp, None).await?;
+                Ok::<_, anyhow::Error>(
+                    calls
+                        .take()
+                        .into_iter()
+                        .filter(|c| c.path == "/cgi-bin/message/send")
+                        .map(|c| c.body["msgtype"].as_str().unwrap().to_string())
+                        .collect::<Vec<_>>(),
+                )
+            }
+        };
+
+        // 逐段发出后不再发送网页链接
+        assert_eq!(
+            chat(StreamMode::Paragraph).await?,
+            vec!["markdown", "markdown", "markdown"]
+        );
+        // 没有逐段发出时仍然发布为网页
+        assert_eq!(chat(StreamMode::Off).await?, vec!["textcard"]);
         Ok(())
     }
 
+    #[test]
+    fn test_answer_card() {
+        let m = answer_card(
+            "问题",
+            &format!("**结论**\n{}", "长".repeat(200)),
+            3,
+            "u".to_string(),
+        );
+        let v = serde_json::to_value(&m).unwrap();
+        assert_eq!(v["textcard"]["title"], "问题");
+        assert_eq!(v["textcard"]["url"], "u");
+        let d = v["textcard"]["description"].as_str().unwrap();
+        assert!(d.contains("对话耗时：3s"), "{}", d);
+        assert!(d.contains("结论 长") && d.ends_with("…</div>"), "{}", d);
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            messages(Some("s"), "q2", vec![("q1".to_string(), "a1".to_string())]),
+            vec![
+                ChatMessage::new(Role::System, "s"),
+                ChatMessage::new(Role::User, "q1"),
+                ChatMessage::new(Role::Assistant, "a1"),
+                ChatMessage::new(Role::User, "q2"),
+            ]
+        );
+    }
+
+    /// 一直不返回的大模型
+    struct Pending;
+
+    #[async_trait::async_trait]
+    impl LlmBackend for Pending {
+        fn name(&self) -> &'static str {
+            "pending"
+        }
+        async fn chat(&self, _messages: &[ChatMessage]) -> Result<String> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stop() {
+        let mp = unreachable_mp();
+        let glm = GLM::new(Arc::new(Pending), &Default::default());
+        let chat_mgr: Arc<Mutex<ChatMgr>> = Default::default();
+        for q in ["q1", "q2"] {
+            glm.async_chat("u", q, chat_mgr.clone(), mp.clone(), None)
+                .await;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let t = glm.pool.enqueue("other");
+        assert_eq!(glm.pool.status("other", t.id()), Status::Queued(0));
+        drop(t);
+
+        // 正在回答和排队中的问题都停止，并发名额释放
+        assert_eq!(glm.stop("u"), 2);
+        assert_eq!(glm.pool.cancel_user("u"), 0);
+        assert_eq!(glm.stop("u"), 0);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let t = glm.pool.enqueue("other");
+        assert_eq!(glm.pool.status("other", t.id()), Status::Running);
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn test_parallel() -> Result<()> {
         let h1 = tokio::spawn(tokio::time::timeout(
@@ -396,7 +604,7 @@ mod test {
         });
         error!("start");
         h2.await?;
-        h1.await?;
+        h1.await??;
 
         Ok(())
     }
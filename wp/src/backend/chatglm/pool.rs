@@ -0,0 +1,276 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// 排队中的请求状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// 前面还有多少个请求
+    Queued(usize),
+    Running,
+    Done,
+}
+
+struct Waiter {
+    id: u64,
+    tx: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    /// 有请求在等待、且没有请求在执行的成员，按轮转顺序排列
+    ready: VecDeque<String>,
+    /// 每个成员等待中的请求
+    queues: HashMap<String, VecDeque<Waiter>>,
+    /// 正在执行的成员和请求 id
+    busy: HashMap<String, u64>,
+}
+
+/// 大模型请求的并发控制。同一成员的请求按顺序执行，同时最多执行一个；
+/// 不同成员之间轮流执行，避免一个人连续提问占满所有并发
+pub struct Pool {
+    concurrency: usize,
+    state: Mutex<State>,
+}
+
+impl Pool {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            state: Default::default(),
+        }
+    }
+
+    pub fn enqueue(self: &Arc<Self>, user: &str) -> Ticket {
+        let (tx, rx) = oneshot::channel();
+        let mut s = self.state.lock().unwrap();
+        let id = s.next_id;
+        s.next_id += 1;
+        s.queues
+            .entry(user.to_string())
+            .or_default()
+            .push_back(Waiter { id, tx });
+        if !s.busy.contains_key(user) && !s.ready.iter().any(|u| u == user) {
+            s.ready.push_back(user.to_string());
+        }
+        self.schedule(&mut s);
+        Ticket {
+            pool: self.clone(),
+            user: user.to_string(),
+            id,
+            rx,
+            started: false,
+        }
+    }
+
+    pub fn status(&self, user: &str, id: u64) -> Status {
+        let s = self.state.lock().unwrap();
+        if s.busy.get(user) == Some(&id) {
+            return Status::Running;
+        }
+        let index = match s
+            .queues
+            .get(user)
+            .and_then(|q| q.iter().position(|w| w.id == id))
+        {
+            Some(i) => i,
+            None => return Status::Done,
+        };
+        // 按轮转顺序推算：先轮到 ready 中的成员，再轮到正在执行的成员。
+        // 第 r 轮中每个还有第 r 个请求的成员各执行一个
+        let mut busy = s.busy.iter().collect::<Vec<_>>();
+        busy.sort_by_key(|(_, id)| **id);
+        let order = s
+            .ready
+            .iter()
+            .chain(busy.into_iter().map(|(u, _)| u))
+            .map(|u| (u, s.queues.get(u).map(|q| q.len()).unwrap_or(0)))
+            .collect::<Vec<_>>();
+        let mut ahead = 0;
+        for round in 0..index {
+            ahead += order.iter().filter(|(_, n)| *n > round).count();
+        }
+        ahead += order
+            .iter()
+            .take_while(|(u, _)| u.as_str() != user)
+            .filter(|(_, n)| *n > index)
+            .count();
+        Status::Queued(ahead)
+    }
+
+    fn schedule(&self, s: &mut State) {
+        while s.busy.len() < self.concurrency {
+            let user = match s.ready.pop_front() {
+                Some(u) => u,
+                None => return,
+            };
+            let q = match s.queues.get_mut(&user) {
+                Some(q) => q,
+                None => continue,
+            };
+            let w = match q.pop_front() {
+                Some(w) => w,
+                None => continue,
+            };
+            if q.is_empty() {
+                s.queues.remove(&user);
+            }
+            s.busy.insert(user, w.id);
+            let _ = w.tx.send(());
+        }
+    }
+
+    fn release(&self, user: &str) {
+        let mut s = self.state.lock().unwrap();
+        s.busy.remove(user);
+        if s.queues.contains_key(user) {
+            s.ready.push_back(user.to_string());
+        }
+        self.schedule(&mut s);
+    }
+
+    /// 取消成员所有还在排队的请求，返回取消的个数。`GLM::stop` 停止问题时调用
+    pub fn cancel_user(&self, user: &str) -> usize {
+        let mut s = self.state.lock().unwrap();
+        s.ready.retain(|u| u != user);
+        s.queues.remove(user).map(|q| q.len()).unwrap_or(0)
+    }
+
+    /// 请求在开始执行前被放弃
+    fn cancel(&self, user: &str, id: u64) {
+        let mut s = self.state.lock().unwrap();
+        if let Some(q) = s.queues.get_mut(user) {
+            q.retain(|w| w.id != id);
+            if q.is_empty() {
+                s.queues.remove(user);
+                s.ready.retain(|u| u != user);
+            }
+        }
+    }
+}
+
+/// 排队凭证，`ready` 返回后开始执行
+pub struct Ticket {
+    pool: Arc<Pool>,
+    user: String,
+    id: u64,
+    rx: oneshot::Receiver<()>,
+    started: bool,
+}
+
+impl Ticket {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// 排队时被 `cancel_user` 取消返回 None，比如成员发送了 `/stop`
+    pub async fn ready(mut self) -> Option<Permit> {
+        let r = (&mut self.rx).await;
+        self.started = true;
+        r.ok().map(|_| Permit {
+            pool: self.pool.clone(),
+            user: self.user.clone(),
+        })
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if self.started {
+            return;
+        }
+        match self.rx.try_recv() {
+            // 已经轮到但还没有开始执行
+            Ok(()) => self.pool.release(&self.user),
+            Err(_) => self.pool.cancel(&self.user, self.id),
+        }
+    }
+}
+
+/// 执行结束后释放并发名额
+pub struct Permit {
+    pool: Arc<Pool>,
+    user: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.pool.release(&self.user);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fair_queue() {
+        let pool = Arc::new(Pool::new(1));
+        let a1 = pool.enqueue("a");
+        let a2 = pool.enqueue("a");
+        let a3 = pool.enqueue("a");
+        let b1 = pool.enqueue("b");
+        let (a2_id, a3_id, b1_id) = (a2.id(), a3.id(), b1.id());
+        assert_eq!(pool.status("a", a1.id()), Status::Running);
+        // a 正在执行，下一轮先轮到 b，再轮到 a
+        assert_eq!(pool.status("b", b1_id), Status::Queued(0));
+        assert_eq!(pool.status("a", a2_id), Status::Queued(1));
+        assert_eq!(pool.status("a", a3_id), Status::Queued(2));
+
+        let p = a1.ready().await;
+        assert!(p.is_some());
+        drop(p);
+        assert_eq!(pool.status("b", b1_id), Status::Running);
+        assert_eq!(pool.status("a", a2_id), Status::Queued(0));
+        drop(b1.ready().await);
+        assert_eq!(pool.status("a", a2_id), Status::Running);
+
+        // 放弃排队的请求不再占用位置
+        drop(a3);
+        assert_eq!(pool.status("a", a3_id), Status::Done);
+        drop(a2.ready().await);
+        assert_eq!(pool.status("a", a2_id), Status::Done);
+        assert!(pool.state.lock().unwrap().busy.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrency() {
+        let pool = Arc::new(Pool::new(2));
+        let a1 = pool.enqueue("a");
+        let a2 = pool.enqueue("a");
+        let b1 = pool.enqueue("b");
+        let c1 = pool.enqueue("c");
+        // 同一成员同时只执行一个请求
+        assert_eq!(pool.status("a", a1.id()), Status::Running);
+        assert_eq!(pool.status("b", b1.id()), Status::Running);
+        assert_eq!(pool.status("c", c1.id()), Status::Queued(0));
+        assert_eq!(pool.status("a", a2.id()), Status::Queued(1));
+
+        // 轮到但没有执行就放弃的请求释放名额
+        let c1_id = c1.id();
+        drop(b1);
+        assert_eq!(pool.status("c", c1_id), Status::Running);
+        drop(c1);
+        let a2_id = a2.id();
+        assert_eq!(pool.status("a", a2_id), Status::Queued(0));
+        drop(a1);
+        assert_eq!(pool.status("a", a2_id), Status::Running);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_user() {
+        let pool = Arc::new(Pool::new(1));
+        let a1 = pool.enqueue("a");
+        let b1 = pool.enqueue("b");
+        let b2 = pool.enqueue("b");
+        let c1 = pool.enqueue("c");
+        assert_eq!(pool.cancel_user("b"), 2);
+        assert_eq!(pool.cancel_user("a"), 0);
+        assert!(b1.ready().await.is_none());
+        assert_eq!(pool.status("b", b2.id()), Status::Done);
+        assert_eq!(pool.status("c", c1.id()), Status::Queued(0));
+        drop(a1.ready().await);
+        assert_eq!(pool.status("c", c1.id()), Status::Running);
+    }
+}
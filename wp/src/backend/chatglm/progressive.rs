@@ -0,0 +1,237 @@
+use crate::backend::context::StreamMode;
+use crate::backend::mp::MP;
+use crate::backend::render;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// 把流式输出的回答陆续发给成员
+pub struct Progressive {
+    mode: StreamMode,
+    interval: Duration,
+    mp: Arc<MP>,
+    user: String,
+    /// 发送文本消息
+    plain: bool,
+    answer: String,
+    /// paragraph 模式下已经发出的长度
+    sent: usize,
+    /// replace 模式下最近一次发出的消息，超长时拆成了多条
+    partial: Vec<String>,
+    updated: Instant,
+}
+
+impl Progressive {
+    pub fn new(mode: StreamMode, interval: Duration, mp: Arc<MP>, user: &str, plain: bool) -> Self {
+        Self {
+            mode,
+            interval,
+            mp,
+            user: user.to_string(),
+            plain,
+            answer: String::new(),
+            sent: 0,
+            partial: vec![],
+            updated: Instant::now(),
+        }
+    }
+
+    pub async fn push(&mut self, delta: &str) {
+        self.answer.push_str(delta);
+        match self.mode {
+            StreamMode::Off => {}
+            StreamMode::Paragraph => {
+                while let Some(end) = paragraph_end(&self.answer, self.sent) {
+                    let p = self.answer[self.sent..end].trim().to_string();
+                    self.sent = end;
+                    if !p.is_empty() {
+                        self.send(p).await;
+                    }
+                }
+            }
+            StreamMode::Replace => {
+                if self.updated.elapsed() >= self.interval {
+                    self.updated = Instant::now();
+                    let old = std::mem::take(&mut self.partial);
+                    self.partial = self.send(format!("{}▍", self.answer)).await;
+                    for id in old {
+                        self.recall(&id).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// paragraph 模式下已经有段落发给了成员
+    pub fn delivered(&self) -> bool {
+        self.sent > 0
+    }
+
+    /// 回答结束，返回还没有发出的部分。replace 模式下的消息留到最终回答发出后再撤回
+    pub fn finish(&self) -> String {
+        self.answer[self.sent..].trim_start().to_string()
+    }
+
+    /// 最终回答已经发出，撤回 replace 模式下的消息
+    pub async fn recall_partial(mut self) {
+        for id in std::mem::take(&mut self.partial) {
+            self.recall(&id).await;
+        }
+    }
+
+    /// 最终回答没有发出，保留 replace 模式下的消息
+    pub fn keep_partial(mut self) {
+        self.partial.clear();
+    }
+
+    async fn send(&self, content: String) -> Vec<String> {
+        match self
+            .mp
+            .send_split(render::message(&content, self.plain).to_users([&self.user]))
+            .await
+        {
+            Ok(r) => r.into_iter().map(|r| r.msg_id).collect(),
+            Err(e) => {
+                warn!(u = self.user, "glm partial response send error: {:?}", e);
+                vec![]
+            }
+        }
+    }
+
+    async fn recall(&self, msg_id: &str) {
+        if let Err(e) = self.mp.message_recall(msg_id).await {
+            warn!(u = self.user, "glm partial response recall error: {:?}", e);
+        }
+    }
+}
+
+/// 回答被停止时撤回 replace 模式下还没有替换的消息
+impl Drop for Progressive {
+    fn drop(&mut self) {
+        let partial = std::mem::take(&mut self.partial);
+        if partial.is_empty() {
+            return;
+        }
+        let mp = self.mp.clone();
+        tokio::spawn(async move {
+            for id in partial {
+                if let Err(e) = mp.message_recall(&id).await {
+                    warn!("glm partial response recall error: {:?}", e);
+                }
+            }
+        });
+    }
+}
+
+/// 从 `from` 开始第一个完整段落的结束位置，代码块内的空行不算段落结束
+fn paragraph_end(s: &str, from: usize) -> Option<usize> {
+    let mut fences = s[..from].matches("```").count();
+    let mut last = from;
+    for (i, _) in s[from..].match_indices("\n\n") {
+        let i = from + i;
+        fences += s[last..i].matches("```").count();
+        last = i;
+        if fences.is_multiple_of(2) {
+            return Some(i + 2);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::mp::capture::Call;
+    use crate::backend::mp::test_util::fake_wecom;
+    use crate::backend::mp::Message;
+
+    /// 发送的文本或者撤回的 msgid
+    fn summary(calls: Vec<Call>) -> Vec<String> {
+        calls
+            .into_iter()
+            .map(|c| match c.path.as_str() {
+                "/cgi-bin/message/send" => {
+                    format!("send {}", c.body["text"]["content"].as_str().unwrap())
+                }
+                "/cgi-bin/message/recall" => {
+                    format!("recall {}", c.body["msgid"].as_str().unwrap())
+                }
+                p => p.to_string(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_replace() {
+        let (mp, calls) = fake_wecom();
+        let mut p = Progressive::new(StreamMode::Replace, Duration::ZERO, mp.clone(), "u", true);
+        p.push("a").await;
+        // 发出新的部分回答后撤回上一条
+        p.push("b").await;
+        assert_eq!(summary(calls.take()), ["send a▍", "send ab▍", "recall m1"]);
+
+        // 最终回答发出之后才撤回部分回答
+        assert_eq!(p.finish(), "ab");
+        mp.send(Message::text("ab").to_users(["u"])).await.unwrap();
+        p.recall_partial().await;
+        assert_eq!(summary(calls.take()), ["send ab", "recall m2"]);
+    }
+
+    #[tokio::test]
+    async fn test_replace_interval() {
+        let (mp, calls) = fake_wecom();
+        let mut p = Progressive::new(
+            StreamMode::Replace,
+            Duration::from_millis(100),
+            mp,
+            "u",
+            true,
+        );
+        p.push("a").await;
+        assert!(calls.take().is_empty());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        p.push("b").await;
+        p.push("c").await;
+        assert_eq!(summary(calls.take()), ["send ab▍"]);
+        p.keep_partial();
+    }
+
+    #[tokio::test]
+    async fn test_replace_keep_partial() {
+        let (mp, calls) = fake_wecom();
+        let mut p = Progressive::new(StreamMode::Replace, Duration::ZERO, mp.clone(), "u", true);
+        p.push("a").await;
+        calls.fail("/cgi-bin/message/send");
+        // 最终回答发送失败时保留部分回答，丢弃后也不再撤回
+        assert!(mp.send(Message::text("a").to_users(["u"])).await.is_err());
+        p.keep_partial();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(summary(calls.take()), ["send a▍", "send a"]);
+    }
+
+    #[tokio::test]
+    async fn test_replace_drop() {
+        let (mp, calls) = fake_wecom();
+        let mut p = Progressive::new(StreamMode::Replace, Duration::ZERO, mp, "u", true);
+        p.push("a").await;
+        // 回答被停止时撤回部分回答
+        drop(p);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(summary(calls.take()), ["send a▍", "recall m1"]);
+    }
+
+    #[test]
+    fn test_paragraph_end() {
+        let s = "第一段\n\n```rust\nfn a() {}\n\nfn b() {}\n```\n\n最后";
+        let first = paragraph_end(s, 0).unwrap();
+        assert_eq!(&s[..first], "第一段\n\n");
+        // 代码块中的空行不拆分
+        let second = paragraph_end(s, first).unwrap();
+        assert_eq!(
+            &s[first..second],
+            "```rust\nfn a() {}\n\nfn b() {}\n```\n\n"
+        );
+        assert_eq!(paragraph_end(s, second), None);
+        assert_eq!(paragraph_end("```\na\n\nb", 0), None);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::backend::chatglm::{Pool, Status};
+use crate::backend::mp::{Message, MP};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// ```toml
+/// [progress]
+/// first_secs = 13
+/// interval_secs = 39
+/// queued = "前面还有 {n} 个问题，忙完马上回复你"
+/// recall = true
+/// ```
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProgressConfig {
+    /// 提问后多久发送第一条进度提醒，0 表示不提醒
+    #[serde(default = "default_first_secs")]
+    pub first_secs: u64,
+    /// 之后每隔多久提醒一次
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// 正在回答时的提醒
+    #[serde(default = "default_running")]
+    pub running: String,
+    /// 下一个就轮到时的提醒
+    #[serde(default = "default_next")]
+    pub next: String,
+    /// 排队时的提醒，`{n}` 替换为前面还有多少个问题
+    #[serde(default = "default_queued")]
+    pub queued: String,
+    /// 回答或者失败提示发出后撤回进度提醒
+    #[serde(default = "default_recall")]
+    pub recall: bool,
+}
+
+fn default_first_secs() -> u64 {
+    13
+}
+
+fn default_interval_secs() -> u64 {
+    39
+}
+
+fn default_running() -> String {
+    "小秘书😣正在燃烧为数不多[求赞助]的脑细胞帮你回答问题，莫急莫急".to_string()
+}
+
+fn default_next() -> String {
+    "小秘书😣忙得焦头烂额，下一个就轮到你了".to_string()
+}
+
+fn default_queued() -> String {
+    "小秘书😣忙得焦头烂额，前面还有 {n} 个问题，忙完马上回复你".to_string()
+}
+
+fn default_recall() -> bool {
+    true
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        Self {
+            first_secs: default_first_secs(),
+            interval_secs: default_interval_secs(),
+            running: default_running(),
+            next: default_next(),
+            queued: default_queued(),
+            recall: default_recall(),
+        }
+    }
+}
+
+impl ProgressConfig {
+    /// 回答已经结束时返回 None
+    fn text(&self, status: Status) -> Option<String> {
+        match status {
+            Status::Running => Some(self.running.clone()),
+            Status::Queued(0) => Some(self.next.clone()),
+            Status::Queued(n) => Some(self.queued.replace("{n}", &n.to_string())),
+            Status::Done => None,
+        }
+    }
+}
+
+/// 等待回答期间定时给成员发送进度提醒，记录发出的消息用于撤回
+pub struct Reminder {
+    task: Option<JoinHandle<()>>,
+    /// 已经发出的提醒的 msgid
+    sent: Arc<Mutex<Vec<String>>>,
+    mp: Arc<MP>,
+    user: String,
+    recall: bool,
+}
+
+impl Reminder {
+    /// 提醒 `pool` 中 `id` 的排队和执行状态
+    pub fn start(
+        conf: Arc<ProgressConfig>,
+        pool: Arc<Pool>,
+        id: u64,
+        mp: Arc<MP>,
+        user: &str,
+    ) -> Self {
+        let sent: Arc<Mutex<Vec<String>>> = Default::default();
+        let recall = conf.recall;
+        let task = (conf.first_secs > 0).then(|| {
+            let sent = sent.clone();
+            let mp = mp.clone();
+            let user = user.to_string();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(conf.first_secs)).await;
+                loop {
+                    let text = match conf.text(pool.status(&user, id)) {
+                        Some(t) => t,
+                        None => return,
+                    };
+                    match mp.send(Message::text(text).to_users([&user])).await {
+                        Ok(r) => sent.lock().unwrap().push(r.msg_id),
+                        Err(e) => warn!(u = user, "glm progress send error: {:?}", e),
+                    }
+                    tokio::time::sleep(Duration::from_secs(conf.interval_secs.max(1))).await;
+                }
+            })
+        });
+        Self {
+            task,
+            sent,
+            mp,
+            user: user.to_string(),
+            recall,
+        }
+    }
+
+    /// 不再发送新的提醒，已经发出的留到 `recall` 时撤回
+    pub fn stop(&mut self) {
+        if let Some(t) = self.task.take() {
+            t.abort();
+        }
+    }
+
+    /// 撤回已经发出的提醒，在回答发出之后调用
+    pub async fn recall(mut self) {
+        self.stop();
+        if !self.recall {
+            return;
+        }
+        let sent = std::mem::take(&mut *self.sent.lock().unwrap());
+        recall(&self.mp, &self.user, sent).await;
+    }
+}
+
+/// 问题被停止或者取消时也撤回已经发出的提醒
+impl Drop for Reminder {
+    fn drop(&mut self) {
+        self.stop();
+        let sent = std::mem::take(&mut *self.sent.lock().unwrap());
+        if !self.recall || sent.is_empty() {
+            return;
+        }
+        let mp = self.mp.clone();
+        let user = std::mem::take(&mut self.user);
+        tokio::spawn(async move { recall(&mp, &user, sent).await });
+    }
+}
+
+async fn recall(mp: &MP, user: &str, sent: Vec<String>) {
+    for id in sent {
+        if let Err(e) = mp.message_recall(&id).await {
+            warn!(u = user, "glm progress recall error: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::mp::capture::Call;
+    use crate::backend::mp::test_util::fake_wecom;
+
+    #[tokio::test]
+    async fn test_recall() {
+        let (mp, calls) = fake_wecom();
+        let pool = Arc::new(Pool::new(1));
+        let ticket = pool.enqueue("u");
+        let conf = Arc::new(ProgressConfig {
+            first_secs: 1,
+            interval_secs: 1,
+            ..Default::default()
+        });
+        let paths = |calls: Vec<Call>| {
+            calls
+                .into_iter()
+                .map(|c| match c.path.as_str() {
+                    "/cgi-bin/message/recall" => c.body["msgid"].as_str().unwrap().to_string(),
+                    p => p.to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // 回答发出后撤回提醒
+        let r = Reminder::start(conf.clone(), pool.clone(), ticket.id(), mp.clone(), "u");
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let sent = calls.take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].body["text"]["content"], default_running());
+        r.recall().await;
+        assert_eq!(paths(calls.take()), ["m1"]);
+
+        // 失败或者停止时丢弃，也会撤回
+        let r = Reminder::start(conf.clone(), pool.clone(), ticket.id(), mp.clone(), "u");
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        drop(r);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(paths(calls.take()), ["/cgi-bin/message/send", "m2"]);
+
+        // 不撤回时保留
+        let conf = Arc::new(ProgressConfig {
+            recall: false,
+            ..(*conf).clone()
+        });
+        let r = Reminder::start(conf, pool.clone(), ticket.id(), mp, "u");
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        r.recall().await;
+        assert_eq!(paths(calls.take()), ["/cgi-bin/message/send"]);
+    }
+
+    #[test]
+    fn test_text() {
+        let conf: ProgressConfig = toml::from_str(r#"queued = "还有 {n} 个""#).unwrap();
+        assert_eq!(conf.interval_secs, 39);
+        assert_eq!(conf.text(Status::Queued(3)).unwrap(), "还有 3 个");
+        assert_eq!(conf.text(Status::Queued(0)).unwrap(), default_next());
+        assert_eq!(conf.text(Status::Running).unwrap(), default_running());
+        assert_eq!(conf.text(Status::Done), None);
+    }
+}
@@ -0,0 +1,221 @@
+pub mod chat;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::info;
+
+/// 聊天机器人的斜杠命令，在调用大模型之前处理
+#[async_trait]
+pub trait Command: Send + Sync {
+    /// 不带斜杠的命令名，也是 `[commands]` 配置中引用的名字
+    fn name(&self) -> &'static str;
+    /// 中文别名
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// 参数说明，比如 `<名字>`
+    fn args(&self) -> &'static str {
+        ""
+    }
+    /// 一句话说明，用于 `/help`
+    fn help(&self) -> &'static str;
+    /// 返回回复给成员的文本
+    async fn run(&self, user: &str, args: &str) -> String;
+}
+
+/// ```toml
+/// [commands]
+/// disabled = ["export"]
+///
+/// [commands.permissions]
+/// model = ["zhangsan", "lisi"]
+/// ```
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CommandsConfig {
+    /// 关闭的命令，发送后当作普通问题
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// 只允许这些成员使用的命令，未配置的命令所有人可用
+    #[serde(default)]
+    pub permissions: HashMap<String, Vec<String>>,
+}
+
+const HELP: &str = "help";
+const HELP_ALIASES: &[&str] = &["帮助"];
+
+pub struct Commands {
+    list: Vec<Arc<dyn Command>>,
+    /// 命令名和别名到 list 下标
+    names: HashMap<&'static str, usize>,
+    permissions: HashMap<String, Vec<String>>,
+}
+
+impl Commands {
+    /// 命令名或别名重复、配置中引用了未注册的命令时返回错误
+    pub fn new(conf: &CommandsConfig, commands: Vec<Arc<dyn Command>>) -> Result<Self> {
+        let known = |n: &str| n == HELP || commands.iter().any(|c| c.name() == n);
+        if let Some(n) = conf
+            .disabled
+            .iter()
+            .chain(conf.permissions.keys())
+            .find(|n| !known(n))
+        {
+            return Err(anyhow!("未知的命令 {}", n));
+        }
+        let list = commands
+            .into_iter()
+            .filter(|c| !conf.disabled.iter().any(|d| d == c.name()))
+            .collect::<Vec<_>>();
+        let mut names = HashMap::new();
+        for n in [HELP].iter().chain(HELP_ALIASES) {
+            names.insert(*n, usize::MAX);
+        }
+        for (i, c) in list.iter().enumerate() {
+            for n in [c.name()].iter().chain(c.aliases()) {
+                if names.insert(*n, i).is_some() {
+                    return Err(anyhow!("命令 /{} 重复", n));
+                }
+            }
+        }
+        Ok(Self {
+            list,
+            names,
+            permissions: conf.permissions.clone(),
+        })
+    }
+
+    /// 不是已注册的命令时返回 None，交给大模型回答
+    pub async fn dispatch(&self, user: &str, content: &str) -> Option<String> {
+        let (name, args) = parse(content)?;
+        let i = *self.names.get(name)?;
+        if i == usize::MAX {
+            return Some(self.help(user));
+        }
+        let c = &self.list[i];
+        if !self.allowed(c.name(), user) {
+            return Some(format!("没有权限使用 /{}", c.name()));
+        }
+        info!(u = user, cmd = c.name(), "chat command");
+        Some(c.run(user, args).await)
+    }
+
+    /// 成员可以使用的命令
+    pub fn help(&self, user: &str) -> String {
+        let mut r = String::from("可用命令：");
+        let line = |name: &str, args: &str, aliases: &[&str], help: &str| {
+            let mut l = format!("\n/{}", name);
+            if !args.is_empty() {
+                l.push(' ');
+                l.push_str(args);
+            }
+            if !aliases.is_empty() {
+                let a = aliases
+                    .iter()
+                    .map(|a| format!("/{}", a))
+                    .collect::<Vec<_>>();
+                l.push_str(&format!("（{}）", a.join("、")));
+            }
+            l.push_str(&format!("：{}", help));
+            l
+        };
+        r.push_str(&line(HELP, "", HELP_ALIASES, "显示这条帮助"));
+        for c in self.list.iter().filter(|c| self.allowed(c.name(), user)) {
+            r.push_str(&line(c.name(), c.args(), c.aliases(), c.help()));
+        }
+        r
+    }
+
+    fn allowed(&self, name: &str, user: &str) -> bool {
+        self.permissions
+            .get(name)
+            .map(|users| users.iter().any(|u| u == user))
+            .unwrap_or(true)
+    }
+}
+
+/// `/name args`，也接受全角斜杠
+fn parse(content: &str) -> Option<(&str, &str)> {
+    let s = content.trim();
+    let s = s.strip_prefix('/').or_else(|| s.strip_prefix('／'))?;
+    let (name, args) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl Command for Echo {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+        fn aliases(&self) -> &'static [&'static str] {
+            &["复述"]
+        }
+        fn args(&self) -> &'static str {
+            "<内容>"
+        }
+        fn help(&self) -> &'static str {
+            "复述内容"
+        }
+        async fn run(&self, user: &str, args: &str) -> String {
+            format!("{}: {}", user, args)
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" /model  gpt-4 "), Some(("model", "gpt-4")));
+        assert_eq!(parse("／帮助"), Some(("帮助", "")));
+        assert_eq!(parse("/"), None);
+        assert_eq!(parse("你好"), None);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() -> Result<()> {
+        let conf = CommandsConfig {
+            disabled: vec![],
+            permissions: HashMap::from([("echo".to_string(), vec!["a".to_string()])]),
+        };
+        let commands = Commands::new(&conf, vec![Arc::new(Echo)])?;
+        assert_eq!(commands.dispatch("a", "/复述 hi").await.unwrap(), "a: hi");
+        assert_eq!(
+            commands.dispatch("b", "/echo hi").await.unwrap(),
+            "没有权限使用 /echo"
+        );
+        // 未注册的命令交给大模型
+        assert_eq!(commands.dispatch("a", "/etc/hosts 是什么").await, None);
+        assert_eq!(
+            commands.dispatch("a", "/help").await.unwrap(),
+            "可用命令：\n/help（/帮助）：显示这条帮助\n/echo <内容>（/复述）：复述内容"
+        );
+        assert_eq!(
+            commands.dispatch("b", "/帮助").await.unwrap(),
+            "可用命令：\n/help（/帮助）：显示这条帮助"
+        );
+
+        let conf = CommandsConfig {
+            disabled: vec!["echo".to_string()],
+            permissions: Default::default(),
+        };
+        let commands = Commands::new(&conf, vec![Arc::new(Echo)])?;
+        assert_eq!(commands.dispatch("a", "/echo hi").await, None);
+
+        let conf = CommandsConfig {
+            disabled: vec!["missing".to_string()],
+            permissions: Default::default(),
+        };
+        assert!(Commands::new(&conf, vec![Arc::new(Echo)]).is_err());
+        assert!(Commands::new(&Default::default(), vec![Arc::new(Echo), Arc::new(Echo)]).is_err());
+        Ok(())
+    }
+}
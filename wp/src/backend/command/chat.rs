@@ -0,0 +1,344 @@
+use crate::backend::chatglm::{ANSWER_TIMEOUT, GLM};
+use crate::backend::command::Command;
+use crate::backend::context::{Chat, ChatMgr};
+use crate::backend::mp::{Message, MP};
+use crate::backend::render::truncate;
+use async_trait::async_trait;
+use std::sync::Arc;
+use time::macros::{format_description, offset};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// 对话相关命令共用的状态
+#[derive(Clone)]
+pub struct ChatState {
+    pub mp: Arc<MP>,
+    pub glm: Arc<GLM>,
+    pub chat_mgr: Arc<Mutex<ChatMgr>>,
+}
+
+/// 按 `/help` 中的顺序注册
+pub fn commands(s: ChatState) -> Vec<Arc<dyn Command>> {
+    vec![
+        Arc::new(Clean(s.clone())),
+        Arc::new(Model(s.clone())),
+        Arc::new(System(s.clone())),
+        Arc::new(History(s.clone())),
+        Arc::new(Retry(s.clone())),
+        Arc::new(Stop(s.clone())),
+        Arc::new(Export(s)),
+    ]
+}
+
+pub struct Clean(ChatState);
+
+#[async_trait]
+impl Command for Clean {
+    fn name(&self) -> &'static str {
+        "clean"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["清空", "新对话"]
+    }
+    fn help(&self) -> &'static str {
+        "清空对话上下文，开始新的对话"
+    }
+    async fn run(&self, user: &str, _args: &str) -> String {
+        self.0.chat_mgr.lock().await.clear(user);
+        "让我们开始新的对话吧".to_string()
+    }
+}
+
+pub struct Model(ChatState);
+
+#[async_trait]
+impl Command for Model {
+    fn name(&self) -> &'static str {
+        "model"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["模型"]
+    }
+    fn args(&self) -> &'static str {
+        "[名字]"
+    }
+    fn help(&self) -> &'static str {
+        "查看或切换模型，default 为默认模型"
+    }
+    async fn run(&self, user: &str, args: &str) -> String {
+        let models = self.0.glm.models();
+        let mut m = self.0.chat_mgr.lock().await;
+        if args.is_empty() {
+            let current = m.settings(user).model;
+            return format!(
+                "当前模型：{}\n可选模型：default{}",
+                current.as_deref().unwrap_or("default"),
+                models
+                    .iter()
+                    .map(|m| format!("、{}", m))
+                    .collect::<String>()
+            );
+        }
+        if args == "default" || args == "默认" {
+            m.settings_mut(user).model = None;
+            return "已切换到默认模型".to_string();
+        }
+        if !models.contains(&args) {
+            return format!("没有模型 {}，发送 /model 查看可选模型", args);
+        }
+        m.settings_mut(user).model = Some(args.to_string());
+        format!("已切换到模型 {}", args)
+    }
+}
+
+pub struct System(ChatState);
+
+#[async_trait]
+impl Command for System {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["设定"]
+    }
+    fn args(&self) -> &'static str {
+        "[提示词|clear]"
+    }
+    fn help(&self) -> &'static str {
+        "查看或设置系统提示词，clear 清除"
+    }
+    async fn run(&self, user: &str, args: &str) -> String {
+        let mut m = self.0.chat_mgr.lock().await;
+        match args {
+            "" => match m.settings(user).system {
+                Some(s) => format!("当前系统提示词：{}", s),
+                None => "还没有设置系统提示词".to_string(),
+            },
+            "clear" | "清除" => {
+                m.settings_mut(user).system = None;
+                "已清除系统提示词".to_string()
+            }
+            s => {
+                m.settings_mut(user).system = Some(s.to_string());
+                "已设置系统提示词，之后的问题都会带上".to_string()
+            }
+        }
+    }
+}
+
+/// `/history` 最多显示的对话轮数
+const HISTORY_LIMIT: usize = 10;
+
+pub struct History(ChatState);
+
+#[async_trait]
+impl Command for History {
+    fn name(&self) -> &'static str {
+        "history"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["历史"]
+    }
+    fn help(&self) -> &'static str {
+        "查看最近的对话"
+    }
+    async fn run(&self, user: &str, _args: &str) -> String {
+        let m = self.0.chat_mgr.lock().await;
+        let chats = conversations(&m, user);
+        if chats.is_empty() {
+            return "还没有对话记录".to_string();
+        }
+        let skip = chats.len().saturating_sub(HISTORY_LIMIT);
+        let mut r = format!("最近 {} 轮对话：", chats.len() - skip);
+        for (i, c) in chats.iter().skip(skip).enumerate() {
+            r.push_str(&format!(
+                "\n{}. {}\n　{}",
+                i + 1,
+                truncate(&c.1, 30),
+                truncate(&c.2.replace('\n', " "), 40)
+            ));
+        }
+        r
+    }
+}
+
+pub struct Retry(ChatState);
+
+#[async_trait]
+impl Command for Retry {
+    fn name(&self) -> &'static str {
+        "retry"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["重试"]
+    }
+    fn help(&self) -> &'static str {
+        "重新回答上一个问题"
+    }
+    async fn run(&self, user: &str, _args: &str) -> String {
+        let last = self.0.chat_mgr.lock().await.pop(user);
+        let (q, _) = match last {
+            Some(c) => c,
+            None => return "没有可以重新回答的问题".to_string(),
+        };
+        self.0
+            .glm
+            .async_chat(
+                user,
+                &q,
+                self.0.chat_mgr.clone(),
+                self.0.mp.clone(),
+                Some(ANSWER_TIMEOUT),
+            )
+            .await;
+        format!("重新回答：{}", truncate(&q, 30))
+    }
+}
+
+pub struct Stop(ChatState);
+
+#[async_trait]
+impl Command for Stop {
+    fn name(&self) -> &'static str {
+        "stop"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["停止"]
+    }
+    fn help(&self) -> &'static str {
+        "停止正在回答和排队中的问题"
+    }
+    async fn run(&self, user: &str, _args: &str) -> String {
+        match self.0.glm.stop(user) {
+            0 => "没有正在回答的问题".to_string(),
+            n => format!("已停止 {} 个问题", n),
+        }
+    }
+}
+
+pub struct Export(ChatState);
+
+#[async_trait]
+impl Command for Export {
+    fn name(&self) -> &'static str {
+        "export"
+    }
+    fn aliases(&self) -> &'static [&'static str] {
+        &["导出"]
+    }
+    fn help(&self) -> &'static str {
+        "把对话记录导出为 markdown 文件"
+    }
+    async fn run(&self, user: &str, _args: &str) -> String {
+        let content = {
+            let m = self.0.chat_mgr.lock().await;
+            let chats = conversations(&m, user);
+            if chats.is_empty() {
+                return "还没有对话记录".to_string();
+            }
+            export(&chats)
+        };
+        // 上传可能很慢，不能等到被动回复超时
+        let mp = self.0.mp.clone();
+        let user = user.to_string();
+        tokio::spawn(async move {
+            let r = async {
+                let media_id = mp
+                    .upload_media("file", "对话记录.md", content.as_bytes())
+                    .await?;
+                mp.send(Message::file(media_id).to_users([&user])).await
+            }
+            .await;
+            if let Err(e) = r {
+                warn!(u = user, "export chat history error: {:?}", e);
+                let _ = mp
+                    .send(Message::text("导出失败，请稍后再试试").to_users([&user]))
+                    .await;
+            }
+        });
+        "正在导出对话记录，稍后以文件发给你".to_string()
+    }
+}
+
+fn conversations(m: &ChatMgr, user: &str) -> Vec<Chat> {
+    m.get(user)
+        .map(|c| c.conversations.clone())
+        .unwrap_or_default()
+}
+
+fn export(chats: &[Chat]) -> String {
+    let mut r = String::from("# 对话记录\n");
+    for (ts, q, a) in chats {
+        let t = time::OffsetDateTime::from_unix_timestamp(*ts)
+            .map(|t| t.to_offset(offset!(+8)))
+            .ok()
+            .and_then(|t| {
+                t.format(format_description!(
+                    "[year]-[month]-[day] [hour]:[minute]:[second]"
+                ))
+                .ok()
+            })
+            .unwrap_or_default();
+        r.push_str(&format!("\n## {}\n\n**{}**\n\n{}\n", t, q, a));
+    }
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::command::Commands;
+    use crate::backend::llm::Mock;
+    use crate::backend::mp::test_util::unreachable_mp;
+
+    #[tokio::test]
+    async fn test_commands() -> anyhow::Result<()> {
+        let mp = unreachable_mp();
+        let chat_mgr = Arc::new(Mutex::new(ChatMgr::default()));
+        let glm = GLM::new(Arc::new(Mock::new(None)), &Default::default())
+            .with_models([("qwen".to_string(), Arc::new(Mock::new(None)) as _)].into());
+        let commands = Commands::new(
+            &Default::default(),
+            commands(ChatState {
+                mp,
+                glm: Arc::new(glm),
+                chat_mgr: chat_mgr.clone(),
+            }),
+        )?;
+        let run = |c: &'static str| {
+            let commands = &commands;
+            async move { commands.dispatch("u", c).await.unwrap() }
+        };
+
+        assert_eq!(
+            run("/模型").await,
+            "当前模型：default\n可选模型：default、qwen"
+        );
+        assert_eq!(run("/model qwen").await, "已切换到模型 qwen");
+        assert!(run("/model gpt").await.starts_with("没有模型 gpt"));
+        assert_eq!(
+            run("/system 用中文回答").await,
+            "已设置系统提示词，之后的问题都会带上"
+        );
+        let settings = chat_mgr.lock().await.settings("u");
+        assert_eq!(settings.model.as_deref(), Some("qwen"));
+        assert_eq!(settings.system.as_deref(), Some("用中文回答"));
+
+        assert_eq!(run("/history").await, "还没有对话记录");
+        chat_mgr.lock().await.add("u", "q1", "a1\nb1", 0);
+        assert_eq!(run("/历史").await, "最近 1 轮对话：\n1. q1\n　a1 b1");
+        assert_eq!(run("/export").await, "正在导出对话记录，稍后以文件发给你");
+        assert_eq!(run("/stop").await, "没有正在回答的问题");
+        assert_eq!(run("/clean").await, "让我们开始新的对话吧");
+        assert_eq!(run("/retry").await, "没有可以重新回答的问题");
+        Ok(())
+    }
+
+    #[test]
+    fn test_export() {
+        assert_eq!(
+            export(&[(0, "q".to_string(), "a".to_string())]),
+            "# 对话记录\n\n## 1970-01-01 08:00:00\n\n**q**\n\na\n"
+        );
+    }
+}
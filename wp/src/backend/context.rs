@@ -1,11 +1,91 @@
+use crate::backend::render::truncate;
+use serde::Deserialize;
 use std::collections::HashMap;
 
 pub type Chat = (i64, String, String);
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChatConfig {
+    /// 只把最近多少秒内的对话作为上下文
+    #[serde(default = "default_history_secs")]
+    pub history_secs: i64,
+    /// 上下文中历史问题和回答的总字符数，超出时丢弃最早的对话
+    #[serde(default = "default_history_chars")]
+    pub history_chars: usize,
+    /// 同时请求大模型的对话数，同一成员的问题总是依次回答
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// 流式输出时怎样把回答陆续发给成员
+    #[serde(default)]
+    pub stream: StreamMode,
+    /// replace 模式下两次更新消息的最小间隔
+    #[serde(default = "default_stream_interval_secs")]
+    pub stream_interval_secs: u64,
+    /// 给这些成员发送文本消息而不是 markdown，比如在微信插件中使用的成员。
+    /// 企业微信的回调中没有区分微信插件，只能在这里配置
+    #[serde(default)]
+    pub plain_users: Vec<String>,
+    /// 成员提出新问题时停止还没有回答完的问题
+    #[serde(default)]
+    pub stop_previous: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamMode {
+    /// 回答完成后一次性发送
+    #[default]
+    Off,
+    /// 每生成完一段就作为单独的消息发送
+    Paragraph,
+    /// 定时撤回上一条消息，发送目前为止的回答
+    Replace,
+}
+
+fn default_history_secs() -> i64 {
+    60 * 30
+}
+
+fn default_history_chars() -> usize {
+    4000
+}
+
+fn default_concurrency() -> usize {
+    1
+}
+
+fn default_stream_interval_secs() -> u64 {
+    10
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            history_secs: default_history_secs(),
+            history_chars: default_history_chars(),
+            concurrency: default_concurrency(),
+            stream: StreamMode::default(),
+            stream_interval_secs: default_stream_interval_secs(),
+            plain_users: vec![],
+            stop_previous: false,
+        }
+    }
+}
+
+/// 成员通过命令修改的对话设置，`/clean` 不会清除
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    /// `/model` 选择的模型，None 为默认模型
+    pub model: Option<String>,
+    /// `/system` 设置的系统提示词
+    pub system: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChatContext {
     pub user_id: String,
     pub conversations: Vec<Chat>,
+    pub settings: Settings,
 }
 
 impl ChatContext {
@@ -13,20 +93,34 @@ impl ChatContext {
         Self {
             user_id: user_id.to_string(),
             conversations: vec![],
+            settings: Settings::default(),
         }
     }
-    pub fn history(&self) -> Vec<Vec<String>> {
+    /// 按时间顺序返回 (问题, 回答)。从最新的对话往前取，
+    /// 超出时间窗口或字符预算后更早的对话都不再使用。
+    /// 最近一轮超出预算时截断后保留，追问长回答时仍然有上下文
+    pub fn history(&self, conf: &ChatConfig, now: i64) -> Vec<(String, String)> {
+        let before = now - conf.history_secs;
+        let mut budget = conf.history_chars;
         let mut r = vec![];
-        let before = time::OffsetDateTime::now_utc().unix_timestamp() - 60 * 30;
-
-        // conversations for loop
-        for c in &self.conversations {
+        for c in self.conversations.iter().rev() {
             if c.0 < before {
-                continue;
+                break;
+            }
+            let (q, a) = (c.1.chars().count(), c.2.chars().count());
+            if q + a > budget {
+                if r.is_empty() && budget > 0 {
+                    // 问题最多占一半预算，其余留给回答
+                    let q = q.min((budget / 2).max(budget.saturating_sub(a)));
+                    r.push((truncate(&c.1, q), truncate(&c.2, budget - q)));
+                }
+                break;
             }
-            r.push(vec![c.1.clone(), c.2.clone()]);
+            let n = q + a;
+            budget -= n;
+            r.push((c.1.clone(), c.2.clone()));
         }
-
+        r.reverse();
         r
     }
 }
@@ -34,30 +128,111 @@ impl ChatContext {
 #[derive(Debug, Clone, Default)]
 pub struct ChatMgr {
     pub chats: HashMap<String, ChatContext>,
+    conf: ChatConfig,
 }
 
 impl ChatMgr {
+    pub fn new(conf: &ChatConfig) -> Self {
+        Self {
+            chats: HashMap::new(),
+            conf: conf.clone(),
+        }
+    }
+    /// 记录一轮对话，同时丢弃时间窗口之外的对话
     pub fn add(&mut self, user_id: &str, q: &str, a: &str, ts: i64) {
-        let c = self
-            .chats
-            .entry(user_id.to_string())
-            .or_insert(ChatContext {
-                user_id: user_id.to_string(),
-                conversations: vec![],
-            });
+        let before = ts - self.conf.history_secs;
+        let c = self.context(user_id);
+        c.conversations.retain(|c| c.0 >= before);
         c.conversations.push((ts, q.to_string(), a.to_string()));
     }
     pub fn get(&self, user_id: &str) -> Option<&ChatContext> {
         self.chats.get(user_id)
     }
+    /// 当前可以作为上下文的对话
+    pub fn history(&self, user_id: &str) -> Vec<(String, String)> {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        self.get(user_id)
+            .map(|c| c.history(&self.conf, now))
+            .unwrap_or_default()
+    }
     pub fn clear(&mut self, user_id: &str) {
-        let c = self
-            .chats
+        self.context(user_id).conversations.clear();
+    }
+    /// 取出最近一轮对话，用于重新回答
+    pub fn pop(&mut self, user_id: &str) -> Option<(String, String)> {
+        self.chats
+            .get_mut(user_id)
+            .and_then(|c| c.conversations.pop())
+            .map(|c| (c.1, c.2))
+    }
+    pub fn settings(&self, user_id: &str) -> Settings {
+        self.get(user_id)
+            .map(|c| c.settings.clone())
+            .unwrap_or_default()
+    }
+    pub fn settings_mut(&mut self, user_id: &str) -> &mut Settings {
+        &mut self.context(user_id).settings
+    }
+    fn context(&mut self, user_id: &str) -> &mut ChatContext {
+        self.chats
             .entry(user_id.to_string())
-            .or_insert(ChatContext {
-                user_id: user_id.to_string(),
-                conversations: vec![],
-            });
-        c.conversations.clear();
+            .or_insert_with(|| ChatContext::new(user_id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let conf = ChatConfig {
+            history_secs: 100,
+            history_chars: 10,
+            ..Default::default()
+        };
+        let mut m = ChatMgr::new(&conf);
+        m.add("u", "q0", "a0", 0);
+        m.add("u", "q1", "a1", 150);
+        m.add("u", "q2", "a2", 160);
+        m.add("u", "q3", "a3", 170);
+        // 添加时已经丢弃了窗口之外的对话
+        assert_eq!(m.get("u").unwrap().conversations.len(), 3);
+
+        // 字符预算只够最近两轮
+        let c = m.get("u").unwrap();
+        assert_eq!(
+            c.history(&conf, 170),
+            vec![
+                ("q2".to_string(), "a2".to_string()),
+                ("q3".to_string(), "a3".to_string())
+            ]
+        );
+        assert_eq!(
+            c.history(&conf, 265),
+            vec![("q3".to_string(), "a3".to_string())]
+        );
+
+        // 最近一轮超出预算时截断保留，更早的对话不再使用
+        m.add("u", "问题", &"回答".repeat(10), 180);
+        assert_eq!(
+            m.get("u").unwrap().history(&conf, 180),
+            vec![("问题".to_string(), "回答回答回答回答…".to_string())]
+        );
+        m.add("u", &"问".repeat(20), "答", 190);
+        assert_eq!(
+            m.get("u").unwrap().history(&conf, 190),
+            vec![("问问问问问问问问问…".to_string(), "答".to_string())]
+        );
+        m.pop("u");
+        m.pop("u");
+
+        m.settings_mut("u").system = Some("s".to_string());
+        assert_eq!(m.pop("u"), Some(("q3".to_string(), "a3".to_string())));
+        m.clear("u");
+        assert!(m.history("u").is_empty());
+        // 清空对话不影响设置
+        assert_eq!(m.settings("u").system.as_deref(), Some("s"));
+        assert!(m.history("other").is_empty());
     }
 }
@@ -0,0 +1,98 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 企业微信 5 秒内没有收到响应会重试回调，最多三次，
+/// 在这段时间内记住已经处理过的消息，重复的回调直接丢弃
+pub struct Dedupe {
+    ttl: Duration,
+    capacity: usize,
+    seen: Mutex<Seen>,
+}
+
+#[derive(Default)]
+struct Seen {
+    keys: HashSet<String>,
+    // 按写入顺序排列，用于淘汰过期和超出容量的 key
+    order: VecDeque<(String, Instant)>,
+}
+
+impl Seen {
+    fn pop_front(&mut self) {
+        if let Some((k, _)) = self.order.pop_front() {
+            self.keys.remove(&k);
+        }
+    }
+}
+
+impl Dedupe {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            ttl,
+            capacity: capacity.max(1),
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    /// 第一次出现返回 true，TTL 内重复出现返回 false
+    pub fn first_seen(&self, key: &str) -> bool {
+        self.first_seen_at(key, Instant::now())
+    }
+
+    fn first_seen_at(&self, key: &str, now: Instant) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        while let Some((_, at)) = seen.order.front() {
+            if now.duration_since(*at) < self.ttl {
+                break;
+            }
+            seen.pop_front();
+        }
+        if seen.keys.contains(key) {
+            return false;
+        }
+        while seen.order.len() >= self.capacity {
+            seen.pop_front();
+        }
+        seen.keys.insert(key.to_string());
+        seen.order.push_back((key.to_string(), now));
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Dedupe {
+    fn default() -> Self {
+        Self::new(10000, Duration::from_secs(300))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_first_seen() {
+        let d = Dedupe::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+        assert!(d.first_seen_at("a", now));
+        assert!(!d.first_seen_at("a", now + Duration::from_secs(1)));
+        assert!(d.first_seen_at("b", now + Duration::from_secs(5)));
+        // 过期后重新计算
+        assert!(d.first_seen_at("a", now + Duration::from_secs(11)));
+        assert!(!d.first_seen_at("a", now + Duration::from_secs(12)));
+        assert_eq!(d.len(), 2);
+
+        // 超出容量时淘汰最早的 key
+        assert!(d.first_seen_at("c", now + Duration::from_secs(13)));
+        assert!(d.first_seen_at("b", now + Duration::from_secs(14)));
+        assert!(!d.first_seen_at("c", now + Duration::from_secs(15)));
+        assert_eq!(d.len(), 2);
+    }
+}
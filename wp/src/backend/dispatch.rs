@@ -0,0 +1,39 @@
+use crate::backend::dedupe::Dedupe;
+use crate::backend::handler::Router;
+use crate::backend::metrics::Metrics;
+use crate::backend::mp::callback::CallbackMessage;
+use crate::backend::mp::{PassiveReply, MP};
+use crate::backend::stream::StreamHub;
+use std::sync::Arc;
+use tracing::{info, trace};
+
+/// 处理回调需要的依赖，通过 `Extension` 注入
+#[derive(Clone)]
+pub struct CallbackState {
+    pub mp: Arc<MP>,
+    pub dedupe: Arc<Dedupe>,
+    pub metrics: Arc<Metrics>,
+    pub hub: Arc<StreamHub>,
+    pub router: Arc<Router>,
+}
+
+/// 解密后的回调入口：去重、推送，然后交给处理器
+pub async fn dispatch(state: &CallbackState, msg: CallbackMessage) -> Option<PassiveReply> {
+    trace!("dispatch: msg = {:?}", msg);
+    state.metrics.inc(
+        "wp_callback_received_total",
+        &[("msg_type", msg.msg_type())],
+    );
+    if let Some(key) = msg.dedupe_key() {
+        if !state.dedupe.first_seen(&key) {
+            info!(key, "drop duplicated callback");
+            state.metrics.inc(
+                "wp_callback_duplicated_total",
+                &[("msg_type", msg.msg_type())],
+            );
+            return None;
+        }
+    }
+    state.hub.publish(&msg);
+    state.router.route(&msg, false).await
+}
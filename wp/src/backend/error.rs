@@ -0,0 +1,134 @@
+use crate::backend::mp::callback::CallbackError;
+use crate::backend::mp::WecomError;
+use axum::extract::rejection::{JsonRejection, QueryRejection, StringRejection};
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use thiserror::Error;
+use tracing::warn;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// 所有路由统一的错误，返回与企业微信接口一致的 `{"errcode","errmsg"}`
+#[derive(Debug, Error)]
+pub enum Error {
+    /// 请求格式不正确
+    #[error("{0}")]
+    BadRequest(String),
+    /// 缺少或错误的访问令牌
+    #[error("{0}")]
+    Unauthorized(String),
+    /// 回调请求的签名校验或解密失败
+    #[error(transparent)]
+    Callback(#[from] CallbackError),
+    /// 企业微信返回的错误码，原样返回给调用方
+    #[error(transparent)]
+    Wecom(#[from] WecomError),
+    /// 无法连接企业微信或上游超时
+    #[error("upstream error: {0}")]
+    Upstream(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+// 企业微信 40058 不合法的参数，40014 不合法的 access_token，-1 系统繁忙
+const ERRCODE_INVALID_PARAM: i64 = 40058;
+const ERRCODE_INVALID_TOKEN: i64 = 40014;
+const ERRCODE_SYSTEM_BUSY: i64 = -1;
+
+impl Error {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Callback(CallbackError::Signature) => StatusCode::FORBIDDEN,
+            Error::Callback(_) => StatusCode::BAD_REQUEST,
+            // 企业微信的业务错误使用 200，由调用方判断 errcode
+            Error::Wecom(_) => StatusCode::OK,
+            Error::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    pub fn err_code(&self) -> i64 {
+        match self {
+            Error::BadRequest(_) => ERRCODE_INVALID_PARAM,
+            Error::Unauthorized(_) => ERRCODE_INVALID_TOKEN,
+            Error::Callback(e) => e.err_code(),
+            Error::Wecom(e) => e.err_code,
+            Error::Upstream(_) | Error::Internal(_) => ERRCODE_SYSTEM_BUSY,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(e: anyhow::Error) -> Self {
+        if let Some(we) = e.downcast_ref::<WecomError>() {
+            return Error::Wecom(we.clone());
+        }
+        if e.downcast_ref::<reqwest::Error>().is_some() {
+            return Error::Upstream(format!("{:#}", e));
+        }
+        Error::Internal(format!("{:#}", e))
+    }
+}
+
+// 提取器的错误也按企业微信的格式返回，而不是 axum 默认的纯文本
+macro_rules! bad_request_from {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Error {
+            fn from(e: $t) -> Self {
+                Error::BadRequest(e.body_text())
+            }
+        })*
+    };
+}
+
+bad_request_from!(
+    QueryRejection,
+    JsonRejection,
+    StringRejection,
+    WebSocketUpgradeRejection
+);
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status.is_server_error() {
+            warn!(status = status.as_u16(), "request failed: {}", self);
+        }
+        let err_msg = match &self {
+            Error::Wecom(e) => e.err_msg.clone(),
+            _ => self.to_string(),
+        };
+        (
+            status,
+            Json(json!({"errcode": self.err_code(), "errmsg": err_msg})),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_from_anyhow() {
+        let e: Error =
+            anyhow::Error::from(WecomError::new("发送消息失败", 81013, "invalid")).into();
+        assert_eq!(e.status_code(), StatusCode::OK);
+        assert_eq!(e.err_code(), 81013);
+
+        // 自己序列化或解析上游响应失败不是调用方的错，请求参数错误在提取时处理
+        let e: Error = anyhow::Error::from(serde_json::from_str::<i32>("x").unwrap_err()).into();
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(e.err_code(), ERRCODE_SYSTEM_BUSY);
+
+        let re = reqwest::get("http://127.0.0.1:1").await.unwrap_err();
+        let e: Error = anyhow::Error::from(re).into();
+        assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(e.err_code(), ERRCODE_SYSTEM_BUSY);
+    }
+}
@@ -0,0 +1,436 @@
+use crate::backend::metrics::Metrics;
+use crate::backend::mp::callback::CallbackMessage;
+use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// 签名与时间戳请求头，签名内容为 `{timestamp}.{body}`
+pub const SIGNATURE_HEADER: &str = "X-WP-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-WP-Timestamp";
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ForwardConfig {
+    /// 重试后仍然失败的消息追加写入该文件，每行一个 JSON
+    #[serde(default)]
+    pub dead_letter: Option<String>,
+    #[serde(default)]
+    pub subscribers: Vec<SubscriberConfig>,
+}
+
+/// 下游订阅者，所有匹配条件同时满足才转发，未配置的条件不限制
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubscriberConfig {
+    pub name: String,
+    pub url: String,
+    /// HMAC-SHA256 密钥，为空时不签名
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub msg_types: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub from_users: Vec<String>,
+    /// 仅匹配文本消息的内容
+    #[serde(default)]
+    pub content_regex: Option<String>,
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// 第一次重试前等待的时间，之后每次翻倍
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+fn default_backoff_ms() -> u64 {
+    1000
+}
+fn default_timeout_secs() -> u64 {
+    5
+}
+
+struct Subscriber {
+    conf: SubscriberConfig,
+    content_regex: Option<Regex>,
+}
+
+impl Subscriber {
+    fn matches(&self, msg: &CallbackMessage) -> bool {
+        let c = &self.conf;
+        if !c.msg_types.is_empty() && !c.msg_types.iter().any(|t| t == msg.msg_type()) {
+            return false;
+        }
+        if !c.events.is_empty() {
+            match msg.event() {
+                Some(e) if c.events.iter().any(|x| x == e) => {}
+                _ => return false,
+            }
+        }
+        if !c.from_users.is_empty() {
+            match msg.from_user_name() {
+                Some(u) if c.from_users.iter().any(|x| x == u) => {}
+                _ => return false,
+            }
+        }
+        if let Some(re) = &self.content_regex {
+            match msg {
+                CallbackMessage::Text(m) if re.is_match(&m.content) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+/// 转发给下游的消息体
+#[derive(Serialize, Debug)]
+pub struct ForwardPayload<'a> {
+    pub msg_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<&'a str>,
+    pub received_at: i64,
+    /// 解密后的消息，字段名与企业微信 XML 一致；不支持的消息为原始 XML 字符串
+    pub message: &'a CallbackMessage,
+}
+
+impl<'a> ForwardPayload<'a> {
+    pub fn new(msg: &'a CallbackMessage) -> Self {
+        Self {
+            msg_type: msg.msg_type(),
+            event: msg.event(),
+            received_at: time::OffsetDateTime::now_utc().unix_timestamp(),
+            message: msg,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DeadLetter<'a> {
+    subscriber: &'a str,
+    url: &'a str,
+    attempts: u32,
+    error: String,
+    failed_at: i64,
+    payload: serde_json::Value,
+}
+
+/// 把解密后的回调转发给配置的下游，企业微信每个应用只能配置一个回调地址
+pub struct Forwarder {
+    client: reqwest::Client,
+    subscribers: Vec<Subscriber>,
+    dead_letter: Option<Mutex<String>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Forwarder {
+    pub fn new(conf: &ForwardConfig, metrics: Arc<Metrics>) -> Result<Self> {
+        let subscribers = conf
+            .subscribers
+            .iter()
+            .map(|c| {
+                let content_regex = match &c.content_regex {
+                    Some(r) => Some(
+                        Regex::new(r)
+                            .with_context(|| format!("forward {} content_regex 不合法", c.name))?,
+                    ),
+                    None => None,
+                };
+                Ok(Subscriber {
+                    conf: c.clone(),
+                    content_regex,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            subscribers,
+            dead_letter: conf.dead_letter.clone().map(Mutex::new),
+            metrics,
+        })
+    }
+
+    /// 异步投递给所有匹配的订阅者，不阻塞回调的响应
+    pub fn dispatch(self: &Arc<Self>, msg: &CallbackMessage) {
+        let subs = self
+            .subscribers
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.matches(msg))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if subs.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_string(&ForwardPayload::new(msg)) {
+            Ok(b) => Arc::new(b),
+            Err(e) => {
+                warn!("serialize forward payload failed: {:?}", e);
+                return;
+            }
+        };
+        for i in subs {
+            let f = self.clone();
+            let body = body.clone();
+            tokio::spawn(async move { f.deliver(i, &body).await });
+        }
+    }
+
+    async fn deliver(&self, i: usize, body: &str) {
+        let conf = &self.subscribers[i].conf;
+        let mut attempts = 0;
+        let mut backoff = Duration::from_millis(conf.backoff_ms);
+        let err = loop {
+            attempts += 1;
+            let e = match self.post(conf, body).await {
+                Ok(()) => {
+                    self.metrics.inc(
+                        "wp_forward_total",
+                        &[("subscriber", &conf.name), ("result", "ok")],
+                    );
+                    return;
+                }
+                Err(e) => e,
+            };
+            if attempts > conf.max_retries {
+                break e;
+            }
+            warn!(
+                subscriber = conf.name,
+                attempts, "forward failed, retry: {:?}", e
+            );
+            self.metrics.inc(
+                "wp_forward_total",
+                &[("subscriber", &conf.name), ("result", "retry")],
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        };
+        warn!(
+            subscriber = conf.name,
+            attempts, "forward failed, give up: {:?}", err
+        );
+        self.metrics.inc(
+            "wp_forward_total",
+            &[("subscriber", &conf.name), ("result", "dead_letter")],
+        );
+        if let Err(e) = self.write_dead_letter(conf, attempts, err, body).await {
+            warn!(subscriber = conf.name, "write dead letter failed: {:?}", e);
+        }
+    }
+
+    async fn post(&self, conf: &SubscriberConfig, body: &str) -> Result<()> {
+        let ts = time::OffsetDateTime::now_utc().unix_timestamp().to_string();
+        let mut req = self
+            .client
+            .post(&conf.url)
+            .timeout(Duration::from_secs(conf.timeout_secs))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, &ts);
+        if let Some(secret) = &conf.secret {
+            req = req.header(SIGNATURE_HEADER, sign(secret, &ts, body));
+        }
+        let r = req.body(body.to_string()).send().await?;
+        if !r.status().is_success() {
+            return Err(anyhow!("下游返回 {}", r.status()));
+        }
+        info!(subscriber = conf.name, "forwarded");
+        Ok(())
+    }
+
+    async fn write_dead_letter(
+        &self,
+        conf: &SubscriberConfig,
+        attempts: u32,
+        err: anyhow::Error,
+        body: &str,
+    ) -> Result<()> {
+        let path = match &self.dead_letter {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let mut line = serde_json::to_string(&DeadLetter {
+            subscriber: &conf.name,
+            url: &conf.url,
+            attempts,
+            error: format!("{:#}", err),
+            failed_at: time::OffsetDateTime::now_utc().unix_timestamp(),
+            payload: serde_json::from_str(body)?,
+        })?;
+        line.push('\n');
+        // 持有锁保证多个任务写入的行不会交错
+        let path = path.lock().await;
+        let mut f = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_str())
+            .await?;
+        f.write_all(line.as_bytes()).await?;
+        f.flush().await?;
+        Ok(())
+    }
+}
+
+/// `sha256=` 加上 HMAC-SHA256(secret, "{timestamp}.{body}") 的十六进制
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC 支持任意长度的密钥");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::mp::callback::TextCallbackMessage;
+
+    fn subscriber(name: &str, url: &str) -> SubscriberConfig {
+        SubscriberConfig {
+            name: name.to_string(),
+            url: url.to_string(),
+            secret: None,
+            msg_types: vec![],
+            events: vec![],
+            from_users: vec![],
+            content_regex: None,
+            max_retries: 0,
+            backoff_ms: 0,
+            timeout_secs: 1,
+        }
+    }
+
+    fn text(from: &str, content: &str) -> CallbackMessage {
+        CallbackMessage::Text(TextCallbackMessage {
+            to_user_name: "toUser".to_string(),
+            from_user_name: from.to_string(),
+            create_time: 1348831860,
+            msg_type: "text".to_string(),
+            content: content.to_string(),
+            msg_id: "1234567890123456".to_string(),
+            agent_id: "1".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_matches() -> Result<()> {
+        let mut c = subscriber("alert", "http://127.0.0.1:1");
+        c.msg_types = vec!["text".to_string()];
+        c.from_users = vec!["zhangsan".to_string()];
+        c.content_regex = Some("^告警".to_string());
+        let f = Forwarder::new(
+            &ForwardConfig {
+                dead_letter: None,
+                subscribers: vec![c, subscriber("all", "http://127.0.0.1:1")],
+            },
+            Arc::new(Metrics::default()),
+        )?;
+        let (alert, all) = (&f.subscribers[0], &f.subscribers[1]);
+        assert!(alert.matches(&text("zhangsan", "告警：磁盘已满")));
+        assert!(!alert.matches(&text("zhangsan", "你好")));
+        assert!(!alert.matches(&text("lisi", "告警：磁盘已满")));
+        assert!(!alert.matches(&CallbackMessage::Unknown("<xml/>".to_string())));
+        assert!(all.matches(&CallbackMessage::Unknown("<xml/>".to_string())));
+
+        let mut c = subscriber("bad", "http://127.0.0.1:1");
+        c.content_regex = Some("(".to_string());
+        assert!(Forwarder::new(
+            &ForwardConfig {
+                dead_letter: None,
+                subscribers: vec![c],
+            },
+            Arc::new(Metrics::default()),
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("secret", "1700000000", "{}"),
+            "sha256=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver() -> Result<()> {
+        use axum::{http::HeaderMap, routing::post, Router};
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/hook",
+            post(move |h: HeaderMap, body: String| async move {
+                tx.send((h, body)).unwrap();
+                "ok"
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse()?).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut c = subscriber("hook", &format!("http://{}/hook", addr));
+        c.secret = Some("secret".to_string());
+        let f = Arc::new(Forwarder::new(
+            &ForwardConfig {
+                dead_letter: None,
+                subscribers: vec![c],
+            },
+            Arc::new(Metrics::default()),
+        )?);
+        f.dispatch(&text("zhangsan", "hello"));
+
+        let (h, body) = rx.recv().await.unwrap();
+        let ts = h[TIMESTAMP_HEADER].to_str()?;
+        assert_eq!(h[SIGNATURE_HEADER].to_str()?, sign("secret", ts, &body));
+        let v: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(v["msg_type"], "text");
+        assert_eq!(v["message"]["Content"], "hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "wp_forward_dead_letter_{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut c = subscriber("down", "http://127.0.0.1:1");
+        c.max_retries = 2;
+        let metrics = Arc::new(Metrics::default());
+        let f = Forwarder::new(
+            &ForwardConfig {
+                dead_letter: Some(path.to_string_lossy().to_string()),
+                subscribers: vec![c],
+            },
+            metrics.clone(),
+        )?;
+        f.deliver(0, r#"{"msg_type":"text"}"#).await;
+
+        let dl = std::fs::read_to_string(&path)?;
+        let v: serde_json::Value = serde_json::from_str(dl.trim())?;
+        assert_eq!(v["subscriber"], "down");
+        assert_eq!(v["attempts"], 3);
+        assert_eq!(v["payload"]["msg_type"], "text");
+        assert_eq!(
+            metrics.get(
+                "wp_forward_total",
+                &[("subscriber", "down"), ("result", "retry")]
+            ),
+            2
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
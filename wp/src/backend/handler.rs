@@ -0,0 +1,272 @@
+pub mod approval;
+pub mod auto_reply;
+pub mod card;
+pub mod chat_bot;
+pub mod forward;
+
+pub use approval::{ApprovalConfig, ApprovalWatcher};
+pub use auto_reply::AutoReply;
+pub use card::CardHandler;
+pub use chat_bot::ChatBot;
+pub use forward::ForwardHandler;
+
+use crate::backend::card::TemplateCardHandler;
+use crate::backend::chatglm::GLM;
+use crate::backend::command::chat::{self, ChatState};
+use crate::backend::command::Commands;
+use crate::backend::context::ChatMgr;
+use crate::backend::forward::Forwarder;
+use crate::backend::metrics::Metrics;
+use crate::backend::mp::callback::CallbackMessage;
+use crate::backend::mp::{PassiveReply, MP};
+use crate::backend::rules::RuleSet;
+use crate::backend::Config;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{info, trace};
+
+/// 处理器的结果
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// 交给后续处理器
+    Continue,
+    /// 不再执行后续处理器，可以带上被动回复
+    Stop(Option<PassiveReply>),
+}
+
+/// 解密、去重后的回调按配置依次交给处理器
+#[async_trait]
+pub trait CallbackHandler: Send + Sync {
+    /// 在 `[router]` 配置中引用处理器的名字
+    fn name(&self) -> &'static str;
+    /// 回放录制的回调时是否执行。回放使用模拟的企业微信接口和大模型，
+    /// 只有副作用无法模拟的处理器返回 false
+    fn replayable(&self) -> bool {
+        true
+    }
+    async fn handle(&self, msg: &CallbackMessage) -> Outcome;
+}
+
+/// ```toml
+/// [router]
+/// handlers = ["forward", "approval", "card", "auto_reply", "chat_bot"]
+///
+/// [router.agents]
+/// 1000002 = ["forward"]
+/// ```
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct RouterConfig {
+    /// 默认的处理器链，未配置时按注册顺序使用全部处理器
+    #[serde(default)]
+    pub handlers: Option<Vec<String>>,
+    /// 按 AgentID 指定处理器链
+    #[serde(default)]
+    pub agents: HashMap<String, Vec<String>>,
+}
+
+/// 创建处理器用到的共享状态，回放时替换其中的企业微信接口和大模型
+#[derive(Clone)]
+pub struct Handlers {
+    pub mp: Arc<MP>,
+    pub glm: Arc<GLM>,
+    pub chat_mgr: Arc<Mutex<ChatMgr>>,
+    pub metrics: Arc<Metrics>,
+    pub forwarder: Arc<Forwarder>,
+    pub rules: Arc<RuleSet>,
+    pub card_handler: Arc<dyn TemplateCardHandler>,
+}
+
+impl Handlers {
+    /// 注册全部处理器，按 `[router]` 配置组成处理器链
+    pub fn router(self, conf: &Config) -> Result<Router> {
+        let commands = Commands::new(
+            &conf.commands,
+            chat::commands(ChatState {
+                mp: self.mp.clone(),
+                glm: self.glm.clone(),
+                chat_mgr: self.chat_mgr.clone(),
+            }),
+        )
+        .context("初始化聊天命令失败")?;
+        let handlers: Vec<Arc<dyn CallbackHandler>> = vec![
+            Arc::new(ForwardHandler::new(self.forwarder)),
+            Arc::new(ApprovalWatcher::new(
+                self.mp.clone(),
+                self.metrics.clone(),
+                &conf.approval,
+            )),
+            Arc::new(CardHandler::new(self.mp.clone(), self.card_handler)),
+            Arc::new(AutoReply::new(self.mp.clone(), self.rules, self.metrics)),
+            Arc::new(ChatBot::new(
+                self.mp,
+                self.glm,
+                self.chat_mgr,
+                Arc::new(commands),
+            )),
+        ];
+        Router::new(&conf.router, handlers).context("初始化回调处理器失败")
+    }
+}
+
+type Chain = Vec<Arc<dyn CallbackHandler>>;
+
+pub struct Router {
+    default: Chain,
+    agents: HashMap<String, Chain>,
+}
+
+impl Router {
+    /// 配置中引用了未注册的处理器时返回错误
+    pub fn new(conf: &RouterConfig, handlers: Vec<Arc<dyn CallbackHandler>>) -> Result<Self> {
+        let by_name = handlers
+            .iter()
+            .map(|h| (h.name(), h.clone()))
+            .collect::<HashMap<_, _>>();
+        let chain = |names: &[String]| -> Result<Chain> {
+            names
+                .iter()
+                .map(|n| {
+                    by_name
+                        .get(n.as_str())
+                        .cloned()
+                        .ok_or_else(|| anyhow!("未知的回调处理器 {}", n))
+                })
+                .collect()
+        };
+        let default = match &conf.handlers {
+            Some(names) => chain(names)?,
+            None => handlers.clone(),
+        };
+        let agents = conf
+            .agents
+            .iter()
+            .map(|(agent, names)| Ok((agent.clone(), chain(names)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { default, agents })
+    }
+
+    fn chain(&self, agent_id: Option<&str>) -> &Chain {
+        agent_id
+            .and_then(|a| self.agents.get(a))
+            .unwrap_or(&self.default)
+    }
+
+    /// 依次执行处理器，直到某个处理器返回 `Outcome::Stop`
+    pub async fn route(&self, msg: &CallbackMessage, replay: bool) -> Option<PassiveReply> {
+        for h in self.chain(msg.agent_id()) {
+            if replay && !h.replayable() {
+                continue;
+            }
+            if let Outcome::Stop(reply) = h.handle(msg).await {
+                trace!(handler = h.name(), "callback handled");
+                return reply;
+            }
+        }
+        if let CallbackMessage::Unknown(raw) = msg {
+            info!("unsupported callback message: {}", raw);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::mp::callback::TextCallbackMessage;
+    use std::sync::Mutex;
+
+    struct Recording {
+        name: &'static str,
+        replayable: bool,
+        outcome: fn() -> Outcome,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    #[async_trait]
+    impl CallbackHandler for Recording {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+        fn replayable(&self) -> bool {
+            self.replayable
+        }
+        async fn handle(&self, _msg: &CallbackMessage) -> Outcome {
+            self.calls.lock().unwrap().push(self.name);
+            (self.outcome)()
+        }
+    }
+
+    fn text(agent_id: &str) -> CallbackMessage {
+        CallbackMessage::Text(TextCallbackMessage {
+            to_user_name: "toUser".to_string(),
+            from_user_name: "fromUser".to_string(),
+            create_time: 1348831860,
+            msg_type: "text".to_string(),
+            content: "hi".to_string(),
+            msg_id: "1234567890123456".to_string(),
+            agent_id: agent_id.to_string(),
+        })
+    }
+
+    fn handlers(calls: &Arc<Mutex<Vec<&'static str>>>) -> Vec<Arc<dyn CallbackHandler>> {
+        let h = |name, replayable, outcome| -> Arc<dyn CallbackHandler> {
+            Arc::new(Recording {
+                name,
+                replayable,
+                outcome,
+                calls: calls.clone(),
+            })
+        };
+        vec![
+            h("forward", false, || Outcome::Continue),
+            h("reply", true, || {
+                Outcome::Stop(Some(PassiveReply::text("ok")))
+            }),
+            h("chat_bot", true, || Outcome::Stop(None)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_route() -> Result<()> {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let conf = RouterConfig {
+            handlers: None,
+            agents: HashMap::from([(
+                "2".to_string(),
+                vec!["forward".to_string(), "chat_bot".to_string()],
+            )]),
+        };
+        let router = Router::new(&conf, handlers(&calls))?;
+
+        // 未配置默认链时使用全部处理器，Stop 之后不再执行
+        assert_eq!(
+            router.route(&text("1"), false).await,
+            Some(PassiveReply::text("ok"))
+        );
+        assert_eq!(*calls.lock().unwrap(), vec!["forward", "reply"]);
+
+        calls.lock().unwrap().clear();
+        assert_eq!(router.route(&text("2"), false).await, None);
+        assert_eq!(*calls.lock().unwrap(), vec!["forward", "chat_bot"]);
+
+        // 回放时跳过有副作用的处理器
+        calls.lock().unwrap().clear();
+        router.route(&text("2"), true).await;
+        assert_eq!(*calls.lock().unwrap(), vec!["chat_bot"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_handler() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let conf = RouterConfig {
+            handlers: Some(vec!["missing".to_string()]),
+            agents: Default::default(),
+        };
+        assert!(Router::new(&conf, handlers(&calls)).is_err());
+    }
+}
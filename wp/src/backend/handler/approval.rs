@@ -0,0 +1,149 @@
+use crate::backend::handler::{CallbackHandler, Outcome};
+use crate::backend::metrics::Metrics;
+use crate::backend::mp::callback::{CallbackEvent, CallbackMessage};
+use crate::backend::mp::{Message, MP};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct ApprovalConfig {
+    /// 审批通过、驳回或撤销后通知申请人
+    #[serde(default)]
+    pub notify_applicant: bool,
+}
+
+/// 审批应用和第三方审批的状态变化
+#[derive(Debug, PartialEq)]
+struct Change<'a> {
+    sp_no: &'a str,
+    sp_name: &'a str,
+    status: i32,
+    applicant: &'a str,
+}
+
+impl<'a> Change<'a> {
+    fn from_event(e: &'a CallbackEvent) -> Option<Self> {
+        match e {
+            CallbackEvent::SysApprovalChange(e) => Some(Self {
+                sp_no: &e.approval_info.sp_no,
+                sp_name: &e.approval_info.sp_name,
+                status: e.approval_info.sp_status,
+                applicant: &e.approval_info.applyer.user_id,
+            }),
+            CallbackEvent::OpenApprovalChange(e) => Some(Self {
+                sp_no: &e.approval_info.third_no,
+                sp_name: &e.approval_info.open_sp_name,
+                status: e.approval_info.open_sp_status,
+                applicant: &e.approval_info.apply_user_id,
+            }),
+            _ => None,
+        }
+    }
+
+    fn status_text(&self) -> &'static str {
+        match self.status {
+            1 => "审批中",
+            2 => "已通过",
+            3 => "已驳回",
+            4 => "已撤销",
+            6 => "通过后撤销",
+            7 => "已删除",
+            10 => "已支付",
+            _ => "未知状态",
+        }
+    }
+
+    /// 只在审批结束时通知申请人
+    fn notice(&self) -> Option<String> {
+        match self.status {
+            2 | 3 | 4 | 6 => Some(format!(
+                "你的审批「{}」{}，审批单号 {}",
+                self.sp_name,
+                self.status_text(),
+                self.sp_no
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// 记录审批状态变化，按配置通知申请人，不影响后续处理器
+pub struct ApprovalWatcher {
+    mp: Arc<MP>,
+    metrics: Arc<Metrics>,
+    conf: ApprovalConfig,
+}
+
+impl ApprovalWatcher {
+    pub fn new(mp: Arc<MP>, metrics: Arc<Metrics>, conf: &ApprovalConfig) -> Self {
+        Self {
+            mp,
+            metrics,
+            conf: conf.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl CallbackHandler for ApprovalWatcher {
+    fn name(&self) -> &'static str {
+        "approval"
+    }
+    async fn handle(&self, msg: &CallbackMessage) -> Outcome {
+        let change = match msg {
+            CallbackMessage::Event(e) => match Change::from_event(e) {
+                Some(c) => c,
+                None => return Outcome::Continue,
+            },
+            _ => return Outcome::Continue,
+        };
+        info!(
+            sp_no = change.sp_no,
+            sp_name = change.sp_name,
+            status = change.status,
+            applicant = change.applicant,
+            "approval changed"
+        );
+        self.metrics.inc(
+            "wp_approval_changed_total",
+            &[("status", change.status_text())],
+        );
+        if let (true, Some(notice)) = (self.conf.notify_applicant, change.notice()) {
+            let mp = self.mp.clone();
+            let msg = Message::text(notice).to_users([change.applicant]);
+            let sp_no = change.sp_no.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = mp.send(msg).await {
+                    warn!(sp_no, "notify approval applicant failed: {:?}", e);
+                }
+            });
+        }
+        Outcome::Continue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_notice() {
+        let mut c = Change {
+            sp_no: "202306010001",
+            sp_name: "请假",
+            status: 1,
+            applicant: "zhangsan",
+        };
+        assert_eq!(c.notice(), None);
+        c.status = 3;
+        assert_eq!(
+            c.notice().as_deref(),
+            Some("你的审批「请假」已驳回，审批单号 202306010001")
+        );
+        c.status = 7;
+        assert_eq!(c.status_text(), "已删除");
+        assert_eq!(c.notice(), None);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::backend::handler::{CallbackHandler, Outcome};
+use crate::backend::metrics::Metrics;
+use crate::backend::mp::callback::CallbackMessage;
+use crate::backend::mp::MP;
+use crate::backend::rules::RuleSet;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// 文本消息命中自动回复规则时直接回复，不再交给大模型
+pub struct AutoReply {
+    mp: Arc<MP>,
+    rules: Arc<RuleSet>,
+    metrics: Arc<Metrics>,
+}
+
+impl AutoReply {
+    pub fn new(mp: Arc<MP>, rules: Arc<RuleSet>, metrics: Arc<Metrics>) -> Self {
+        Self { mp, rules, metrics }
+    }
+}
+
+#[async_trait]
+impl CallbackHandler for AutoReply {
+    fn name(&self) -> &'static str {
+        "auto_reply"
+    }
+    async fn handle(&self, msg: &CallbackMessage) -> Outcome {
+        let xml = match msg {
+            CallbackMessage::Text(xml) => xml,
+            _ => return Outcome::Continue,
+        };
+        let m = match self
+            .rules
+            .resolve(&self.mp, &xml.from_user_name, &xml.content)
+            .await
+        {
+            Some(m) => m,
+            None => return Outcome::Continue,
+        };
+        info!(rule = m.name, u = xml.from_user_name, "auto reply");
+        self.metrics
+            .inc("wp_rule_matched_total", &[("rule", &m.name)]);
+        if let Some(r) = m.reply.passive() {
+            return Outcome::Stop(Some(r));
+        }
+        let mp = self.mp.clone();
+        let msg = m.reply.message(&xml.from_user_name);
+        tokio::spawn(async move {
+            if let Err(e) = mp.send(msg).await {
+                warn!(rule = m.name, "send auto reply failed: {:?}", e);
+            }
+        });
+        Outcome::Stop(None)
+    }
+}
@@ -0,0 +1,42 @@
+use crate::backend::card::{self, TemplateCardHandler};
+use crate::backend::handler::{CallbackHandler, Outcome};
+use crate::backend::mp::callback::{CallbackEvent, CallbackMessage};
+use crate::backend::mp::MP;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// 模板卡片按钮被点击后更新卡片
+pub struct CardHandler {
+    mp: Arc<MP>,
+    handler: Arc<dyn TemplateCardHandler>,
+}
+
+impl CardHandler {
+    pub fn new(mp: Arc<MP>, handler: Arc<dyn TemplateCardHandler>) -> Self {
+        Self { mp, handler }
+    }
+}
+
+#[async_trait]
+impl CallbackHandler for CardHandler {
+    fn name(&self) -> &'static str {
+        "card"
+    }
+    /// response_code 只能使用一次，回放时不更新卡片
+    fn replayable(&self) -> bool {
+        false
+    }
+    async fn handle(&self, msg: &CallbackMessage) -> Outcome {
+        match msg {
+            CallbackMessage::Event(CallbackEvent::TemplateCard(e)) => {
+                tokio::spawn(card::handle_event(
+                    self.mp.clone(),
+                    self.handler.clone(),
+                    e.clone(),
+                ));
+                Outcome::Stop(None)
+            }
+            _ => Outcome::Continue,
+        }
+    }
+}
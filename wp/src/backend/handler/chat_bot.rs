@@ -0,0 +1,63 @@
+use crate::backend::chatglm::{ANSWER_TIMEOUT, GLM};
+use crate::backend::command::Commands;
+use crate::backend::context::ChatMgr;
+use crate::backend::handler::{CallbackHandler, Outcome};
+use crate::backend::mp::callback::CallbackMessage;
+use crate::backend::mp::{PassiveReply, MP};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// 先处理斜杠命令，其他文本消息交给大模型回答
+pub struct ChatBot {
+    mp: Arc<MP>,
+    glm: Arc<GLM>,
+    chat_mgr: Arc<Mutex<ChatMgr>>,
+    commands: Arc<Commands>,
+}
+
+impl ChatBot {
+    pub fn new(
+        mp: Arc<MP>,
+        glm: Arc<GLM>,
+        chat_mgr: Arc<Mutex<ChatMgr>>,
+        commands: Arc<Commands>,
+    ) -> Self {
+        Self {
+            mp,
+            glm,
+            chat_mgr,
+            commands,
+        }
+    }
+}
+
+#[async_trait]
+impl CallbackHandler for ChatBot {
+    fn name(&self) -> &'static str {
+        "chat_bot"
+    }
+    async fn handle(&self, msg: &CallbackMessage) -> Outcome {
+        let xml = match msg {
+            CallbackMessage::Text(xml) => xml,
+            _ => return Outcome::Continue,
+        };
+        if let Some(reply) = self
+            .commands
+            .dispatch(&xml.from_user_name, &xml.content)
+            .await
+        {
+            return Outcome::Stop(Some(PassiveReply::text(reply)));
+        }
+        self.glm
+            .async_chat(
+                &xml.from_user_name,
+                &xml.content,
+                self.chat_mgr.clone(),
+                self.mp.clone(),
+                Some(ANSWER_TIMEOUT),
+            )
+            .await;
+        Outcome::Stop(None)
+    }
+}
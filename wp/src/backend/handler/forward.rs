@@ -0,0 +1,30 @@
+use crate::backend::forward::Forwarder;
+use crate::backend::handler::{CallbackHandler, Outcome};
+use crate::backend::mp::callback::CallbackMessage;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// 把回调转发给订阅的下游 webhook，不影响后续处理器
+pub struct ForwardHandler {
+    forwarder: Arc<Forwarder>,
+}
+
+impl ForwardHandler {
+    pub fn new(forwarder: Arc<Forwarder>) -> Self {
+        Self { forwarder }
+    }
+}
+
+#[async_trait]
+impl CallbackHandler for ForwardHandler {
+    fn name(&self) -> &'static str {
+        "forward"
+    }
+    fn replayable(&self) -> bool {
+        false
+    }
+    async fn handle(&self, msg: &CallbackMessage) -> Outcome {
+        self.forwarder.dispatch(msg);
+        Outcome::Continue
+    }
+}
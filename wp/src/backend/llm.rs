@@ -0,0 +1,168 @@
+mod mock;
+mod ollama;
+mod openai;
+
+pub use mock::Mock;
+pub use ollama::Ollama;
+pub use openai::OpenAi;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+/// OpenAI 和 Ollama 的 messages 格式相同
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+/// 大模型接口，传入完整的多轮对话，返回回答
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// 用于日志
+    fn name(&self) -> &'static str;
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<String>;
+    /// 流式请求，每收到一段回答就发送到 `tx`，最后返回完整的回答。
+    /// 不支持流式输出的接口一次性发送全部回答
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        tx: UnboundedSender<String>,
+    ) -> Result<String> {
+        let r = self.chat(messages).await?;
+        let _ = tx.send(r.clone());
+        Ok(r)
+    }
+}
+
+/// ```toml
+/// [llm]
+/// type = "openai"
+/// api_base = "https://api.openai.com/v1"
+/// api_key = "sk-..."
+/// model = "gpt-3.5-turbo"
+/// temperature = 0.7
+/// ```
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LlmConfig {
+    /// 兼容 OpenAI `/chat/completions` 的接口
+    Openai {
+        api_base: String,
+        #[serde(default)]
+        api_key: Option<String>,
+        model: String,
+        #[serde(default)]
+        temperature: Option<f32>,
+        #[serde(default)]
+        max_tokens: Option<u32>,
+    },
+    /// Ollama 的 `/api/chat`
+    Ollama {
+        #[serde(default = "default_ollama_api_base")]
+        api_base: String,
+        model: String,
+        #[serde(default)]
+        temperature: Option<f32>,
+    },
+    /// 固定回答，未配置时复述最后一个问题，用于测试
+    Mock {
+        #[serde(default)]
+        reply: Option<String>,
+    },
+}
+
+fn default_ollama_api_base() -> String {
+    "http://127.0.0.1:11434".to_string()
+}
+
+impl LlmConfig {
+    /// 未配置 `[llm]` 时沿用 glm_api，按 OpenAI 兼容接口调用 ChatGLM
+    pub fn chatglm(api_base: &str) -> Self {
+        LlmConfig::Openai {
+            api_base: api_base.to_string(),
+            api_key: None,
+            model: "chatglm2-6b".to_string(),
+            temperature: None,
+            max_tokens: None,
+        }
+    }
+
+    pub fn build(&self) -> Arc<dyn LlmBackend> {
+        match self {
+            LlmConfig::Openai {
+                api_base,
+                api_key,
+                model,
+                temperature,
+                max_tokens,
+            } => Arc::new(OpenAi::new(
+                api_base,
+                api_key.as_deref(),
+                model,
+                *temperature,
+                *max_tokens,
+            )),
+            LlmConfig::Ollama {
+                api_base,
+                model,
+                temperature,
+            } => Arc::new(Ollama::new(api_base, model, *temperature)),
+            LlmConfig::Mock { reply } => Arc::new(Mock::new(reply.clone())),
+        }
+    }
+}
+
+/// 非 2xx 时把响应体带到错误信息中
+async fn check_status(name: &str, r: reqwest::Response) -> Result<reqwest::Response> {
+    let status = r.status();
+    if status.is_success() {
+        return Ok(r);
+    }
+    let body = r.text().await.unwrap_or_default();
+    Err(anyhow!("{} 返回 {}: {}", name, status, body))
+}
+
+/// 逐行读取流式响应（SSE 或 NDJSON），`f` 返回 false 时停止读取
+async fn read_lines(
+    mut r: reqwest::Response,
+    mut f: impl FnMut(&str) -> Result<bool>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = r.chunk().await? {
+        buf.extend_from_slice(&chunk);
+        // 按字节找换行，避免把一个 UTF-8 字符拆到两个 chunk 里解码
+        while let Some(i) = buf.iter().position(|b| *b == b'\n') {
+            let line = buf.drain(..=i).collect::<Vec<_>>();
+            let line = std::str::from_utf8(&line)?.trim();
+            if !line.is_empty() && !f(line)? {
+                return Ok(());
+            }
+        }
+    }
+    let line = std::str::from_utf8(&buf)?.trim();
+    if !line.is_empty() {
+        f(line)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use crate::backend::llm::{ChatMessage, LlmBackend, Role};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// 不请求任何接口，结果只取决于输入
+pub struct Mock {
+    reply: Option<String>,
+}
+
+impl Mock {
+    pub fn new(reply: Option<String>) -> Self {
+        Self { reply }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for Mock {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
+        if let Some(r) = &self.reply {
+            return Ok(r.clone());
+        }
+        messages
+            .iter()
+            .rev()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.clone())
+            .ok_or_else(|| anyhow!("没有问题"))
+    }
+    /// 按行发送，模拟流式输出
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        tx: UnboundedSender<String>,
+    ) -> Result<String> {
+        let r = self.chat(messages).await?;
+        for line in r.split_inclusive('\n') {
+            let _ = tx.send(line.to_string());
+        }
+        Ok(r)
+    }
+}
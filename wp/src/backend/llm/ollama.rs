@@ -0,0 +1,155 @@
+use crate::backend::llm::{check_status, read_lines, ChatMessage, LlmBackend};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+// https://github.com/ollama/ollama/blob/main/docs/api.md#generate-a-chat-completion
+pub struct Ollama {
+    client: reqwest::Client,
+    api: String,
+    model: String,
+    temperature: Option<f32>,
+}
+
+impl Ollama {
+    pub fn new(api_base: &str, model: &str, temperature: Option<f32>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api: format!("{}/api/chat", api_base.trim_end_matches('/')),
+            model: model.to_string(),
+            temperature,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct ChatReq<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    stream: bool,
+    options: Options,
+}
+
+/// 流式请求时每行一个 ChatResp，最后一行的 done 为 true
+#[derive(Deserialize)]
+struct ChatResp {
+    message: ChatMessage,
+    #[serde(default)]
+    done: bool,
+}
+
+impl Ollama {
+    async fn request(&self, messages: &[ChatMessage], stream: bool) -> Result<reqwest::Response> {
+        let req = self.client.post(&self.api).json(&ChatReq {
+            model: &self.model,
+            messages,
+            stream,
+            options: Options {
+                temperature: self.temperature,
+            },
+        });
+        check_status(self.name(), req.send().await?).await
+    }
+}
+
+#[async_trait]
+impl LlmBackend for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
+        let r = self
+            .request(messages, false)
+            .await?
+            .json::<ChatResp>()
+            .await?;
+        Ok(r.message.content)
+    }
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        tx: UnboundedSender<String>,
+    ) -> Result<String> {
+        let r = self.request(messages, true).await?;
+        let mut answer = String::new();
+        read_lines(r, |line| {
+            let r = serde_json::from_str::<ChatResp>(line)?;
+            if !r.message.content.is_empty() {
+                answer.push_str(&r.message.content);
+                let _ = tx.send(r.message.content);
+            }
+            Ok(!r.done)
+        })
+        .await?;
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::llm::Role;
+    use axum::{routing::post, Json, Router};
+
+    #[tokio::test]
+    async fn test_chat() -> Result<()> {
+        let app = Router::new().route(
+            "/api/chat",
+            post(|Json(v): Json<serde_json::Value>| async move {
+                assert_eq!(v["model"], "qwen");
+                assert_eq!(v["stream"], false);
+                Json(serde_json::json!({
+                    "model": "qwen",
+                    "message": {"role": "assistant", "content": v["messages"][0]["content"]},
+                    "done": true
+                }))
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse()?).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let llm = Ollama::new(&format!("http://{}", addr), "qwen", None);
+        assert_eq!(
+            llm.chat(&[ChatMessage::new(Role::User, "你好")]).await?,
+            "你好"
+        );
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_chat_stream() -> Result<()> {
+        let app = Router::new().route(
+            "/api/chat",
+            post(|Json(v): Json<serde_json::Value>| async move {
+                assert_eq!(v["stream"], true);
+                [
+                    r#"{"message":{"role":"assistant","content":"你"},"done":false}"#,
+                    r#"{"message":{"role":"assistant","content":"好"},"done":false}"#,
+                    r#"{"message":{"role":"assistant","content":""},"done":true}"#,
+                ]
+                .join("\n")
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse()?).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let llm = Ollama::new(&format!("http://{}", addr), "qwen", None);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let answer = llm
+            .chat_stream(&[ChatMessage::new(Role::User, "hi")], tx)
+            .await?;
+        assert_eq!(answer, "你好");
+        assert_eq!(rx.recv().await.as_deref(), Some("你"));
+        assert_eq!(rx.recv().await.as_deref(), Some("好"));
+        assert_eq!(rx.recv().await, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use crate::backend::llm::{check_status, read_lines, ChatMessage, LlmBackend};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+pub struct OpenAi {
+    client: reqwest::Client,
+    api: String,
+    api_key: Option<String>,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+}
+
+impl OpenAi {
+    pub fn new(
+        api_base: &str,
+        api_key: Option<&str>,
+        model: &str,
+        temperature: Option<f32>,
+        max_tokens: Option<u32>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api: format!("{}/chat/completions", api_base.trim_end_matches('/')),
+            api_key: api_key.map(|k| k.to_string()),
+            model: model.to_string(),
+            temperature,
+            max_tokens,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ChatReq<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct ChatResp {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ChatMessage,
+}
+
+/// 流式响应中每个 `data:` 事件
+#[derive(Deserialize)]
+struct StreamChunk {
+    choices: Vec<StreamChoice>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+#[derive(Deserialize, Default)]
+struct Delta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+impl OpenAi {
+    async fn request(&self, messages: &[ChatMessage], stream: bool) -> Result<reqwest::Response> {
+        let mut req = self.client.post(&self.api).json(&ChatReq {
+            model: &self.model,
+            messages,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            stream,
+        });
+        if let Some(key) = &self.api_key {
+            req = req.bearer_auth(key);
+        }
+        check_status(self.name(), req.send().await?).await
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OpenAi {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+    async fn chat(&self, messages: &[ChatMessage]) -> Result<String> {
+        let r = self
+            .request(messages, false)
+            .await?
+            .json::<ChatResp>()
+            .await?;
+        r.choices
+            .into_iter()
+            .next()
+            .map(|c| c.message.content)
+            .ok_or_else(|| anyhow!("openai 没有返回回答"))
+    }
+    async fn chat_stream(
+        &self,
+        messages: &[ChatMessage],
+        tx: UnboundedSender<String>,
+    ) -> Result<String> {
+        let r = self.request(messages, true).await?;
+        let mut answer = String::new();
+        read_lines(r, |line| {
+            // SSE 中的注释和其他字段都忽略
+            let data = match line.strip_prefix("data:") {
+                Some(d) => d.trim(),
+                None => return Ok(true),
+            };
+            if data == "[DONE]" {
+                return Ok(false);
+            }
+            let chunk = serde_json::from_str::<StreamChunk>(data)?;
+            if let Some(c) = chunk
+                .choices
+                .into_iter()
+                .next()
+                .and_then(|c| c.delta.content)
+            {
+                answer.push_str(&c);
+                let _ = tx.send(c);
+            }
+            Ok(true)
+        })
+        .await?;
+        Ok(answer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::llm::Role;
+    use axum::http::HeaderMap;
+    use axum::{routing::post, Json, Router};
+
+    #[tokio::test]
+    async fn test_chat() -> Result<()> {
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(|h: HeaderMap, Json(v): Json<serde_json::Value>| async move {
+                assert_eq!(h["authorization"], "Bearer sk-test");
+                assert_eq!(v["model"], "gpt-test");
+                assert_eq!(v["temperature"], 0.5);
+                assert_eq!(v["messages"][1]["role"], "assistant");
+                let n = v["messages"].as_array().unwrap().len();
+                Json(serde_json::json!({
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": format!("{} messages", n)}}]
+                }))
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse()?).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let llm = OpenAi::new(
+            &format!("http://{}/v1/", addr),
+            Some("sk-test"),
+            "gpt-test",
+            Some(0.5),
+            None,
+        );
+        let messages = [
+            ChatMessage::new(Role::User, "q1"),
+            ChatMessage::new(Role::Assistant, "a1"),
+            ChatMessage::new(Role::User, "q2"),
+        ];
+        assert_eq!(llm.chat(&messages).await?, "3 messages");
+
+        let llm = OpenAi::new(&format!("http://{}/missing", addr), None, "m", None, None);
+        let e = llm.chat(&messages).await.unwrap_err();
+        assert!(e.to_string().contains("404"), "{}", e);
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_chat_stream() -> Result<()> {
+        let app = Router::new().route(
+            "/chat/completions",
+            post(|Json(v): Json<serde_json::Value>| async move {
+                assert_eq!(v["stream"], true);
+                [
+                    r#"data: {"choices":[{"index":0,"delta":{"role":"assistant"}}]}"#,
+                    r#"data: {"choices":[{"index":0,"delta":{"content":"你"}}]}"#,
+                    ": keep-alive",
+                    r#"data: {"choices":[{"index":0,"delta":{"content":"好\n\n"}}]}"#,
+                    "data: [DONE]",
+                    r#"data: {"choices":[{"index":0,"delta":{"content":"ignored"}}]}"#,
+                ]
+                .join("\n\n")
+            }),
+        );
+        let server = axum::Server::bind(&"127.0.0.1:0".parse()?).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let llm = OpenAi::new(&format!("http://{}", addr), None, "m", None, None);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let answer = llm
+            .chat_stream(&[ChatMessage::new(Role::User, "hi")], tx)
+            .await?;
+        assert_eq!(answer, "你好\n\n");
+        assert_eq!(rx.recv().await.as_deref(), Some("你"));
+        assert_eq!(rx.recv().await.as_deref(), Some("好\n\n"));
+        assert_eq!(rx.recv().await, None);
+        Ok(())
+    }
+}
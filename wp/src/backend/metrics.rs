@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// 进程内的计数器，以 Prometheus 文本格式从 `/metrics` 导出
+#[derive(Default)]
+pub struct Metrics {
+    // name -> labels -> value
+    counters: Mutex<BTreeMap<&'static str, BTreeMap<String, u64>>>,
+}
+
+impl Metrics {
+    pub fn inc(&self, name: &'static str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1)
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&str, &str)], v: u64) {
+        let mut c = self.counters.lock().unwrap();
+        *c.entry(name)
+            .or_default()
+            .entry(format_labels(labels))
+            .or_default() += v;
+    }
+
+    pub fn get(&self, name: &str, labels: &[(&str, &str)]) -> u64 {
+        let c = self.counters.lock().unwrap();
+        c.get(name)
+            .and_then(|m| m.get(&format_labels(labels)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn render(&self) -> String {
+        let c = self.counters.lock().unwrap();
+        let mut s = String::new();
+        for (name, series) in c.iter() {
+            let _ = writeln!(s, "# TYPE {} counter", name);
+            for (labels, v) in series {
+                let _ = writeln!(s, "{}{} {}", name, labels, v);
+            }
+        }
+        s
+    }
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let l = labels
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "{}=\"{}\"",
+                k,
+                v.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", l.join(","))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let m = Metrics::default();
+        m.inc("wp_callback_total", &[("msg_type", "text")]);
+        m.inc("wp_callback_total", &[("msg_type", "text")]);
+        m.inc("wp_callback_duplicated_total", &[]);
+        assert_eq!(m.get("wp_callback_total", &[("msg_type", "text")]), 2);
+        assert_eq!(m.get("wp_callback_total", &[("msg_type", "event")]), 0);
+        assert_eq!(
+            m.render(),
+            "# TYPE wp_callback_duplicated_total counter\n\
+             wp_callback_duplicated_total 1\n\
+             # TYPE wp_callback_total counter\n\
+             wp_callback_total{msg_type=\"text\"} 2\n"
+        );
+    }
+}
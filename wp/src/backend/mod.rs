@@ -1,10 +1,37 @@
+pub mod answer;
 pub mod api;
+pub mod auth;
+pub mod card;
 pub mod chatglm;
+pub mod command;
 pub mod context;
+pub mod dedupe;
+pub mod dispatch;
+pub mod error;
+pub mod forward;
+pub mod handler;
+pub mod llm;
+pub mod metrics;
 pub mod mp;
+pub mod recorder;
+pub mod render;
+pub mod rules;
+pub mod stream;
 pub mod xx;
 
+use crate::backend::answer::AnswerConfig;
+use crate::backend::card::CardConfig;
+use crate::backend::chatglm::ProgressConfig;
+use crate::backend::command::CommandsConfig;
+use crate::backend::context::ChatConfig;
+use crate::backend::forward::ForwardConfig;
+use crate::backend::handler::{ApprovalConfig, RouterConfig};
+use crate::backend::llm::LlmConfig;
+use crate::backend::recorder::RecorderConfig;
+use crate::backend::rules::RulesConfig;
+use crate::backend::stream::StreamConfig;
 use serde::Deserialize;
+use std::collections::HashMap;
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub corp_id: String,
@@ -12,5 +39,47 @@ pub struct Config {
     pub agent_id: i64,
     pub encoded_aes_key: String,
     pub token: String,
+    /// 兼容 OpenAI 接口的 ChatGLM 地址，配置了 `[llm]` 时不使用
+    #[serde(default)]
     pub glm_api: String,
+    /// 大模型接口，未配置时使用 glm_api
+    #[serde(default)]
+    pub llm: Option<LlmConfig>,
+    /// 成员可以通过 `/model` 切换的其他模型
+    #[serde(default)]
+    pub models: HashMap<String, LlmConfig>,
+    /// 多轮对话的上下文
+    #[serde(default)]
+    pub chat: ChatConfig,
+    /// 企业微信接口地址，默认 https://qyapi.weixin.qq.com
+    #[serde(default)]
+    pub api_base: Option<String>,
+    #[serde(default)]
+    pub card: CardConfig,
+    /// 把回调转发给下游 webhook
+    #[serde(default)]
+    pub forward: ForwardConfig,
+    /// 通过 SSE / WebSocket 推送回调
+    #[serde(default)]
+    pub stream: StreamConfig,
+    /// 录制原始回调请求，用于回放排查问题
+    #[serde(default)]
+    pub recorder: RecorderConfig,
+    /// 关键词自动回复，在调用大模型之前匹配
+    #[serde(default)]
+    pub rules: RulesConfig,
+    /// 按 AgentID 选择回调处理器
+    #[serde(default)]
+    pub router: RouterConfig,
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+    /// 较长的回答发布为网页
+    #[serde(default)]
+    pub answer: AnswerConfig,
+    /// 聊天机器人的斜杠命令
+    #[serde(default)]
+    pub commands: CommandsConfig,
+    /// 等待回答期间的进度提醒
+    #[serde(default)]
+    pub progress: ProgressConfig,
 }
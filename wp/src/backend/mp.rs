@@ -1,15 +1,47 @@
 pub mod callback;
+pub mod capture;
 mod client;
 mod media;
-mod msg;
+pub mod msg;
+pub mod reply;
+pub mod split;
+pub mod template_card;
+#[cfg(test)]
+pub mod test_util;
+mod user;
+
+pub use msg::{NewsArticle, SendMsgReq as Message, SendResult};
+pub use reply::PassiveReply;
+pub use template_card::{CardType, CardUpdate, TemplateCard};
 
-use crate::backend::mp::callback::CallbackMessage;
+use crate::backend::mp::callback::{CallbackError, CallbackMessage};
 use anyhow::{anyhow, Result};
 use axum::body::Bytes;
 use http::{HeaderMap, StatusCode};
 use tokio::sync::RwLock;
 use tracing::{debug, info, trace};
-use wechat_crypto::{decode_aes_key, verify_url, VerifyInfo};
+use wechat_crypto::{decode_aes_key, VerifyInfo};
+
+/// 企业微信接口返回的非 0 错误码，可以从 `anyhow::Error` 中 downcast 出来原样返回给调用方
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{action} error: [{err_code}] {err_msg}")]
+pub struct WecomError {
+    pub action: &'static str,
+    pub err_code: i64,
+    pub err_msg: String,
+}
+
+impl WecomError {
+    pub fn new(action: &'static str, err_code: impl Into<i64>, err_msg: &str) -> Self {
+        Self {
+            action,
+            err_code: err_code.into(),
+            err_msg: err_msg.to_string(),
+        }
+    }
+}
+
+pub const DEFAULT_API_BASE: &str = "https://qyapi.weixin.qq.com";
 
 struct Token {
     content: String,
@@ -23,6 +55,7 @@ pub struct MP {
     client: reqwest::Client,
     aek_key: Vec<u8>,
     token: String,
+    api_base: String,
 }
 
 impl MP {
@@ -45,12 +78,18 @@ impl MP {
             client: reqwest::Client::new(),
             aek_key,
             token: token.to_string(),
+            api_base: DEFAULT_API_BASE.to_string(),
         }
     }
+    /// 替换企业微信接口地址，用于反向代理或测试
+    pub fn with_api_base(mut self, api_base: &str) -> Self {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+        self
+    }
     async fn refresh_token(&self) -> Result<()> {
         info!("refresh_token");
         let (access_token, expires_in) =
-            client::get_access_token(&self.corp_id, &self.corp_secret).await?;
+            client::get_access_token(&self.api_base, &self.corp_id, &self.corp_secret).await?;
         let mut w = self.access_token.write().await;
         w.content = access_token;
         w.expires_after =
@@ -68,14 +107,83 @@ impl MP {
         Ok(r.content.clone())
     }
 
-    pub async fn proxy_message_send(&self, msg: &str) -> Result<String> {
+    /// 企业微信返回的错误码原样保留在结果中
+    pub async fn proxy_message_send(&self, req: Message) -> Result<SendResult> {
+        let token = self.get_token().await?;
+        msg::send_raw(&self.client, &self.api_base, &token, self.agent_id, req).await
+    }
+    pub async fn send(&self, msg: Message) -> Result<SendResult> {
+        let token = self.get_token().await?;
+        msg::send(&self.client, &self.api_base, &token, self.agent_id, msg).await
+    }
+    /// 超出长度限制的消息拆成多条依次发送，遇到失败时停止
+    pub async fn send_split(&self, msg: Message) -> Result<Vec<SendResult>> {
+        let mut r = vec![];
+        for m in msg.split() {
+            r.push(self.send(m).await?);
+        }
+        Ok(r)
+    }
+    /// 上传临时素材，`media_type` 为 image / voice / video / file，返回 media_id
+    pub async fn upload_media(
+        &self,
+        media_type: &str,
+        file_name: &str,
+        b: &[u8],
+    ) -> Result<String> {
+        let token = self.get_token().await?;
+        media::media_upload(
+            &self.client,
+            &self.api_base,
+            media_type,
+            &token,
+            file_name,
+            b,
+        )
+        .await
+    }
+    pub async fn update_template_card(
+        &self,
+        response_code: &str,
+        update: &CardUpdate,
+    ) -> Result<()> {
+        let token = self.get_token().await?;
+        msg::update_template_card(
+            &self.client,
+            &self.api_base,
+            &token,
+            self.agent_id,
+            response_code,
+            update,
+        )
+        .await
+    }
+    /// 成员所属的部门 id
+    pub async fn user_departments(&self, user_id: &str) -> Result<Vec<i64>> {
+        let token = self.get_token().await?;
+        user::get_user_departments(&self.client, &self.api_base, &token, user_id).await
+    }
+    /// 网页授权地址，成员同意后带着 code 和 state 跳转到 `redirect_uri`
+    // https://developer.work.weixin.qq.com/document/path/91022
+    pub fn oauth_url(&self, redirect_uri: &str, state: &str) -> String {
+        let encode =
+            |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+        format!(
+            "https://open.weixin.qq.com/connect/oauth2/authorize?appid={}&redirect_uri={}&response_type=code&scope=snsapi_base&state={}&agentid={}#wechat_redirect",
+            self.corp_id,
+            encode(redirect_uri),
+            encode(state),
+            self.agent_id
+        )
+    }
+    /// 用网页授权的 code 换取成员 userid
+    pub async fn oauth_user(&self, code: &str) -> Result<String> {
         let token = self.get_token().await?;
-        let msg_id = msg::send_msg(&self.client, &token, self.agent_id, msg).await?;
-        Ok(msg_id)
+        user::get_oauth_user(&self.client, &self.api_base, &token, code).await
     }
     pub async fn message_recall(&self, msg_id: &str) -> Result<()> {
         let token = self.get_token().await?;
-        msg::recall_msg(&self.client, &token, msg_id).await?;
+        msg::recall_msg(&self.client, &self.api_base, &token, msg_id).await?;
         Ok(())
     }
     pub async fn proxy(
@@ -98,7 +206,7 @@ impl MP {
         }
 
         // u.query_pairs()
-        let u = rebuild_url(uri, &token).await?;
+        let u = rebuild_url(uri, &self.api_base, &token).await?;
         trace!("proxy url: {}", u);
         trace!("proxy headers: {:?}", h);
         let r = self.client.post(u).headers(h).body(b).send().await?;
@@ -112,26 +220,41 @@ impl MP {
 
 // 服务器回复消息
 impl MP {
-    pub fn verify_url(&self, q: &VerifyInfo, echo_str: &str) -> Result<String> {
-        verify_url(
-            self.token.as_str(),
-            q,
-            echo_str,
+    pub fn verify_url(&self, q: &VerifyInfo, echo_str: &str) -> Result<String, CallbackError> {
+        callback::decrypt_echo_str(&self.aek_key, &self.corp_id, &self.token, q, echo_str)
+    }
+    pub fn handle_msg(&self, q: &VerifyInfo, b: &str) -> Result<CallbackMessage, CallbackError> {
+        callback::decrypt_message(&self.aek_key, &self.corp_id, &self.token, q, b)
+    }
+    /// 把被动回复加密为 `/wccb` 的响应包，`to_user` 为回调消息的 FromUserName
+    pub fn encrypt_reply(&self, reply: &PassiveReply, to_user: &str, nonce: i64) -> Result<String> {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        let plaintext = reply.to_xml(to_user, &self.corp_id, now);
+        reply::encrypt_reply(
             &self.aek_key,
             &self.corp_id,
+            &self.token,
+            &plaintext,
+            now,
+            nonce,
         )
     }
-    pub fn handle_msg(&self, q: &VerifyInfo, b: &str) -> Result<CallbackMessage> {
-        let msg = callback::decrypt_message(&self.aek_key, &self.corp_id, &self.token, q, b)?;
-        Ok(msg)
+    /// 跳过签名校验，仅用于回放录制的回调
+    pub fn handle_msg_unverified(&self, b: &str) -> Result<CallbackMessage> {
+        Ok(callback::decrypt_message_unverified(
+            &self.aek_key,
+            &self.corp_id,
+            b,
+        )?)
     }
 }
 
-async fn rebuild_url(uri: &str, token: &str) -> Result<String> {
+async fn rebuild_url(uri: &str, api_base: &str, token: &str) -> Result<String> {
+    let base = url::Url::parse(api_base)?;
     let mut u = url::Url::parse(uri)?;
-    u.set_host(Some("qyapi.weixin.qq.com"))?;
-    u.set_scheme("https").map_err(|_| anyhow!(""))?;
-    u.set_port(None).map_err(|_| anyhow!(""))?;
+    u.set_host(base.host_str())?;
+    u.set_scheme(base.scheme()).map_err(|_| anyhow!(""))?;
+    u.set_port(base.port()).map_err(|_| anyhow!(""))?;
     let q = u.query().unwrap_or("");
     let qs = qstring::QString::from(q);
     let mut nq = qstring::QString::new(vec![("access_token", token)]);
@@ -157,6 +280,7 @@ mod test {
         let r = dbg!(
             rebuild_url(
                 "http://127.0.0.1:3000/cgi-bin/media/upload?access_token=ACCESS_TOKEN&type=image",
+                DEFAULT_API_BASE,
                 "666"
             )
             .await?
@@ -166,6 +290,17 @@ mod test {
             "https://qyapi.weixin.qq.com/cgi-bin/media/upload?access_token=666&type=image"
         );
 
+        let r = rebuild_url(
+            "https://123/cgi-bin/media/upload?type=image",
+            "http://127.0.0.1:8080",
+            "666",
+        )
+        .await?;
+        assert_eq!(
+            r,
+            "http://127.0.0.1:8080/cgi-bin/media/upload?access_token=666&type=image"
+        );
+
         // dbg!(rebuild_url("/cgi-bin/media/upload?", "666").await?);
         Ok(())
     }
@@ -209,7 +344,7 @@ mod test {
             &serv_conf.encoded_aes_key,
             &serv_conf.token,
         );
-        let msg_id = dbg!(mp.proxy_message_send(msg).await?);
+        let msg_id = dbg!(mp.proxy_message_send(serde_json::from_str(msg)?).await?).msg_id;
 
         dbg!(mp.message_recall(&msg_id).await?);
         Ok(())
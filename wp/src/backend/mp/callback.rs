@@ -1,6 +1,13 @@
-use anyhow::{anyhow, Result};
+pub mod event;
+
+pub use event::{CallbackEvent, TemplateCardEventMessage};
+
+use anyhow::Result;
 use base64::Engine;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
 use wechat_crypto::{calc_signature, decrypt, parse_plain_text, VerifyInfo};
 
 pub fn check_sign(token: &str, q: &VerifyInfo, data: &str) -> bool {
@@ -17,51 +24,151 @@ pub fn check_sign(token: &str, q: &VerifyInfo, data: &str) -> bool {
 //    <AgentID><![CDATA[toAgentID]]></AgentID>
 //    <Encrypt><![CDATA[msg_encrypt]]></Encrypt>
 // </xml>
+// 只需要密文，其他字段以解密后的为准
 #[derive(Deserialize, Debug)]
 struct EncryptedXML {
-    #[serde(rename = "ToUserName")]
-    to_user_name: String,
-    #[serde(rename = "AgentID")]
-    agent_id: String,
     #[serde(rename = "Encrypt")]
     encrypted_msg: String,
 }
+/// 回调请求校验或解密失败的原因，`/wccb` 据此返回 4xx 并按类型计数
+#[derive(Debug, Error)]
+pub enum CallbackError {
+    #[error("请求体不是合法的加密 XML: {0}")]
+    Malformed(String),
+    #[error("签名不正确")]
+    Signature,
+    #[error("解密失败: {0}")]
+    Decrypt(String),
+    #[error("receiver_id={0} 与服务端配置不一致")]
+    ReceiverId(String),
+}
+
+impl CallbackError {
+    /// 用于日志和指标的标签
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CallbackError::Malformed(_) => "malformed",
+            CallbackError::Signature => "signature",
+            CallbackError::Decrypt(_) => "decrypt",
+            CallbackError::ReceiverId(_) => "receiver_id",
+        }
+    }
+
+    /// 企业微信加解密库约定的错误码
+    // https://developer.work.weixin.qq.com/document/path/90930
+    pub fn err_code(&self) -> i64 {
+        match self {
+            CallbackError::Signature => -40001,
+            CallbackError::Malformed(_) => -40002,
+            CallbackError::ReceiverId(_) => -40005,
+            CallbackError::Decrypt(_) => -40007,
+        }
+    }
+}
+
 pub fn decrypt_message(
     key: &[u8],
     receiver_id: &str,
     token: &str,
     verify_info: &VerifyInfo,
     xml: &str,
-) -> Result<CallbackMessage> {
-    let encrypted_msg = quick_xml::de::from_str::<EncryptedXML>(xml)?.encrypted_msg;
-    let sign = calc_signature(
-        token,
-        verify_info.timestamp.to_string().as_str(),
-        verify_info.nonce.to_string().as_str(),
-        &encrypted_msg,
-    );
-    if sign != verify_info.signature {
-        return Err(anyhow!("签名不正确"));
+) -> Result<CallbackMessage, CallbackError> {
+    let encrypted_msg = encrypted_msg(xml)?;
+    if !check_sign(token, verify_info, &encrypted_msg) {
+        return Err(CallbackError::Signature);
+    }
+    Ok(decode_xml(&open(key, receiver_id, &encrypted_msg)?))
+}
+
+/// 不校验签名直接解密，用于回放录制的回调
+pub fn decrypt_message_unverified(
+    key: &[u8],
+    receiver_id: &str,
+    xml: &str,
+) -> Result<CallbackMessage, CallbackError> {
+    let encrypted_msg = encrypted_msg(xml)?;
+    Ok(decode_xml(&open(key, receiver_id, &encrypted_msg)?))
+}
+
+/// 校验回调 URL 时解密 echostr
+pub fn decrypt_echo_str(
+    key: &[u8],
+    receiver_id: &str,
+    token: &str,
+    verify_info: &VerifyInfo,
+    echo_str: &str,
+) -> Result<String, CallbackError> {
+    if !check_sign(token, verify_info, echo_str) {
+        return Err(CallbackError::Signature);
     }
-    // verify_message(token, verify_info, &encrypted_msg)?;
+    open(key, receiver_id, echo_str)
+}
+
+fn encrypted_msg(xml: &str) -> Result<String, CallbackError> {
+    quick_xml::de::from_str::<EncryptedXML>(xml)
+        .map(|x| x.encrypted_msg)
+        .map_err(|e| CallbackError::Malformed(e.to_string()))
+}
 
-    let b = base64::engine::general_purpose::STANDARD.decode(encrypted_msg.as_bytes())?;
-    let r = decrypt(key, &b)?;
-    let (msg, decoded_receiver_id) = parse_plain_text(&r)?;
+/// 解密并校验 receiver_id，返回明文
+fn open(key: &[u8], receiver_id: &str, encrypted_msg: &str) -> Result<String, CallbackError> {
+    let b = base64::engine::general_purpose::STANDARD
+        .decode(encrypted_msg.as_bytes())
+        .map_err(|e| CallbackError::Malformed(format!("base64 解码失败: {}", e)))?;
+    let (msg, decoded_receiver_id) = decrypt(key, &b)
+        .and_then(|r| parse_plain_text(&r))
+        .map_err(|e| CallbackError::Decrypt(format!("{:#}", e)))?;
     if receiver_id != decoded_receiver_id {
-        return Err(anyhow!("receiver_id={} 与服务端配置不一致", receiver_id));
+        return Err(CallbackError::ReceiverId(decoded_receiver_id));
     }
-    Ok(decode_xml(&msg))
+    Ok(msg)
+}
+
+/// 所有回调消息共有的字段，用于决定具体的消息类型
+#[derive(Deserialize, Debug)]
+struct CallbackHeader {
+    #[serde(rename = "MsgType")]
+    msg_type: String,
+    #[serde(rename = "Event", default)]
+    event: Option<String>,
 }
 
+/// 先读取 MsgType 和 Event，再解析为对应的消息结构。
+/// 不支持的类型或字段不符合预期时保留原始 XML
 fn decode_xml(xml: &str) -> CallbackMessage {
-    if let Ok(m) = quick_xml::de::from_str::<TextCallbackMessage>(xml) {
-        return CallbackMessage::Text(m);
-    }
-    if let Ok(m) = quick_xml::de::from_str::<ImageCallbackMessage>(xml) {
-        return CallbackMessage::Image(m);
-    }
-    CallbackMessage::Others
+    let header = match quick_xml::de::from_str::<CallbackHeader>(xml) {
+        Ok(h) => h,
+        Err(e) => {
+            warn!("callback message without MsgType: {:?}", e);
+            return CallbackMessage::Unknown(xml.to_string());
+        }
+    };
+    let r = match header.msg_type.as_str() {
+        "text" => parse(xml, CallbackMessage::Text),
+        "image" => parse(xml, CallbackMessage::Image),
+        "voice" => parse(xml, CallbackMessage::Voice),
+        "video" => parse(xml, CallbackMessage::Video),
+        "location" => parse(xml, CallbackMessage::Location),
+        "link" => parse(xml, CallbackMessage::Link),
+        "event" => match event::decode_event(header.event.as_deref().unwrap_or_default(), xml) {
+            Some(r) => r.map(CallbackMessage::Event),
+            None => return CallbackMessage::Unknown(xml.to_string()),
+        },
+        _ => return CallbackMessage::Unknown(xml.to_string()),
+    };
+    r.unwrap_or_else(|e| {
+        warn!(
+            msg_type = header.msg_type,
+            event = header.event,
+            "decode callback message failed: {:?}",
+            e
+        );
+        CallbackMessage::Unknown(xml.to_string())
+    })
+}
+
+fn parse<T: DeserializeOwned, R>(xml: &str, f: impl FnOnce(T) -> R) -> Result<R> {
+    Ok(f(quick_xml::de::from_str::<T>(xml)?))
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -105,11 +212,182 @@ pub struct ImageCallbackMessage {
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct VoiceCallbackMessage {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "MediaId")]
+    pub media_id: String,
+    /// 语音格式，如 amr，speex 等
+    #[serde(rename = "Format")]
+    pub format: String,
+    #[serde(rename = "MsgId")]
+    pub msg_id: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct VideoCallbackMessage {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "MediaId")]
+    pub media_id: String,
+    #[serde(rename = "ThumbMediaId")]
+    pub thumb_media_id: String,
+    #[serde(rename = "MsgId")]
+    pub msg_id: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct LocationCallbackMessage {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    /// 纬度
+    #[serde(rename = "Location_X")]
+    pub location_x: f64,
+    /// 经度
+    #[serde(rename = "Location_Y")]
+    pub location_y: f64,
+    #[serde(rename = "Scale")]
+    pub scale: i64,
+    #[serde(rename = "Label")]
+    pub label: String,
+    #[serde(rename = "MsgId")]
+    pub msg_id: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+    #[serde(rename = "AppType", default)]
+    pub app_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct LinkCallbackMessage {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Title")]
+    pub title: String,
+    #[serde(rename = "Description")]
+    pub description: String,
+    #[serde(rename = "Url")]
+    pub url: String,
+    #[serde(rename = "PicUrl")]
+    pub pic_url: String,
+    #[serde(rename = "MsgId")]
+    pub msg_id: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+/// 只用于序列化，解析按 MsgType 分派，见 `decode_xml`
+#[derive(Serialize, Debug, PartialEq)]
 #[serde(untagged)]
 pub enum CallbackMessage {
     Text(TextCallbackMessage),
     Image(ImageCallbackMessage),
-    Others,
+    Voice(VoiceCallbackMessage),
+    Video(VideoCallbackMessage),
+    Location(LocationCallbackMessage),
+    Link(LinkCallbackMessage),
+    Event(CallbackEvent),
+    /// 暂不支持的消息，保留解密后的原始 XML
+    Unknown(String),
+}
+
+impl CallbackMessage {
+    /// 消息类型，事件统一为 event
+    pub fn msg_type(&self) -> &'static str {
+        match self {
+            CallbackMessage::Text(_) => "text",
+            CallbackMessage::Image(_) => "image",
+            CallbackMessage::Voice(_) => "voice",
+            CallbackMessage::Video(_) => "video",
+            CallbackMessage::Location(_) => "location",
+            CallbackMessage::Link(_) => "link",
+            CallbackMessage::Event(_) => "event",
+            CallbackMessage::Unknown(_) => "unknown",
+        }
+    }
+    pub fn from_user_name(&self) -> Option<&str> {
+        match self {
+            CallbackMessage::Text(m) => Some(&m.from_user_name),
+            CallbackMessage::Image(m) => Some(&m.from_user_name),
+            CallbackMessage::Voice(m) => Some(&m.from_user_name),
+            CallbackMessage::Video(m) => Some(&m.from_user_name),
+            CallbackMessage::Location(m) => Some(&m.from_user_name),
+            CallbackMessage::Link(m) => Some(&m.from_user_name),
+            CallbackMessage::Event(e) => Some(e.from_user_name()),
+            CallbackMessage::Unknown(_) => None,
+        }
+    }
+    /// 接收消息的应用，通讯录变更等事件没有 AgentID
+    pub fn agent_id(&self) -> Option<&str> {
+        match self {
+            CallbackMessage::Text(m) => Some(&m.agent_id),
+            CallbackMessage::Image(m) => Some(&m.agent_id),
+            CallbackMessage::Voice(m) => Some(&m.agent_id),
+            CallbackMessage::Video(m) => Some(&m.agent_id),
+            CallbackMessage::Location(m) => Some(&m.agent_id),
+            CallbackMessage::Link(m) => Some(&m.agent_id),
+            CallbackMessage::Event(e) => e.agent_id(),
+            CallbackMessage::Unknown(_) => None,
+        }
+    }
+    /// 事件类型，非事件消息返回 None
+    pub fn event(&self) -> Option<&str> {
+        match self {
+            CallbackMessage::Event(e) => Some(e.event()),
+            _ => None,
+        }
+    }
+    /// 用于识别企业微信重试的回调。普通消息使用 MsgId，
+    /// 事件没有 MsgId，使用 FromUserName + CreateTime + Event
+    pub fn dedupe_key(&self) -> Option<String> {
+        match self {
+            CallbackMessage::Text(m) => Some(m.msg_id.clone()),
+            CallbackMessage::Image(m) => Some(m.msg_id.clone()),
+            CallbackMessage::Voice(m) => Some(m.msg_id.clone()),
+            CallbackMessage::Video(m) => Some(m.msg_id.clone()),
+            CallbackMessage::Location(m) => Some(m.msg_id.clone()),
+            CallbackMessage::Link(m) => Some(m.msg_id.clone()),
+            CallbackMessage::Event(e) => Some(format!(
+                "{}#{}#{}",
+                e.from_user_name(),
+                e.create_time(),
+                e.event()
+            )),
+            CallbackMessage::Unknown(_) => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -121,7 +399,7 @@ mod test {
     #[tokio::test]
     async fn test_check_sign() {
         let token = "QDG6eK";
-        let receiver_id = "wx5823bf96d3bd56c7";
+        let _receiver_id = "wx5823bf96d3bd56c7";
         let verify_msg_sign = "5c45ff5e21c57e6ad56bac8758b79b1d9ac89fd3";
         let verify_timestamp = 1409659589;
         let verify_nonce = 263014780;
@@ -141,8 +419,6 @@ mod test {
     <AgentID><![CDATA[toAgentID]]></AgentID>
     <Encrypt><![CDATA[msg_encrypt]]></Encrypt></xml>"#;
         let exml: EncryptedXML = quick_xml::de::from_str(xml).unwrap();
-        assert_eq!(exml.to_user_name, "toUser");
-        assert_eq!(exml.agent_id, "toAgentID");
         assert_eq!(exml.encrypted_msg, "msg_encrypt");
         Ok(())
     }
@@ -207,7 +483,7 @@ mod test {
    <MsgId>1234567890123456</MsgId>
    <AgentID>1</AgentID>
 </xml>"#;
-        let msg = dbg!(quick_xml::de::from_str::<TextCallbackMessage>(xml).unwrap());
+        let _msg = dbg!(quick_xml::de::from_str::<TextCallbackMessage>(xml).unwrap());
         Ok(())
     }
 
@@ -222,7 +498,353 @@ mod test {
     <MediaId><![CDATA[media_id]]></MediaId>
     <MsgId>1234567890123456</MsgId>
     <AgentID>1</AgentID></xml>"#;
-        let msg = dbg!(quick_xml::de::from_str::<ImageCallbackMessage>(xml).unwrap());
+        let _msg = dbg!(quick_xml::de::from_str::<ImageCallbackMessage>(xml).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_card_event() -> Result<()> {
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName>
+<FromUserName><![CDATA[FromUser]]></FromUserName>
+<CreateTime>123456789</CreateTime>
+<MsgType><![CDATA[event]]></MsgType>
+<Event><![CDATA[template_card_event]]></Event>
+<EventKey><![CDATA[key111]]></EventKey>
+<TaskId><![CDATA[taskid111]]></TaskId>
+<CardType><![CDATA[vote_interaction]]></CardType>
+<ResponseCode><![CDATA[ResponseCode]]></ResponseCode>
+<AgentID>1</AgentID>
+<SelectedItems>
+    <SelectedItem>
+        <QuestionKey><![CDATA[QuestionKey1]]></QuestionKey>
+        <OptionIds>
+            <OptionId><![CDATA[OptionId1]]></OptionId>
+            <OptionId><![CDATA[OptionId2]]></OptionId>
+        </OptionIds>
+    </SelectedItem>
+</SelectedItems>
+</xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::TemplateCard(m)) => {
+                assert_eq!(m.event_key, "key111");
+                assert_eq!(m.task_id, "taskid111");
+                assert_eq!(m.response_code, "ResponseCode");
+                assert_eq!(m.selected_items.items.len(), 1);
+                assert_eq!(m.selected_items.items[0].question_key, "QuestionKey1");
+                assert_eq!(
+                    m.selected_items.items[0].option_ids.ids,
+                    vec!["OptionId1", "OptionId2"]
+                );
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        // 按钮交互型卡片没有 SelectedItems
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>123456789</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[template_card_event]]></Event><EventKey><![CDATA[ack]]></EventKey><TaskId><![CDATA[taskid111]]></TaskId><CardType><![CDATA[button_interaction]]></CardType><ResponseCode><![CDATA[ResponseCode]]></ResponseCode><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::TemplateCard(m)) => {
+                assert_eq!(m.event_key, "ack");
+                assert!(m.selected_items.items.is_empty());
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_media_messages() {
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[fromUser]]></FromUserName><CreateTime>1357290913</CreateTime><MsgType><![CDATA[voice]]></MsgType><MediaId><![CDATA[media_id]]></MediaId><Format><![CDATA[amr]]></Format><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Voice(m) => assert_eq!(m.format, "amr"),
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[fromUser]]></FromUserName><CreateTime>1357290913</CreateTime><MsgType><![CDATA[video]]></MsgType><MediaId><![CDATA[media_id]]></MediaId><ThumbMediaId><![CDATA[thumb_media_id]]></ThumbMediaId><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Video(m) => assert_eq!(m.thumb_media_id, "thumb_media_id"),
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[fromUser]]></FromUserName><CreateTime>1351776360</CreateTime><MsgType><![CDATA[location]]></MsgType><Location_X>23.134</Location_X><Location_Y>113.358</Location_Y><Scale>20</Scale><Label><![CDATA[位置信息]]></Label><MsgId>1234567890123456</MsgId><AgentID>1</AgentID><AppType><![CDATA[wxwork]]></AppType></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Location(m) => {
+                assert_eq!(m.label, "位置信息");
+                assert_eq!(m.app_type.as_deref(), Some("wxwork"));
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[fromUser]]></FromUserName><CreateTime>1348831860</CreateTime><MsgType><![CDATA[link]]></MsgType><Title><![CDATA[this is a title!]]></Title><Description><![CDATA[this is a description!]]></Description><Url><![CDATA[URL]]></Url><PicUrl><![CDATA[this is a url]]></PicUrl><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Link(m) => assert_eq!(m.title, "this is a title!"),
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[UserID]]></FromUserName><CreateTime>1348831860</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[subscribe]]></Event><AgentID>1</AgentID></xml>"#;
+        assert!(matches!(
+            decode_xml(xml),
+            CallbackMessage::Event(CallbackEvent::Subscribe(_))
+        ));
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[enter_agent]]></Event><EventKey><![CDATA[]]></EventKey><AgentID>1</AgentID></xml>"#;
+        assert!(matches!(
+            decode_xml(xml),
+            CallbackMessage::Event(CallbackEvent::EnterAgent(_))
+        ));
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>123456789</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[click]]></Event><EventKey><![CDATA[EVENTKEY]]></EventKey><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::Click(e)) => assert_eq!(e.event_key, "EVENTKEY"),
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>123456789</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[LOCATION]]></Event><Latitude>23.104</Latitude><Longitude>113.320</Longitude><Precision>65.000</Precision><AgentID>1</AgentID><AppType><![CDATA[wxwork]]></AppType></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::Location(e)) => assert_eq!(e.latitude, 23.104),
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[wx28dbb14e3720FAKE]]></ToUserName><FromUserName><![CDATA[sys]]></FromUserName><CreateTime>1425284517</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[batch_job_result]]></Event><BatchJob><JobId><![CDATA[S0MrnndvRG5fadSlLwiBqiDDbM143UqTmKP3152FZk4]]></JobId><JobType><![CDATA[sync_user]]></JobType><ErrCode>0</ErrCode><ErrMsg><![CDATA[ok]]></ErrMsg></BatchJob></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::BatchJobResult(e)) => {
+                assert_eq!(e.batch_job.job_type, "sync_user")
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[sys]]></FromUserName><CreateTime>1403610513</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[change_contact]]></Event><ChangeType>create_user</ChangeType><UserID><![CDATA[zhangsan]]></UserID><Name><![CDATA[张三]]></Name><Department><![CDATA[1,2,3]]></Department></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::ChangeContact(e)) => {
+                assert_eq!(e.change_type, "create_user");
+                assert_eq!(e.user_id.as_deref(), Some("zhangsan"));
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[scancode_push]]></Event><EventKey><![CDATA[6]]></EventKey><ScanCodeInfo><ScanType><![CDATA[qrcode]]></ScanType><ScanResult><![CDATA[1]]></ScanResult></ScanCodeInfo><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::ScancodePush(e)) => {
+                assert_eq!(e.scan_code_info.scan_type, "qrcode")
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408090651</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[pic_sysphoto]]></Event><EventKey><![CDATA[6]]></EventKey><SendPicsInfo><Count>1</Count><PicList><item><PicMd5Sum><![CDATA[1b5f7c23b5bf75682a53e7b6d163e185]]></PicMd5Sum></item></PicList></SendPicsInfo><AgentID>1</AgentID></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::PicSysphoto(e)) => {
+                assert_eq!(e.send_pics_info.pic_list.items.len(), 1)
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[location_select]]></Event><EventKey><![CDATA[6]]></EventKey><SendLocationInfo><Location_X><![CDATA[23]]></Location_X><Location_Y><![CDATA[113]]></Location_Y><Scale><![CDATA[15]]></Scale><Label><![CDATA[ 广州市海珠区客村艺苑路 106号]]></Label><Poiname><![CDATA[]]></Poiname></SendLocationInfo><AgentID>1</AgentID></xml>"#;
+        assert!(matches!(
+            decode_xml(xml),
+            CallbackMessage::Event(CallbackEvent::LocationSelect(_))
+        ));
+
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1527838022</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[open_approval_change]]></Event><AgentID>1</AgentID><ApprovalInfo><ThirdNo><![CDATA[201806010001]]></ThirdNo><OpenSpName><![CDATA[付款]]></OpenSpName><OpenTemplateId><![CDATA[1234567890]]></OpenTemplateId><OpenSpStatus>1</OpenSpStatus><ApplyTime>1527837645</ApplyTime><ApplyUserName><![CDATA[xiaoming]]></ApplyUserName><ApplyUserId><![CDATA[1]]></ApplyUserId><ApplyUserParty><![CDATA[产品部]]></ApplyUserParty><ApplyUserImage><![CDATA[http://www.qq.com/xxx.png]]></ApplyUserImage><ApprovalNodes><ApprovalNode><NodeStatus>1</NodeStatus><NodeAttr>1</NodeAttr><NodeType>1</NodeType><Items><Item><ItemName><![CDATA[xiaohong]]></ItemName><ItemUserId><![CDATA[2]]></ItemUserId><ItemImage><![CDATA[http://www.qq.com/xxx.png]]></ItemImage><ItemStatus>1</ItemStatus><ItemSpeech><![CDATA[]]></ItemSpeech><ItemOpTime>0</ItemOpTime></Item></Items></ApprovalNode></ApprovalNodes><NotifyNodes><NotifyNode><ItemName><![CDATA[xiaogang]]></ItemName><ItemUserId><![CDATA[3]]></ItemUserId><ItemImage><![CDATA[http://www.qq.com/xxx.png]]></ItemImage></NotifyNode></NotifyNodes><approverstep>0</approverstep></ApprovalInfo></xml>"#;
+        match decode_xml(xml) {
+            CallbackMessage::Event(CallbackEvent::OpenApprovalChange(e)) => {
+                assert_eq!(e.approval_info.open_sp_status, 1);
+                assert_eq!(e.approval_info.approval_nodes.nodes.len(), 1);
+            }
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_unknown() {
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[new_event]]></Event><AgentID>1</AgentID></xml>"#;
+        assert_eq!(decode_xml(xml), CallbackMessage::Unknown(xml.to_string()));
+    }
+
+    // 企业微信文档中的示例消息，每条都应解析为对应的类型
+    #[test]
+    fn test_decode_samples() {
+        type Check = fn(&CallbackMessage) -> bool;
+        let cases: Vec<(&str, &str, Check)> = vec![
+            (
+                "text",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[text]]></MsgType><Content><![CDATA[this is a test]]></Content><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Text(t) if t.content == "this is a test"),
+            ),
+            // 字段多于预期的文本消息不能被当作图片消息
+            (
+                "text with extra fields",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[text]]></MsgType><Content><![CDATA[hi]]></Content><PicUrl><![CDATA[url]]></PicUrl><MediaId><![CDATA[media_id]]></MediaId><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Text(_)),
+            ),
+            (
+                "image",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[image]]></MsgType><PicUrl><![CDATA[this is a url]]></PicUrl><MediaId><![CDATA[media_id]]></MediaId><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Image(_)),
+            ),
+            (
+                "voice",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[voice]]></MsgType><MediaId><![CDATA[media_id]]></MediaId><Format><![CDATA[amr]]></Format><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Voice(_)),
+            ),
+            (
+                "video",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[video]]></MsgType><MediaId><![CDATA[media_id]]></MediaId><ThumbMediaId><![CDATA[thumb_media_id]]></ThumbMediaId><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Video(_)),
+            ),
+            (
+                "location",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[location]]></MsgType><Location_X>23.134</Location_X><Location_Y>113.358</Location_Y><Scale>20</Scale><Label><![CDATA[位置信息]]></Label><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Location(_)),
+            ),
+            (
+                "link",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[link]]></MsgType><Title><![CDATA[title]]></Title><Description><![CDATA[description]]></Description><Url><![CDATA[URL]]></Url><PicUrl><![CDATA[url]]></PicUrl><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Link(_)),
+            ),
+            (
+                "subscribe",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[subscribe]]></Event><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::Subscribe(_))),
+            ),
+            (
+                "unsubscribe",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[unsubscribe]]></Event><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::Unsubscribe(_))),
+            ),
+            (
+                "enter_agent",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[enter_agent]]></Event><EventKey><![CDATA[]]></EventKey><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::EnterAgent(_))),
+            ),
+            (
+                "LOCATION",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[LOCATION]]></Event><Latitude>23.104</Latitude><Longitude>113.320</Longitude><Precision>65.000</Precision><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::Location(_))),
+            ),
+            (
+                "click",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[click]]></Event><EventKey><![CDATA[EVENTKEY]]></EventKey><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::Click(_))),
+            ),
+            (
+                "view",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[view]]></Event><EventKey><![CDATA[www.qq.com]]></EventKey><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::View(_))),
+            ),
+            (
+                "scancode_waitmsg",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[scancode_waitmsg]]></Event><EventKey><![CDATA[6]]></EventKey><ScanCodeInfo><ScanType><![CDATA[qrcode]]></ScanType><ScanResult><![CDATA[2]]></ScanResult></ScanCodeInfo><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::ScancodeWaitmsg(_))),
+            ),
+            (
+                "pic_photo_or_album",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[pic_photo_or_album]]></Event><EventKey><![CDATA[6]]></EventKey><SendPicsInfo><Count>1</Count><PicList><item><PicMd5Sum><![CDATA[5a75aaca956d97be686719218f275c6b]]></PicMd5Sum></item></PicList></SendPicsInfo><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::PicPhotoOrAlbum(_))),
+            ),
+            (
+                "pic_weixin",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[pic_weixin]]></Event><EventKey><![CDATA[6]]></EventKey><SendPicsInfo><Count>1</Count><PicList><item><PicMd5Sum><![CDATA[5a75aaca956d97be686719218f275c6b]]></PicMd5Sum></item></PicList></SendPicsInfo><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::PicWeixin(_))),
+            ),
+            (
+                "sys_approval_change",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[sys_approval_change]]></Event><AgentID>3010040</AgentID><ApprovalInfo><SpNo>202006280001</SpNo><SpName><![CDATA[请假]]></SpName><SpStatus>1</SpStatus><TemplateId><![CDATA[C4NxJ2f]]></TemplateId><ApplyTime>1593325926</ApplyTime><Applyer><UserId><![CDATA[WuJunJie]]></UserId><Party><![CDATA[1]]></Party></Applyer><StatuChangeEvent>1</StatuChangeEvent></ApprovalInfo></xml>"#,
+                |m| {
+                    matches!(
+                        m,
+                        CallbackMessage::Event(CallbackEvent::SysApprovalChange(_))
+                    )
+                },
+            ),
+            (
+                "template_card_event",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[template_card_event]]></Event><EventKey><![CDATA[ack]]></EventKey><TaskId><![CDATA[taskid111]]></TaskId><CardType><![CDATA[button_interaction]]></CardType><ResponseCode><![CDATA[ResponseCode]]></ResponseCode><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Event(CallbackEvent::TemplateCard(_))),
+            ),
+            (
+                "template_card_menu_event",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[template_card_menu_event]]></Event><EventKey><![CDATA[key111]]></EventKey><TaskId><![CDATA[taskid111]]></TaskId><CardType><![CDATA[text_notice]]></CardType><ResponseCode><![CDATA[ResponseCode]]></ResponseCode><AgentID>1</AgentID></xml>"#,
+                |m| {
+                    matches!(
+                        m,
+                        CallbackMessage::Event(CallbackEvent::TemplateCardMenu(_))
+                    )
+                },
+            ),
+            (
+                "unknown event",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[new_event]]></Event><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Unknown(_)),
+            ),
+            (
+                "unknown msg_type",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[miniprogram]]></MsgType><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Unknown(_)),
+            ),
+            // 类型已知但缺少字段
+            (
+                "missing fields",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[FromUser]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[image]]></MsgType><AgentID>1</AgentID></xml>"#,
+                |m| matches!(m, CallbackMessage::Unknown(_)),
+            ),
+            (
+                "without msg_type",
+                r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName></xml>"#,
+                |m| matches!(m, CallbackMessage::Unknown(_)),
+            ),
+        ];
+        for (name, xml, check) in cases {
+            let m = decode_xml(xml);
+            assert!(check(&m), "{}: unexpected message: {:?}", name, m);
+        }
+    }
+
+    #[test]
+    fn test_dedupe_key() {
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[fromUser]]></FromUserName><CreateTime>1348831860</CreateTime><MsgType><![CDATA[text]]></MsgType><Content><![CDATA[hi]]></Content><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>"#;
+        assert_eq!(
+            decode_xml(xml).dedupe_key().as_deref(),
+            Some("1234567890123456")
+        );
+        let xml = r#"<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[UserID]]></FromUserName><CreateTime>1348831860</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[subscribe]]></Event><AgentID>1</AgentID></xml>"#;
+        assert_eq!(
+            decode_xml(xml).dedupe_key().as_deref(),
+            Some("UserID#1348831860#subscribe")
+        );
+        assert_eq!(CallbackMessage::Unknown("".to_string()).dedupe_key(), None);
+    }
+
+    #[test]
+    fn test_decrypt_message_unverified() -> Result<()> {
+        let xml = "<xml><ToUserName><![CDATA[wx49f0ab532d5d035a]]></ToUserName>\n\
+            <Encrypt><![CDATA[RgqEoJj5A4EMYlLvWO1F86ioRjZfaex/gePD0gOXTxpsq5Yj4GNglrBb8I2BAJVODGajiFnXBu7mCPatfjsu6IHCrsTyeDXzF6Bv283dGymzxh6ydJRvZsryDyZbLTE7rhnus50qGPMfp2wASFlzEgMW9z1ef/RD8XzaFYgm7iTdaXpXaG4+BiYyolBug/gYNx410cvkKR2/nPwBiT+P4hIiOAQqGp/TywZBtDh1yCF2KOd0gpiMZ5jSw3e29mTvmUHzkVQiMS6td7vXUaWOMZnYZlF3So2SjHnwh4jYFxdgpkHHqIrH/54SNdshoQgWYEvccTKe7FS709/5t6NMxuGhcUGAPOQipvWTT4dShyqio7mlsl5noTrb++x6En749zCpQVhDpbV6GDnTbcX2e8K9QaNWHp91eBdCRxthuL0=]]></Encrypt>\n\
+            <AgentID><![CDATA[1]]></AgentID>\n\
+            </xml>";
+        let aes_key = decode_aes_key("kWxPEV2UEDyxWpmPdKC3F4dgPDmOvfKX1HGnEUDS1aQ")?;
+        let verify_info = VerifyInfo {
+            signature: "0000000000000000000000000000000000000000".to_string(),
+            timestamp: 1411525903,
+            nonce: 461056294,
+        };
+        assert!(matches!(
+            decrypt_message(&aes_key, "wx49f0ab532d5d035a", "123456", &verify_info, xml),
+            Err(CallbackError::Signature)
+        ));
+        match decrypt_message_unverified(&aes_key, "wx49f0ab532d5d035a", xml)? {
+            CallbackMessage::Text(m) => assert_eq!(m.content, "test"),
+            m => panic!("unexpected message: {:?}", m),
+        }
+        let e = decrypt_message_unverified(&aes_key, "other", xml).unwrap_err();
+        assert_eq!(e.kind(), "receiver_id");
+        assert_eq!(e.err_code(), -40005);
+        let e = decrypt_message_unverified(&aes_key, "other", "<xml>").unwrap_err();
+        assert_eq!(e.kind(), "malformed");
+        let xml =
+            "<xml><ToUserName>a</ToUserName><Encrypt>AAAA</Encrypt><AgentID>1</AgentID></xml>";
+        let e = decrypt_message_unverified(&aes_key, "other", xml).unwrap_err();
+        assert_eq!(e.kind(), "decrypt");
         Ok(())
     }
 }
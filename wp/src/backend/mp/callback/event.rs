@@ -0,0 +1,604 @@
+use super::parse;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+// 事件格式
+// https://developer.work.weixin.qq.com/document/path/90240
+// 只用于序列化，解析按 Event 分派，见 `decode_event`
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum CallbackEvent {
+    Subscribe(BasicEvent),
+    Unsubscribe(BasicEvent),
+    EnterAgent(BasicEvent),
+    Location(LocationEvent),
+    BatchJobResult(BatchJobResultEvent),
+    ChangeContact(Box<ChangeContactEvent>),
+    Click(MenuEvent),
+    View(MenuEvent),
+    ScancodePush(ScancodeEvent),
+    ScancodeWaitmsg(ScancodeEvent),
+    PicSysphoto(PicEvent),
+    PicPhotoOrAlbum(PicEvent),
+    PicWeixin(PicEvent),
+    LocationSelect(LocationSelectEvent),
+    OpenApprovalChange(Box<OpenApprovalChangeEvent>),
+    SysApprovalChange(Box<SysApprovalChangeEvent>),
+    TemplateCard(TemplateCardEventMessage),
+    TemplateCardMenu(TemplateCardMenuEvent),
+}
+
+// 所有事件共有的字段
+macro_rules! common_field {
+    ($self:ident, $field:ident) => {
+        match $self {
+            CallbackEvent::Subscribe(e)
+            | CallbackEvent::Unsubscribe(e)
+            | CallbackEvent::EnterAgent(e) => &e.$field,
+            CallbackEvent::Location(e) => &e.$field,
+            CallbackEvent::BatchJobResult(e) => &e.$field,
+            CallbackEvent::ChangeContact(e) => &e.$field,
+            CallbackEvent::Click(e) | CallbackEvent::View(e) => &e.$field,
+            CallbackEvent::ScancodePush(e) | CallbackEvent::ScancodeWaitmsg(e) => &e.$field,
+            CallbackEvent::PicSysphoto(e)
+            | CallbackEvent::PicPhotoOrAlbum(e)
+            | CallbackEvent::PicWeixin(e) => &e.$field,
+            CallbackEvent::LocationSelect(e) => &e.$field,
+            CallbackEvent::OpenApprovalChange(e) => &e.$field,
+            CallbackEvent::SysApprovalChange(e) => &e.$field,
+            CallbackEvent::TemplateCard(e) => &e.$field,
+            CallbackEvent::TemplateCardMenu(e) => &e.$field,
+        }
+    };
+}
+
+impl CallbackEvent {
+    pub fn from_user_name(&self) -> &str {
+        common_field!(self, from_user_name)
+    }
+    pub fn create_time(&self) -> i64 {
+        *common_field!(self, create_time)
+    }
+    pub fn event(&self) -> &str {
+        common_field!(self, event)
+    }
+    pub fn agent_id(&self) -> Option<&str> {
+        match self {
+            CallbackEvent::Subscribe(e)
+            | CallbackEvent::Unsubscribe(e)
+            | CallbackEvent::EnterAgent(e) => Some(&e.agent_id),
+            CallbackEvent::Location(e) => Some(&e.agent_id),
+            CallbackEvent::BatchJobResult(e) => e.agent_id.as_deref(),
+            CallbackEvent::ChangeContact(_) => None,
+            CallbackEvent::Click(e) | CallbackEvent::View(e) => Some(&e.agent_id),
+            CallbackEvent::ScancodePush(e) | CallbackEvent::ScancodeWaitmsg(e) => Some(&e.agent_id),
+            CallbackEvent::PicSysphoto(e)
+            | CallbackEvent::PicPhotoOrAlbum(e)
+            | CallbackEvent::PicWeixin(e) => Some(&e.agent_id),
+            CallbackEvent::LocationSelect(e) => Some(&e.agent_id),
+            CallbackEvent::OpenApprovalChange(e) => Some(&e.agent_id),
+            CallbackEvent::SysApprovalChange(e) => Some(&e.agent_id),
+            CallbackEvent::TemplateCard(e) => Some(&e.agent_id),
+            CallbackEvent::TemplateCardMenu(e) => Some(&e.agent_id),
+        }
+    }
+}
+
+/// 按 Event 字段解析对应的事件结构，不支持的事件返回 None
+pub(super) fn decode_event(event: &str, xml: &str) -> Option<Result<CallbackEvent>> {
+    let r = match event {
+        "subscribe" => parse(xml, CallbackEvent::Subscribe),
+        "unsubscribe" => parse(xml, CallbackEvent::Unsubscribe),
+        "enter_agent" => parse(xml, CallbackEvent::EnterAgent),
+        "LOCATION" => parse(xml, CallbackEvent::Location),
+        "batch_job_result" => parse(xml, CallbackEvent::BatchJobResult),
+        "change_contact" => parse(xml, |e| CallbackEvent::ChangeContact(Box::new(e))),
+        "click" => parse(xml, CallbackEvent::Click),
+        "view" => parse(xml, CallbackEvent::View),
+        "scancode_push" => parse(xml, CallbackEvent::ScancodePush),
+        "scancode_waitmsg" => parse(xml, CallbackEvent::ScancodeWaitmsg),
+        "pic_sysphoto" => parse(xml, CallbackEvent::PicSysphoto),
+        "pic_photo_or_album" => parse(xml, CallbackEvent::PicPhotoOrAlbum),
+        "pic_weixin" => parse(xml, CallbackEvent::PicWeixin),
+        "location_select" => parse(xml, CallbackEvent::LocationSelect),
+        "open_approval_change" => parse(xml, |e| CallbackEvent::OpenApprovalChange(Box::new(e))),
+        "sys_approval_change" => parse(xml, |e| CallbackEvent::SysApprovalChange(Box::new(e))),
+        "template_card_event" => parse(xml, CallbackEvent::TemplateCard),
+        "template_card_menu_event" => parse(xml, CallbackEvent::TemplateCardMenu),
+        _ => return None,
+    };
+    Some(r)
+}
+
+/// 成员关注、取消关注、进入应用事件
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct BasicEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey", default)]
+    pub event_key: Option<String>,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+/// 上报地理位置
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct LocationEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "Latitude")]
+    pub latitude: f64,
+    #[serde(rename = "Longitude")]
+    pub longitude: f64,
+    #[serde(rename = "Precision")]
+    pub precision: f64,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+    #[serde(rename = "AppType", default)]
+    pub app_type: Option<String>,
+}
+
+/// 异步任务完成通知
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct BatchJobResultEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "BatchJob")]
+    pub batch_job: BatchJob,
+    #[serde(rename = "AgentID", default)]
+    pub agent_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BatchJob {
+    #[serde(rename = "JobId")]
+    pub job_id: String,
+    /// sync_user / replace_user / invite_user / replace_party
+    #[serde(rename = "JobType")]
+    pub job_type: String,
+    #[serde(rename = "ErrCode")]
+    pub err_code: i64,
+    #[serde(rename = "ErrMsg")]
+    pub err_msg: String,
+}
+
+/// 通讯录变更事件，ChangeType 为 create_user / update_user / delete_user /
+/// create_party / update_party / delete_party / update_tag，不同类型只有部分字段
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct ChangeContactEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "ChangeType")]
+    pub change_type: String,
+    #[serde(rename = "UserID", default)]
+    pub user_id: Option<String>,
+    #[serde(rename = "NewUserID", default)]
+    pub new_user_id: Option<String>,
+    #[serde(rename = "Name", default)]
+    pub name: Option<String>,
+    #[serde(rename = "Department", default)]
+    pub department: Option<String>,
+    #[serde(rename = "MainDepartment", default)]
+    pub main_department: Option<String>,
+    #[serde(rename = "IsLeaderInDept", default)]
+    pub is_leader_in_dept: Option<String>,
+    #[serde(rename = "Position", default)]
+    pub position: Option<String>,
+    #[serde(rename = "Mobile", default)]
+    pub mobile: Option<String>,
+    #[serde(rename = "Gender", default)]
+    pub gender: Option<String>,
+    #[serde(rename = "Email", default)]
+    pub email: Option<String>,
+    #[serde(rename = "Status", default)]
+    pub status: Option<String>,
+    #[serde(rename = "Alias", default)]
+    pub alias: Option<String>,
+    #[serde(rename = "Id", default)]
+    pub id: Option<String>,
+    #[serde(rename = "ParentId", default)]
+    pub parent_id: Option<String>,
+    #[serde(rename = "Order", default)]
+    pub order: Option<String>,
+    #[serde(rename = "TagId", default)]
+    pub tag_id: Option<String>,
+    #[serde(rename = "AddUserItems", default)]
+    pub add_user_items: Option<String>,
+    #[serde(rename = "DelUserItems", default)]
+    pub del_user_items: Option<String>,
+    #[serde(rename = "AddPartyItems", default)]
+    pub add_party_items: Option<String>,
+    #[serde(rename = "DelPartyItems", default)]
+    pub del_party_items: Option<String>,
+}
+
+/// 点击菜单拉取消息、点击菜单跳转链接
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct MenuEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey")]
+    pub event_key: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+/// 扫码推事件、扫码推事件且弹出“消息接收中”提示框
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct ScancodeEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey")]
+    pub event_key: String,
+    #[serde(rename = "ScanCodeInfo")]
+    pub scan_code_info: ScanCodeInfo,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ScanCodeInfo {
+    #[serde(rename = "ScanType")]
+    pub scan_type: String,
+    #[serde(rename = "ScanResult")]
+    pub scan_result: String,
+}
+
+/// 弹出系统拍照发图、拍照或者相册发图、微信相册发图
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct PicEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey")]
+    pub event_key: String,
+    #[serde(rename = "SendPicsInfo")]
+    pub send_pics_info: SendPicsInfo,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SendPicsInfo {
+    #[serde(rename = "Count")]
+    pub count: i64,
+    #[serde(rename = "PicList", default)]
+    pub pic_list: PicList,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct PicList {
+    #[serde(rename = "item", default)]
+    pub items: Vec<PicItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PicItem {
+    #[serde(rename = "PicMd5Sum")]
+    pub pic_md5_sum: String,
+}
+
+/// 弹出地理位置选择器
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct LocationSelectEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey")]
+    pub event_key: String,
+    #[serde(rename = "SendLocationInfo")]
+    pub send_location_info: SendLocationInfo,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SendLocationInfo {
+    #[serde(rename = "Location_X")]
+    pub location_x: f64,
+    #[serde(rename = "Location_Y")]
+    pub location_y: f64,
+    #[serde(rename = "Scale")]
+    pub scale: i64,
+    #[serde(rename = "Label")]
+    pub label: String,
+    #[serde(rename = "Poiname", default)]
+    pub poiname: Option<String>,
+}
+
+/// 审批状态通知事件（第三方审批应用）
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct OpenApprovalChangeEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+    #[serde(rename = "ApprovalInfo")]
+    pub approval_info: OpenApprovalInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct OpenApprovalInfo {
+    #[serde(rename = "ThirdNo")]
+    pub third_no: String,
+    #[serde(rename = "OpenSpName")]
+    pub open_sp_name: String,
+    #[serde(rename = "OpenTemplateId")]
+    pub open_template_id: String,
+    /// 1 审批中，2 已通过，3 已驳回，4 已撤销
+    #[serde(rename = "OpenSpStatus")]
+    pub open_sp_status: i32,
+    #[serde(rename = "ApplyTime")]
+    pub apply_time: i64,
+    #[serde(rename = "ApplyUserName")]
+    pub apply_user_name: String,
+    #[serde(rename = "ApplyUserId")]
+    pub apply_user_id: String,
+    #[serde(rename = "ApplyUserParty", default)]
+    pub apply_user_party: Option<String>,
+    #[serde(rename = "ApplyUserImage", default)]
+    pub apply_user_image: Option<String>,
+    #[serde(rename = "ApprovalNodes", default)]
+    pub approval_nodes: ApprovalNodes,
+    #[serde(rename = "NotifyNodes", default)]
+    pub notify_nodes: NotifyNodes,
+    #[serde(rename = "approverstep", default)]
+    pub approver_step: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ApprovalNodes {
+    #[serde(rename = "ApprovalNode", default)]
+    pub nodes: Vec<ApprovalNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ApprovalNode {
+    #[serde(rename = "NodeStatus")]
+    pub node_status: i32,
+    #[serde(rename = "NodeAttr")]
+    pub node_attr: i32,
+    #[serde(rename = "NodeType")]
+    pub node_type: i32,
+    #[serde(rename = "Items", default)]
+    pub items: ApprovalItems,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ApprovalItems {
+    #[serde(rename = "Item", default)]
+    pub items: Vec<ApprovalItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ApprovalItem {
+    #[serde(rename = "ItemName")]
+    pub item_name: String,
+    #[serde(rename = "ItemUserId")]
+    pub item_user_id: String,
+    #[serde(rename = "ItemImage", default)]
+    pub item_image: Option<String>,
+    #[serde(rename = "ItemStatus")]
+    pub item_status: i32,
+    #[serde(rename = "ItemSpeech", default)]
+    pub item_speech: Option<String>,
+    #[serde(rename = "ItemOpTime", default)]
+    pub item_op_time: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct NotifyNodes {
+    #[serde(rename = "NotifyNode", default)]
+    pub nodes: Vec<NotifyNode>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct NotifyNode {
+    #[serde(rename = "ItemName")]
+    pub item_name: String,
+    #[serde(rename = "ItemUserId")]
+    pub item_user_id: String,
+    #[serde(rename = "ItemImage", default)]
+    pub item_image: Option<String>,
+}
+
+/// 审批申请状态变化回调通知（企业微信审批应用）
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct SysApprovalChangeEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+    #[serde(rename = "ApprovalInfo")]
+    pub approval_info: SysApprovalInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SysApprovalInfo {
+    #[serde(rename = "SpNo")]
+    pub sp_no: String,
+    #[serde(rename = "SpName")]
+    pub sp_name: String,
+    /// 1 审批中，2 已通过，3 已驳回，4 已撤销，6 通过后撤销，7 已删除，10 已支付
+    #[serde(rename = "SpStatus")]
+    pub sp_status: i32,
+    #[serde(rename = "TemplateId")]
+    pub template_id: String,
+    #[serde(rename = "ApplyTime")]
+    pub apply_time: i64,
+    #[serde(rename = "Applyer")]
+    pub applyer: Applyer,
+    #[serde(rename = "StatuChangeEvent", default)]
+    pub status_change_event: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Applyer {
+    #[serde(rename = "UserId")]
+    pub user_id: String,
+    #[serde(rename = "Party", default)]
+    pub party: Option<String>,
+}
+
+// 模板卡片事件推送
+// https://developer.work.weixin.qq.com/document/path/90240#模板卡片事件推送
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename = "xml")]
+pub struct TemplateCardEventMessage {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey")]
+    pub event_key: String,
+    #[serde(rename = "TaskId")]
+    pub task_id: String,
+    #[serde(rename = "CardType")]
+    pub card_type: String,
+    #[serde(rename = "ResponseCode")]
+    pub response_code: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+    #[serde(rename = "SelectedItems", default)]
+    pub selected_items: SelectedItems,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SelectedItems {
+    #[serde(rename = "SelectedItem", default)]
+    pub items: Vec<SelectedItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SelectedItem {
+    #[serde(rename = "QuestionKey")]
+    pub question_key: String,
+    #[serde(rename = "OptionIds", default)]
+    pub option_ids: OptionIds,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct OptionIds {
+    #[serde(rename = "OptionId", default)]
+    pub ids: Vec<String>,
+}
+
+/// 通用模板卡片右上角菜单事件推送
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename = "xml")]
+pub struct TemplateCardMenuEvent {
+    #[serde(rename = "ToUserName")]
+    pub to_user_name: String,
+    #[serde(rename = "FromUserName")]
+    pub from_user_name: String,
+    #[serde(rename = "CreateTime")]
+    pub create_time: i64,
+    #[serde(rename = "MsgType")]
+    pub msg_type: String,
+    #[serde(rename = "Event")]
+    pub event: String,
+    #[serde(rename = "EventKey")]
+    pub event_key: String,
+    #[serde(rename = "TaskId")]
+    pub task_id: String,
+    #[serde(rename = "CardType")]
+    pub card_type: String,
+    #[serde(rename = "ResponseCode")]
+    pub response_code: String,
+    #[serde(rename = "AgentID")]
+    pub agent_id: String,
+}
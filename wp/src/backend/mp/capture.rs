@@ -0,0 +1,111 @@
+//! 本地模拟的企业微信接口，只记录调用，不真正发出
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::http::Uri;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// 除获取 access_token 之外的一次接口调用
+#[derive(Debug, Clone, Serialize)]
+pub struct Call {
+    pub path: String,
+    /// 不是 JSON 的请求体为 Null，比如上传的素材
+    pub body: Value,
+}
+
+#[derive(Debug, Default)]
+struct Calls {
+    calls: Vec<Call>,
+    sent: usize,
+    failing: Vec<String>,
+    updated: Option<Instant>,
+}
+
+impl Calls {
+    fn handle(&mut self, path: &str, body: &[u8]) -> Value {
+        if path == "/cgi-bin/gettoken" {
+            return json!({"errcode": 0, "errmsg": "ok", "access_token": "capture", "expires_in": 7200});
+        }
+        self.updated = Some(Instant::now());
+        self.calls.push(Call {
+            path: path.to_string(),
+            body: serde_json::from_slice(body).unwrap_or_default(),
+        });
+        if self.failing.iter().any(|p| p == path) {
+            return json!({"errcode": 301002, "errmsg": "capture failing"});
+        }
+        match path {
+            "/cgi-bin/message/send" => {
+                self.sent += 1;
+                json!({"errcode": 0, "errmsg": "ok", "msgid": format!("m{}", self.sent)})
+            }
+            "/cgi-bin/media/upload" => json!({"errcode": 0, "errmsg": "ok", "media_id": "media"}),
+            _ => json!({"errcode": 0, "errmsg": "ok"}),
+        }
+    }
+}
+
+/// 把 MP 的接口地址指向 `api_base` 后，发送的消息都记录在这里。
+/// `message/send` 依次返回 msgid `m1`、`m2`……，丢弃时停止服务
+pub struct Capture {
+    api_base: String,
+    calls: Arc<Mutex<Calls>>,
+    server: JoinHandle<()>,
+}
+
+impl Capture {
+    pub fn start() -> Result<Self> {
+        let calls: Arc<Mutex<Calls>> = Default::default();
+        let c = calls.clone();
+        let app = axum::Router::new().fallback(move |uri: Uri, body: Bytes| {
+            let c = c.clone();
+            async move { axum::Json(c.lock().unwrap().handle(uri.path(), &body)) }
+        });
+        let server =
+            axum::Server::try_bind(&"127.0.0.1:0".parse()?)?.serve(app.into_make_service());
+        let api_base = format!("http://{}", server.local_addr());
+        let server = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        Ok(Self {
+            api_base,
+            calls,
+            server,
+        })
+    }
+
+    pub fn api_base(&self) -> &str {
+        &self.api_base
+    }
+
+    /// 取出目前为止的调用
+    pub fn take(&self) -> Vec<Call> {
+        std::mem::take(&mut self.calls.lock().unwrap().calls)
+    }
+
+    /// 之后调用 `path` 都返回错误
+    pub fn fail(&self, path: &str) {
+        self.calls.lock().unwrap().failing.push(path.to_string());
+    }
+
+    /// 等待后台任务发完消息：`quiet` 内没有新的调用，最多等待 `max`
+    pub async fn settle(&self, quiet: Duration, max: Duration) {
+        let started = Instant::now();
+        loop {
+            tokio::time::sleep(quiet).await;
+            let updated = self.calls.lock().unwrap().updated;
+            if updated.is_none_or(|t| t.elapsed() >= quiet) || started.elapsed() >= max {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
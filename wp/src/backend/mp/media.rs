@@ -1,4 +1,5 @@
-use anyhow::{anyhow, Result};
+use crate::backend::mp::WecomError;
+use anyhow::Result;
 use reqwest::multipart::{Form, Part};
 use serde::Deserialize;
 
@@ -10,26 +11,22 @@ struct UploadMediaResponse {
     err_msg: String,
     #[serde(default)]
     media_id: String,
-    #[serde(default)]
-    created_at: i64,
-    #[serde(default, rename = "type")]
-    media_type: String,
 }
 pub async fn media_upload(
     client: &reqwest::Client,
+    api_base: &str,
     media_type: &str,
     token: &str,
+    file_name: &str,
     b: &[u8],
 ) -> Result<String> {
     let api = format!(
-        "https://qyapi.weixin.qq.com/cgi-bin/media/upload?access_token={}&type={}",
-        token, media_type
+        "{}/cgi-bin/media/upload?access_token={}&type={}",
+        api_base, token, media_type
     );
-    let img = Part::bytes(b.to_owned())
-        .file_name("qrcode.png")
-        .mime_str("image/png")?;
+    let media = Part::bytes(b.to_owned()).file_name(file_name.to_string());
     let f = Form::new();
-    let f = f.part("media", img);
+    let f = f.part("media", media);
 
     let res = client
         .post(api)
@@ -39,11 +36,7 @@ pub async fn media_upload(
         .json::<UploadMediaResponse>()
         .await?;
     if res.err_code != 0 {
-        return Err(anyhow!(
-            "上传图片失败 error: [{}]{}",
-            res.err_code,
-            res.err_msg
-        ));
+        return Err(WecomError::new("上传临时素材失败", res.err_code, &res.err_msg).into());
     }
     Ok(res.media_id)
 }
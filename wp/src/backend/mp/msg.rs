@@ -1,10 +1,13 @@
+use crate::backend::mp::template_card::{CardUpdate, TemplateCard};
+use crate::backend::mp::WecomError;
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fmt::Display;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
-enum MsgType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsgType {
     Text,
     Image,
     Voice,
@@ -14,45 +17,41 @@ enum MsgType {
     News,
     Mpnews,
     Markdown,
-    // MiniprogramNotice,
-    // Taskcard,
-    // InteractiveTaskcard,
-    // TemplateCard,
+    MiniprogramNotice,
+    Taskcard,
+    InteractiveTaskcard,
+    TemplateCard,
 }
 impl Display for MsgType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            MsgType::Text => write!(f, "text"),
-            MsgType::Image => write!(f, "image"),
-            MsgType::Voice => write!(f, "voice"),
-            MsgType::Video => write!(f, "video"),
-            MsgType::File => write!(f, "file"),
-            MsgType::Markdown => write!(f, "markdown"),
-            MsgType::TextCard => write!(f, "textcard"),
-            MsgType::News => write!(f, "news"),
-            MsgType::Mpnews => write!(f, "mpnews"),
-        }
+        write!(f, "{}", self.as_str())
     }
 }
 
-impl From<String> for MsgType {
-    fn from(s: String) -> Self {
-        match s.as_str() {
-            "text" => MsgType::Text,
-            "image" => MsgType::Image,
-            "voice" => MsgType::Voice,
-            "video" => MsgType::Video,
-            "file" => MsgType::File,
-            "markdown" => MsgType::Markdown,
-            "textcard" => MsgType::TextCard,
-            "news" => MsgType::News,
-            "mpnews" => MsgType::Mpnews,
-            _ => MsgType::Text,
+impl FromStr for MsgType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(MsgType::Text),
+            "image" => Ok(MsgType::Image),
+            "voice" => Ok(MsgType::Voice),
+            "video" => Ok(MsgType::Video),
+            "file" => Ok(MsgType::File),
+            "markdown" => Ok(MsgType::Markdown),
+            "textcard" => Ok(MsgType::TextCard),
+            "news" => Ok(MsgType::News),
+            "mpnews" => Ok(MsgType::Mpnews),
+            "miniprogram_notice" => Ok(MsgType::MiniprogramNotice),
+            "taskcard" => Ok(MsgType::Taskcard),
+            "interactive_taskcard" => Ok(MsgType::InteractiveTaskcard),
+            "template_card" => Ok(MsgType::TemplateCard),
+            _ => Err(anyhow!("不支持的消息类型 msgtype={}", s)),
         }
     }
 }
 impl MsgType {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             MsgType::Text => "text",
             MsgType::Image => "image",
@@ -63,6 +62,10 @@ impl MsgType {
             MsgType::TextCard => "textcard",
             MsgType::News => "news",
             MsgType::Mpnews => "mpnews",
+            MsgType::MiniprogramNotice => "miniprogram_notice",
+            MsgType::Taskcard => "taskcard",
+            MsgType::InteractiveTaskcard => "interactive_taskcard",
+            MsgType::TemplateCard => "template_card",
         }
     }
 }
@@ -73,7 +76,7 @@ impl<'de> Deserialize<'de> for MsgType {
         D: serde::Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Ok(MsgType::from(s))
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 impl Serialize for MsgType {
@@ -85,49 +88,121 @@ impl Serialize for MsgType {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct TextContent {
-    content: String,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TextContent {
+    pub content: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MediaContent {
+    pub media_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TextCardContent {
+    pub title: String,
+    pub description: String,
+    pub url: String,
+    #[serde(rename = "btntxt", skip_serializing_if = "Option::is_none")]
+    pub btn_txt: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewsContent {
+    pub articles: Vec<NewsArticle>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NewsArticle {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(rename = "picurl", skip_serializing_if = "Option::is_none")]
+    pub pic_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub appid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pagepath: Option<String>,
+}
+
+impl NewsArticle {
+    pub fn new(title: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            description: None,
+            url: Some(url.into()),
+            pic_url: None,
+            appid: None,
+            pagepath: None,
+        }
+    }
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+    pub fn pic_url(mut self, pic_url: impl Into<String>) -> Self {
+        self.pic_url = Some(pic_url.into());
+        self
+    }
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MpnewsContent {
+    pub articles: Vec<MpnewsArticle>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MpnewsArticle {
+    pub title: String,
+    pub thumb_media_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_source_url: Option<String>,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MiniprogramNoticeContent {
+    pub appid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<String>,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emphasis_first_item: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_item: Option<Vec<KeyValue>>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
 }
-#[derive(Serialize, Deserialize, Debug)]
-struct MediaContent {
-    media_id: String,
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskcardContent {
+    pub title: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub task_id: String,
+    pub btn: Vec<TaskcardButton>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskcardButton {
+    pub key: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    title: Option<String>,
+    pub color: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    description: Option<String>,
-}
-#[derive(Serialize, Deserialize, Debug)]
-struct TextCardContent {
-    title: String,
-    description: String,
-    url: String,
-    #[serde(rename = "btntxt")]
-    btn_txt: String,
-}
-#[derive(Serialize, Deserialize, Debug)]
-struct NewsContent {
-    articles: Vec<NewsArticle>,
-}
-#[derive(Serialize, Deserialize, Debug)]
-struct NewsArticle {
-    title: String,
-    description: String,
-    url: String,
-    #[serde(rename = "picurl")]
-    pic_url: String,
-}
-// #[derive(Serialize, Deserialize, Debug)]
-// struct MpnewsContent {
-//     articles: Vec<MpArticle>,
-// }
-// #[derive(Serialize, Deserialize, Debug)]
-// struct MpArticle {
-//     title: String,
-//     thumb_media_id: String,
-// }
-
-#[derive(Serialize, Deserialize, Debug)]
+    pub is_bold: Option<bool>,
+}
+
+#[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum SendMsgReq {
     Text(SendTextMsgReq),
@@ -138,11 +213,52 @@ pub enum SendMsgReq {
     Markdown(SendMarkdownMsgReq),
     TextCard(SendTextCardMsgReq),
     News(SendNewsMsgReq),
-    // Mpnews(SendMpnewsMsgReq),
+    Mpnews(SendMpnewsMsgReq),
+    MiniprogramNotice(SendMiniprogramNoticeMsgReq),
+    Taskcard(SendTaskcardMsgReq),
+    InteractiveTaskcard(SendInteractiveTaskcardMsgReq),
+    TemplateCard(SendTemplateCardMsgReq),
+}
+
+// 先读取 msgtype 再反序列化为对应的消息，未知类型直接报错
+impl<'de> Deserialize<'de> for SendMsgReq {
+    fn deserialize<D>(deserializer: D) -> Result<SendMsgReq, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let v = serde_json::Value::deserialize(deserializer)?;
+        let msg_type = v
+            .get("msgtype")
+            .and_then(|t| t.as_str())
+            .ok_or_else(|| D::Error::missing_field("msgtype"))?
+            .parse::<MsgType>()
+            .map_err(D::Error::custom)?;
+        let r = match msg_type {
+            MsgType::Text => serde_json::from_value(v).map(SendMsgReq::Text),
+            MsgType::Image => serde_json::from_value(v).map(SendMsgReq::Image),
+            MsgType::Voice => serde_json::from_value(v).map(SendMsgReq::Voice),
+            MsgType::Video => serde_json::from_value(v).map(SendMsgReq::Video),
+            MsgType::File => serde_json::from_value(v).map(SendMsgReq::File),
+            MsgType::Markdown => serde_json::from_value(v).map(SendMsgReq::Markdown),
+            MsgType::TextCard => serde_json::from_value(v).map(SendMsgReq::TextCard),
+            MsgType::News => serde_json::from_value(v).map(SendMsgReq::News),
+            MsgType::Mpnews => serde_json::from_value(v).map(SendMsgReq::Mpnews),
+            MsgType::MiniprogramNotice => {
+                serde_json::from_value(v).map(SendMsgReq::MiniprogramNotice)
+            }
+            MsgType::Taskcard => serde_json::from_value(v).map(SendMsgReq::Taskcard),
+            MsgType::InteractiveTaskcard => {
+                serde_json::from_value(v).map(SendMsgReq::InteractiveTaskcard)
+            }
+            MsgType::TemplateCard => serde_json::from_value(v).map(SendMsgReq::TemplateCard),
+        };
+        r.map_err(|e| D::Error::custom(format!("{} 消息格式错误: {}", msg_type, e)))
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct SendMsgCommon {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendMsgCommon {
     #[serde(rename = "touser", skip_serializing_if = "Option::is_none")]
     pub to_user: Option<String>,
     #[serde(rename = "toparty", skip_serializing_if = "Option::is_none")]
@@ -154,70 +270,352 @@ struct SendMsgCommon {
     #[serde(rename = "agentid", default)]
     pub agent_id: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
-    safe: Option<i8>,
+    pub safe: Option<i8>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    enable_id_trans: Option<i8>,
+    pub enable_id_trans: Option<i8>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    enable_duplicate_check: Option<i8>,
+    pub enable_duplicate_check: Option<i8>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    duplicate_check_interval: Option<i32>,
+    pub duplicate_check_interval: Option<i32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl SendMsgCommon {
+    fn new(msg_type: MsgType) -> Self {
+        Self {
+            to_user: None,
+            to_party: None,
+            to_tag: None,
+            msg_type,
+            agent_id: 0,
+            safe: None,
+            enable_id_trans: None,
+            enable_duplicate_check: None,
+            duplicate_check_interval: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendImageMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    image: MediaContent,
+    pub common: SendMsgCommon,
+    pub image: MediaContent,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendTextMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    text: TextContent,
+    pub common: SendMsgCommon,
+    pub text: TextContent,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendVoiceMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    voice: MediaContent,
+    pub common: SendMsgCommon,
+    pub voice: MediaContent,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendVideoMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    video: MediaContent,
+    pub common: SendMsgCommon,
+    pub video: MediaContent,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendFileMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    file: MediaContent,
+    pub common: SendMsgCommon,
+    pub file: MediaContent,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendMarkdownMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    markdown: TextContent,
+    pub common: SendMsgCommon,
+    pub markdown: TextContent,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendTextCardMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    textcard: TextCardContent,
+    pub common: SendMsgCommon,
+    pub textcard: TextCardContent,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SendNewsMsgReq {
     #[serde(flatten)]
-    common: SendMsgCommon,
-    news: NewsContent,
+    pub common: SendMsgCommon,
+    pub news: NewsContent,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendMpnewsMsgReq {
+    #[serde(flatten)]
+    pub common: SendMsgCommon,
+    pub mpnews: MpnewsContent,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendMiniprogramNoticeMsgReq {
+    #[serde(flatten)]
+    pub common: SendMsgCommon,
+    pub miniprogram_notice: MiniprogramNoticeContent,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendTaskcardMsgReq {
+    #[serde(flatten)]
+    pub common: SendMsgCommon,
+    pub taskcard: TaskcardContent,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendInteractiveTaskcardMsgReq {
+    #[serde(flatten)]
+    pub common: SendMsgCommon,
+    pub interactive_taskcard: TaskcardContent,
+}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SendTemplateCardMsgReq {
+    #[serde(flatten)]
+    pub common: SendMsgCommon,
+    pub template_card: Box<TemplateCard>,
+}
+
+fn media(media_id: impl Into<String>) -> MediaContent {
+    MediaContent {
+        media_id: media_id.into(),
+        title: None,
+        description: None,
+    }
+}
+
+// 构造消息
+impl SendMsgReq {
+    pub fn text(content: impl Into<String>) -> Self {
+        SendMsgReq::Text(SendTextMsgReq {
+            common: SendMsgCommon::new(MsgType::Text),
+            text: TextContent {
+                content: content.into(),
+            },
+        })
+    }
+    pub fn markdown(content: impl Into<String>) -> Self {
+        SendMsgReq::Markdown(SendMarkdownMsgReq {
+            common: SendMsgCommon::new(MsgType::Markdown),
+            markdown: TextContent {
+                content: content.into(),
+            },
+        })
+    }
+    pub fn image(media_id: impl Into<String>) -> Self {
+        SendMsgReq::Image(SendImageMsgReq {
+            common: SendMsgCommon::new(MsgType::Image),
+            image: media(media_id),
+        })
+    }
+    pub fn voice(media_id: impl Into<String>) -> Self {
+        SendMsgReq::Voice(SendVoiceMsgReq {
+            common: SendMsgCommon::new(MsgType::Voice),
+            voice: media(media_id),
+        })
+    }
+    pub fn video(
+        media_id: impl Into<String>,
+        title: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        SendMsgReq::Video(SendVideoMsgReq {
+            common: SendMsgCommon::new(MsgType::Video),
+            video: MediaContent {
+                media_id: media_id.into(),
+                title: Some(title.into()),
+                description: Some(description.into()),
+            },
+        })
+    }
+    pub fn file(media_id: impl Into<String>) -> Self {
+        SendMsgReq::File(SendFileMsgReq {
+            common: SendMsgCommon::new(MsgType::File),
+            file: media(media_id),
+        })
+    }
+    pub fn textcard(
+        title: impl Into<String>,
+        description: impl Into<String>,
+        url: impl Into<String>,
+    ) -> Self {
+        SendMsgReq::TextCard(SendTextCardMsgReq {
+            common: SendMsgCommon::new(MsgType::TextCard),
+            textcard: TextCardContent {
+                title: title.into(),
+                description: description.into(),
+                url: url.into(),
+                btn_txt: None,
+            },
+        })
+    }
+    pub fn news(articles: Vec<NewsArticle>) -> Self {
+        SendMsgReq::News(SendNewsMsgReq {
+            common: SendMsgCommon::new(MsgType::News),
+            news: NewsContent { articles },
+        })
+    }
+    pub fn mpnews(articles: Vec<MpnewsArticle>) -> Self {
+        SendMsgReq::Mpnews(SendMpnewsMsgReq {
+            common: SendMsgCommon::new(MsgType::Mpnews),
+            mpnews: MpnewsContent { articles },
+        })
+    }
+    pub fn miniprogram_notice(notice: MiniprogramNoticeContent) -> Self {
+        SendMsgReq::MiniprogramNotice(SendMiniprogramNoticeMsgReq {
+            common: SendMsgCommon::new(MsgType::MiniprogramNotice),
+            miniprogram_notice: notice,
+        })
+    }
+    pub fn taskcard(taskcard: TaskcardContent) -> Self {
+        SendMsgReq::Taskcard(SendTaskcardMsgReq {
+            common: SendMsgCommon::new(MsgType::Taskcard),
+            taskcard,
+        })
+    }
+    pub fn interactive_taskcard(taskcard: TaskcardContent) -> Self {
+        SendMsgReq::InteractiveTaskcard(SendInteractiveTaskcardMsgReq {
+            common: SendMsgCommon::new(MsgType::InteractiveTaskcard),
+            interactive_taskcard: taskcard,
+        })
+    }
+    pub fn template_card(card: TemplateCard) -> Self {
+        SendMsgReq::TemplateCard(SendTemplateCardMsgReq {
+            common: SendMsgCommon::new(MsgType::TemplateCard),
+            template_card: Box::new(card),
+        })
+    }
+
+    /// 接收成员，多个成员用 `|` 连接，`@all` 表示全部成员
+    pub fn to_users<I, S>(mut self, users: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.common_mut().to_user = join_ids(users);
+        self
+    }
+    pub fn to_parties<I, S>(mut self, parties: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.common_mut().to_party = join_ids(parties);
+        self
+    }
+    pub fn to_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.common_mut().to_tag = join_ids(tags);
+        self
+    }
+    pub fn to_all(self) -> Self {
+        self.to_users(["@all"])
+    }
+    /// 保密消息，仅 text / image / voice / video / file / textcard / mpnews 有效
+    pub fn safe(mut self) -> Self {
+        self.common_mut().safe = Some(1);
+        self
+    }
+    pub fn enable_id_trans(mut self) -> Self {
+        self.common_mut().enable_id_trans = Some(1);
+        self
+    }
+    /// 开启重复消息检查，`interval` 为检查间隔秒数，默认 1800s
+    pub fn duplicate_check(mut self, interval: Option<i32>) -> Self {
+        let c = self.common_mut();
+        c.enable_duplicate_check = Some(1);
+        c.duplicate_check_interval = interval;
+        self
+    }
+    /// textcard 按钮文字，默认为“详情”
+    pub fn btn_txt(mut self, btn_txt: impl Into<String>) -> Self {
+        if let SendMsgReq::TextCard(q) = &mut self {
+            q.textcard.btn_txt = Some(btn_txt.into());
+        }
+        self
+    }
+
+    pub fn msg_type(&self) -> MsgType {
+        match self {
+            SendMsgReq::Text(_) => MsgType::Text,
+            SendMsgReq::Image(_) => MsgType::Image,
+            SendMsgReq::Voice(_) => MsgType::Voice,
+            SendMsgReq::Video(_) => MsgType::Video,
+            SendMsgReq::File(_) => MsgType::File,
+            SendMsgReq::Markdown(_) => MsgType::Markdown,
+            SendMsgReq::TextCard(_) => MsgType::TextCard,
+            SendMsgReq::News(_) => MsgType::News,
+            SendMsgReq::Mpnews(_) => MsgType::Mpnews,
+            SendMsgReq::MiniprogramNotice(_) => MsgType::MiniprogramNotice,
+            SendMsgReq::Taskcard(_) => MsgType::Taskcard,
+            SendMsgReq::InteractiveTaskcard(_) => MsgType::InteractiveTaskcard,
+            SendMsgReq::TemplateCard(_) => MsgType::TemplateCard,
+        }
+    }
+    pub fn common(&self) -> &SendMsgCommon {
+        match self {
+            SendMsgReq::Text(q) => &q.common,
+            SendMsgReq::Image(q) => &q.common,
+            SendMsgReq::Voice(q) => &q.common,
+            SendMsgReq::Video(q) => &q.common,
+            SendMsgReq::File(q) => &q.common,
+            SendMsgReq::Markdown(q) => &q.common,
+            SendMsgReq::TextCard(q) => &q.common,
+            SendMsgReq::News(q) => &q.common,
+            SendMsgReq::Mpnews(q) => &q.common,
+            SendMsgReq::MiniprogramNotice(q) => &q.common,
+            SendMsgReq::Taskcard(q) => &q.common,
+            SendMsgReq::InteractiveTaskcard(q) => &q.common,
+            SendMsgReq::TemplateCard(q) => &q.common,
+        }
+    }
+    fn common_mut(&mut self) -> &mut SendMsgCommon {
+        match self {
+            SendMsgReq::Text(q) => &mut q.common,
+            SendMsgReq::Image(q) => &mut q.common,
+            SendMsgReq::Voice(q) => &mut q.common,
+            SendMsgReq::Video(q) => &mut q.common,
+            SendMsgReq::File(q) => &mut q.common,
+            SendMsgReq::Markdown(q) => &mut q.common,
+            SendMsgReq::TextCard(q) => &mut q.common,
+            SendMsgReq::News(q) => &mut q.common,
+            SendMsgReq::Mpnews(q) => &mut q.common,
+            SendMsgReq::MiniprogramNotice(q) => &mut q.common,
+            SendMsgReq::Taskcard(q) => &mut q.common,
+            SendMsgReq::InteractiveTaskcard(q) => &mut q.common,
+            SendMsgReq::TemplateCard(q) => &mut q.common,
+        }
+    }
+}
+
+fn join_ids<I, S>(ids: I) -> Option<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let s = ids
+        .into_iter()
+        .map(|s| s.as_ref().to_string())
+        .collect::<Vec<_>>()
+        .join("|");
+    if s.is_empty() {
+        None
+    } else {
+        Some(s)
+    }
+}
+
+fn split_ids(s: &Option<String>) -> Vec<String> {
+    s.as_deref()
+        .unwrap_or("")
+        .split('|')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
 }
-// #[derive(Serialize, Deserialize, Debug)]
-// pub struct SendMpnewsMsgReq {
-//     #[serde(flatten)]
-//     common: SendMsgCommon,
-//     mpnews: ,
-// }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SendMsgResponse {
@@ -225,66 +623,172 @@ pub struct SendMsgResponse {
     err_code: i32, //	返回码
     #[serde(rename = "errmsg")]
     err_msg: String, //	对返回码的文本描述内容
-    // invaliduser	不合法的userid，不区分大小写，统一转为小写
-    // invalidparty	不合法的partyid
-    // invalidtag	不合法的标签id
-    // unlicenseduser	没有基础接口许可(包含已过期)的userid
-    #[serde(rename = "msgid")]
+    #[serde(rename = "invaliduser", default)]
+    invalid_user: Option<String>, // 不合法的userid，不区分大小写，统一转为小写
+    #[serde(rename = "invalidparty", default)]
+    invalid_party: Option<String>, // 不合法的partyid
+    #[serde(rename = "invalidtag", default)]
+    invalid_tag: Option<String>, // 不合法的标签id
+    #[serde(rename = "unlicenseduser", default)]
+    unlicensed_user: Option<String>, // 没有基础接口许可(包含已过期)的userid
+    #[serde(rename = "msgid", default)]
     msg_id: Option<String>, //消息id，用于撤回应用消息
-                            // response_code	仅消息类型为“按钮交互型”，“投票选择型”和“多项选择型”的模板卡片消息返回，应用可使用response_code调用更新模版卡片消息接口，72小时内有效，且只能使用一次
+    #[serde(default)]
+    response_code: Option<String>, // 仅消息类型为“按钮交互型”，“投票选择型”和“多项选择型”的模板卡片消息返回，应用可使用response_code调用更新模版卡片消息接口，72小时内有效，且只能使用一次
 }
-pub async fn send_msg(
+
+fn serialize_ids<S>(ids: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&ids.join("|"))
+}
+
+/// 发送应用消息的结果，部分接收人无效时消息仍会发送成功，
+/// 序列化后与企业微信接口的返回格式一致
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SendResult {
+    #[serde(rename = "errcode")]
+    pub err_code: i32,
+    #[serde(rename = "errmsg")]
+    pub err_msg: String,
+    #[serde(
+        rename = "invaliduser",
+        serialize_with = "serialize_ids",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub invalid_users: Vec<String>,
+    #[serde(
+        rename = "invalidparty",
+        serialize_with = "serialize_ids",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub invalid_parties: Vec<String>,
+    #[serde(
+        rename = "invalidtag",
+        serialize_with = "serialize_ids",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub invalid_tags: Vec<String>,
+    #[serde(
+        rename = "unlicenseduser",
+        serialize_with = "serialize_ids",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub unlicensed_users: Vec<String>,
+    #[serde(rename = "msgid", skip_serializing_if = "String::is_empty")]
+    pub msg_id: String,
+    /// 交互型模板卡片的 response_code，用于更新卡片
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_code: Option<String>,
+}
+
+impl From<SendMsgResponse> for SendResult {
+    fn from(r: SendMsgResponse) -> Self {
+        Self {
+            invalid_users: split_ids(&r.invalid_user),
+            invalid_parties: split_ids(&r.invalid_party),
+            invalid_tags: split_ids(&r.invalid_tag),
+            unlicensed_users: split_ids(&r.unlicensed_user),
+            msg_id: r.msg_id.unwrap_or_default(),
+            response_code: r.response_code.filter(|c| !c.is_empty()),
+            err_code: r.err_code,
+            err_msg: r.err_msg,
+        }
+    }
+}
+
+pub async fn send(
     client: &reqwest::Client,
+    api_base: &str,
     token: &str,
     agent_id: i64,
-    msg: &str,
-) -> Result<String> {
-    let body = match serde_json::from_str::<SendMsgReq>(msg)? {
-        SendMsgReq::Text(mut q) => {
-            q.common.msg_type = MsgType::Text;
-            q.common.agent_id = agent_id;
-            SendMsgReq::Text(q)
-        }
-        SendMsgReq::Image(mut q) => {
-            q.common.msg_type = MsgType::Image;
-            q.common.agent_id = agent_id;
-            SendMsgReq::Image(q)
-        }
-        SendMsgReq::Voice(mut q) => {
-            q.common.msg_type = MsgType::Voice;
-            q.common.agent_id = agent_id;
-            SendMsgReq::Voice(q)
-        }
-        SendMsgReq::Video(mut q) => {
-            q.common.msg_type = MsgType::Video;
-            q.common.agent_id = agent_id;
-            SendMsgReq::Video(q)
-        }
-        SendMsgReq::File(mut q) => {
-            q.common.msg_type = MsgType::File;
-            q.common.agent_id = agent_id;
-            SendMsgReq::File(q)
-        }
-        SendMsgReq::Markdown(mut q) => {
-            q.common.msg_type = MsgType::Markdown;
-            q.common.agent_id = agent_id;
-            SendMsgReq::Markdown(q)
-        }
-        SendMsgReq::TextCard(mut q) => {
-            q.common.msg_type = MsgType::TextCard;
-            q.common.agent_id = agent_id;
-            SendMsgReq::TextCard(q)
-        }
-        SendMsgReq::News(mut q) => {
-            q.common.msg_type = MsgType::News;
-            q.common.agent_id = agent_id;
-            SendMsgReq::News(q)
+    msg: SendMsgReq,
+) -> Result<SendResult> {
+    let res = send_raw(client, api_base, token, agent_id, msg).await?;
+    if res.err_code != 0 {
+        return Err(WecomError::new("发送消息失败", res.err_code, &res.err_msg).into());
+    }
+    Ok(res)
+}
+
+/// 企业微信返回的错误码原样保留在结果中
+pub async fn send_raw(
+    client: &reqwest::Client,
+    api_base: &str,
+    token: &str,
+    agent_id: i64,
+    mut msg: SendMsgReq,
+) -> Result<SendResult> {
+    let msg_type = msg.msg_type();
+    let common = msg.common_mut();
+    common.msg_type = msg_type;
+    common.agent_id = agent_id;
+
+    let api = format!("{}/cgi-bin/message/send?access_token={}", api_base, token);
+
+    let res = client
+        .post(api)
+        .body(serde_json::to_string(&msg)?)
+        .send()
+        .await?
+        .json::<SendMsgResponse>()
+        .await?;
+
+    Ok(res.into())
+}
+
+#[derive(Serialize, Debug)]
+struct ReplaceButton<'a> {
+    replace_name: &'a str,
+}
+#[derive(Serialize, Debug)]
+struct UpdateTemplateCardReq<'a> {
+    atall: i8,
+    #[serde(rename = "agentid")]
+    agent_id: i64,
+    response_code: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    button: Option<ReplaceButton<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template_card: Option<&'a TemplateCard>,
+}
+
+impl<'a> UpdateTemplateCardReq<'a> {
+    fn new(agent_id: i64, response_code: &'a str, update: &'a CardUpdate) -> Self {
+        let mut body = UpdateTemplateCardReq {
+            atall: 1,
+            agent_id,
+            response_code,
+            button: None,
+            template_card: None,
+        };
+        match update {
+            CardUpdate::Button { replace_name } => {
+                body.button = Some(ReplaceButton { replace_name });
+            }
+            CardUpdate::Card(card) => {
+                body.template_card = Some(card);
+            }
         }
-    };
+        body
+    }
+}
+
+/// 更新模版卡片消息，response_code 只能使用一次，更新该卡片的全部接收人
+pub async fn update_template_card(
+    client: &reqwest::Client,
+    api_base: &str,
+    token: &str,
+    agent_id: i64,
+    response_code: &str,
+    update: &CardUpdate,
+) -> Result<()> {
+    let body = UpdateTemplateCardReq::new(agent_id, response_code, update);
 
     let api = format!(
-        "https://qyapi.weixin.qq.com/cgi-bin/message/send?access_token={}",
-        token
+        "{}/cgi-bin/message/update_template_card?access_token={}",
+        api_base, token
     );
 
     let res = client
@@ -295,23 +799,21 @@ pub async fn send_msg(
         .json::<SendMsgResponse>()
         .await?;
     if res.err_code != 0 {
-        return Err(anyhow!(
-            "发送消息失败 error: [{}] {}",
-            res.err_code,
-            res.err_msg
-        ));
+        return Err(WecomError::new("更新模板卡片失败", res.err_code, &res.err_msg).into());
     }
 
-    Ok(res.msg_id.unwrap_or("".to_string()))
+    Ok(())
 }
 
-pub async fn recall_msg(client: &reqwest::Client, token: &str, msg_id: &str) -> Result<()> {
+pub async fn recall_msg(
+    client: &reqwest::Client,
+    api_base: &str,
+    token: &str,
+    msg_id: &str,
+) -> Result<()> {
     let body = serde_json::json!({ "msgid": msg_id });
 
-    let api = format!(
-        "https://qyapi.weixin.qq.com/cgi-bin/message/recall?access_token={}",
-        token
-    );
+    let api = format!("{}/cgi-bin/message/recall?access_token={}", api_base, token);
 
     let res = client
         .post(api)
@@ -321,11 +823,7 @@ pub async fn recall_msg(client: &reqwest::Client, token: &str, msg_id: &str) ->
         .json::<SendMsgResponse>()
         .await?;
     if res.err_code != 0 {
-        return Err(anyhow!(
-            "撤回消息失败 error: [{}] {}",
-            res.err_code,
-            res.err_msg
-        ));
+        return Err(WecomError::new("撤回消息失败", res.err_code, &res.err_msg).into());
     }
 
     Ok(())
@@ -335,6 +833,7 @@ pub async fn recall_msg(client: &reqwest::Client, token: &str, msg_id: &str) ->
 mod test {
     use super::*;
     use crate::backend::mp::client::get_access_token;
+    use crate::backend::mp::DEFAULT_API_BASE;
     use crate::backend::Config;
     use assert_json_diff::assert_json_eq;
     use serde_json::json;
@@ -346,8 +845,20 @@ mod test {
         let contents = fs::read_to_string("./config.toml").expect("读取配置失败");
         let serv_conf: Config = toml::from_str(contents.as_str()).unwrap();
 
-        let (token, _) = dbg!(get_access_token(&serv_conf.corp_id, &serv_conf.corp_secret).await?);
-        send_msg(&reqwest::Client::new(),&token, serv_conf.agent_id, r#"{ "touser" : "SongSong", "msgtype" : "text", "agentid" : 1, "text" : { "content" : "content" } }"#).await?;
+        let (token, _) = dbg!(
+            get_access_token(DEFAULT_API_BASE, &serv_conf.corp_id, &serv_conf.corp_secret).await?
+        );
+        let msg = serde_json::from_str::<SendMsgReq>(
+            r#"{ "touser" : "SongSong", "msgtype" : "text", "agentid" : 1, "text" : { "content" : "content" } }"#,
+        )?;
+        send_raw(
+            &reqwest::Client::new(),
+            DEFAULT_API_BASE,
+            &token,
+            serv_conf.agent_id,
+            msg,
+        )
+        .await?;
         Ok(())
     }
 
@@ -436,4 +947,165 @@ mod test {
             assert_json_eq!(vl, vr);
         }
     }
+
+    #[test]
+    fn test_builder() {
+        let cases = vec![
+            (
+                SendMsgReq::text("content").to_users(["a", "b"]).safe(),
+                json!({"touser": "a|b", "msgtype": "text", "agentid": 0, "safe": 1, "text": {"content": "content"}}),
+            ),
+            (
+                SendMsgReq::markdown("**md**")
+                    .to_parties(["1", "2"])
+                    .to_tags(["3"])
+                    .duplicate_check(Some(600)),
+                json!({"toparty": "1|2", "totag": "3", "msgtype": "markdown", "agentid": 0, "enable_duplicate_check": 1, "duplicate_check_interval": 600, "markdown": {"content": "**md**"}}),
+            ),
+            (
+                SendMsgReq::textcard("title", "desc", "https://example.com")
+                    .btn_txt("更多")
+                    .to_all(),
+                json!({"touser": "@all", "msgtype": "textcard", "agentid": 0, "textcard": {"title": "title", "description": "desc", "url": "https://example.com", "btntxt": "更多"}}),
+            ),
+            (
+                SendMsgReq::news(vec![NewsArticle::new("title", "https://example.com")
+                    .description("desc")
+                    .pic_url("https://example.com/a.png")])
+                .to_users(["a"]),
+                json!({"touser": "a", "msgtype": "news", "agentid": 0, "news": {"articles": [{"title": "title", "description": "desc", "url": "https://example.com", "picurl": "https://example.com/a.png"}]}}),
+            ),
+            (
+                SendMsgReq::taskcard(TaskcardContent {
+                    title: "title".to_string(),
+                    description: "desc".to_string(),
+                    url: None,
+                    task_id: "task".to_string(),
+                    btn: vec![TaskcardButton {
+                        key: "ok".to_string(),
+                        name: "批准".to_string(),
+                        replace_name: Some("已批准".to_string()),
+                        color: None,
+                        is_bold: None,
+                    }],
+                })
+                .to_users(["a"]),
+                json!({"touser": "a", "msgtype": "taskcard", "agentid": 0, "taskcard": {"title": "title", "description": "desc", "task_id": "task", "btn": [{"key": "ok", "name": "批准", "replace_name": "已批准"}]}}),
+            ),
+            (
+                SendMsgReq::file("MEDIA_ID").to_users(Vec::<String>::new()),
+                json!({"msgtype": "file", "agentid": 0, "file": {"media_id": "MEDIA_ID"}}),
+            ),
+        ];
+        for (msg, expected) in cases {
+            assert_json_eq!(serde_json::to_value(&msg).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_send_result() {
+        let res = serde_json::from_str::<SendMsgResponse>(
+            r#"{"errcode": 0, "errmsg": "ok", "invaliduser": "userid1|userid2", "invalidparty": "", "msgid": "xx"}"#,
+        )
+        .unwrap();
+        let res = SendResult::from(res);
+        assert_eq!(res.msg_id, "xx");
+        assert_eq!(res.invalid_users, vec!["userid1", "userid2"]);
+        assert!(res.invalid_parties.is_empty());
+        assert!(res.invalid_tags.is_empty());
+    }
+
+    #[test]
+    fn test_send_result_json() {
+        let raw = json!({
+            "errcode": 81013,
+            "errmsg": "user & party & tag all invalid",
+            "invaliduser": "userid1|userid2",
+            "invalidparty": "partyid1",
+            "invalidtag": "tagid1",
+            "unlicenseduser": "userid3",
+            "msgid": ""
+        });
+        let res = SendResult::from(serde_json::from_value::<SendMsgResponse>(raw).unwrap());
+        assert_eq!(res.unlicensed_users, vec!["userid3"]);
+        assert_json_eq!(
+            serde_json::to_value(&res).unwrap(),
+            json!({
+                "errcode": 81013,
+                "errmsg": "user & party & tag all invalid",
+                "invaliduser": "userid1|userid2",
+                "invalidparty": "partyid1",
+                "invalidtag": "tagid1",
+                "unlicenseduser": "userid3"
+            })
+        );
+    }
+
+    #[test]
+    fn test_more_msg_types() {
+        let cases = vec![
+            r#"{"touser":"a","msgtype":"mpnews","agentid":1,"mpnews":{"articles":[{"title":"Title","thumb_media_id":"MEDIA_ID","author":"Author","content_source_url":"URL","content":"Content","digest":"Digest description"}]},"safe":0}"#,
+            r#"{"touser":"a","msgtype":"miniprogram_notice","agentid":1,"miniprogram_notice":{"appid":"wx123123123123123","page":"pages/index?userid=zhangsan&orderid=123123123","title":"会议室预订成功通知","description":"4月27日 16:16","emphasis_first_item":true,"content_item":[{"key":"会议室","value":"402"}]},"enable_id_trans":0}"#,
+            r#"{"touser":"a","msgtype":"interactive_taskcard","agentid":1,"interactive_taskcard":{"title":"赵明登的礼物申请","description":"礼品：A31茶具套装","url":"URL","task_id":"taskid123","btn":[{"key":"key111","name":"批准","color":"red","is_bold":true},{"key":"key222","name":"驳回"}]}}"#,
+            r#"{"touser":"a","msgtype":"template_card","agentid":1,"template_card":{"card_type":"vote_interaction","source":{"icon_url":"图片的url","desc":"企业微信"},"main_title":{"title":"欢迎使用企业微信"},"task_id":"task_id","checkbox":{"question_key":"question_key1","option_list":[{"id":"option_id1","text":"选择题选项1","is_checked":true}],"mode":1},"submit_button":{"text":"提交","key":"key"}}}"#,
+        ];
+        for x in cases {
+            let t = serde_json::from_str::<SendMsgReq>(x).unwrap();
+            let vl = serde_json::to_value(&t).unwrap();
+            let vr = serde_json::from_str::<serde_json::Value>(x).unwrap();
+            assert_json_eq!(vl, vr);
+        }
+    }
+
+    #[test]
+    fn test_unknown_msg_type() {
+        let e = serde_json::from_str::<SendMsgReq>(
+            r#"{"touser":"a","msgtype":"unknown","text":{"content":"content"}}"#,
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("msgtype=unknown"), "{}", e);
+        let e = serde_json::from_str::<SendMsgReq>(r#"{"touser":"a","text":{"content":"c"}}"#)
+            .unwrap_err();
+        assert!(e.to_string().contains("msgtype"), "{}", e);
+        // msgtype 与内容不一致时不再按其他类型解析
+        assert!(serde_json::from_str::<SendMsgReq>(
+            r#"{"touser":"a","msgtype":"image","text":{"content":"c"}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_response_code() {
+        let res = serde_json::from_str::<SendMsgResponse>(
+            r#"{"errcode":0,"errmsg":"ok","invaliduser":"","msgid":"xx","response_code":"xyzxyz"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            SendResult::from(res).response_code.as_deref(),
+            Some("xyzxyz")
+        );
+    }
+
+    #[test]
+    fn test_update_template_card_req() {
+        let card = TemplateCard::new(crate::backend::mp::CardType::ButtonInteraction)
+            .task_id("task_id")
+            .replace_text("已处理");
+        let cases = vec![
+            (
+                CardUpdate::Button {
+                    replace_name: "已确认".to_string(),
+                },
+                json!({"atall": 1, "agentid": 1, "response_code": "code", "button": {"replace_name": "已确认"}}),
+            ),
+            (
+                CardUpdate::Card(Box::new(card)),
+                json!({"atall": 1, "agentid": 1, "response_code": "code", "template_card": {"card_type": "button_interaction", "task_id": "task_id", "replace_text": "已处理"}}),
+            ),
+        ];
+        for (update, expected) in cases {
+            let body = UpdateTemplateCardReq::new(1, "code", &update);
+            assert_json_eq!(serde_json::to_value(&body).unwrap(), expected);
+        }
+    }
 }
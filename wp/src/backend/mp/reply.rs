@@ -0,0 +1,207 @@
+use crate::backend::mp::NewsArticle;
+use anyhow::Result;
+use base64::Engine;
+use std::fmt::Write;
+use wechat_crypto::{calc_signature, encrypt};
+
+/// 被动回复消息，直接作为 `/wccb` 的响应返回给企业微信，不消耗发送消息的接口调用次数
+// https://developer.work.weixin.qq.com/document/path/90241
+#[derive(Debug, Clone, PartialEq)]
+pub enum PassiveReply {
+    Text(String),
+    Image {
+        media_id: String,
+    },
+    Voice {
+        media_id: String,
+    },
+    Video {
+        media_id: String,
+        title: Option<String>,
+        description: Option<String>,
+    },
+    News(Vec<NewsArticle>),
+    /// 更新点击用户的模板卡片按钮
+    UpdateButton {
+        replace_name: String,
+    },
+}
+
+impl PassiveReply {
+    pub fn text(content: impl Into<String>) -> Self {
+        PassiveReply::Text(content.into())
+    }
+
+    pub fn msg_type(&self) -> &'static str {
+        match self {
+            PassiveReply::Text(_) => "text",
+            PassiveReply::Image { .. } => "image",
+            PassiveReply::Voice { .. } => "voice",
+            PassiveReply::Video { .. } => "video",
+            PassiveReply::News(_) => "news",
+            PassiveReply::UpdateButton { .. } => "update_button",
+        }
+    }
+
+    /// 明文 XML，`to_user` 为成员 UserID，`from_user` 为企业 CorpID
+    pub fn to_xml(&self, to_user: &str, from_user: &str, create_time: i64) -> String {
+        let mut s = String::from("<xml>");
+        cdata(&mut s, "ToUserName", to_user);
+        cdata(&mut s, "FromUserName", from_user);
+        let _ = write!(s, "<CreateTime>{}</CreateTime>", create_time);
+        cdata(&mut s, "MsgType", self.msg_type());
+        match self {
+            PassiveReply::Text(content) => cdata(&mut s, "Content", content),
+            PassiveReply::Image { media_id } => {
+                s.push_str("<Image>");
+                cdata(&mut s, "MediaId", media_id);
+                s.push_str("</Image>");
+            }
+            PassiveReply::Voice { media_id } => {
+                s.push_str("<Voice>");
+                cdata(&mut s, "MediaId", media_id);
+                s.push_str("</Voice>");
+            }
+            PassiveReply::Video {
+                media_id,
+                title,
+                description,
+            } => {
+                s.push_str("<Video>");
+                cdata(&mut s, "MediaId", media_id);
+                if let Some(t) = title {
+                    cdata(&mut s, "Title", t);
+                }
+                if let Some(d) = description {
+                    cdata(&mut s, "Description", d);
+                }
+                s.push_str("</Video>");
+            }
+            PassiveReply::News(articles) => {
+                let _ = write!(s, "<ArticleCount>{}</ArticleCount>", articles.len());
+                s.push_str("<Articles>");
+                for a in articles {
+                    s.push_str("<item>");
+                    cdata(&mut s, "Title", &a.title);
+                    if let Some(d) = &a.description {
+                        cdata(&mut s, "Description", d);
+                    }
+                    if let Some(p) = &a.pic_url {
+                        cdata(&mut s, "PicUrl", p);
+                    }
+                    if let Some(u) = &a.url {
+                        cdata(&mut s, "Url", u);
+                    }
+                    s.push_str("</item>");
+                }
+                s.push_str("</Articles>");
+            }
+            PassiveReply::UpdateButton { replace_name } => {
+                s.push_str("<Button>");
+                cdata(&mut s, "ReplaceName", replace_name);
+                s.push_str("</Button>");
+            }
+        }
+        s.push_str("</xml>");
+        s
+    }
+}
+
+// 内容中的 "]]>" 会提前结束 CDATA，拆分到两个 CDATA 中
+fn cdata(s: &mut String, tag: &str, v: &str) {
+    let _ = write!(
+        s,
+        "<{tag}><![CDATA[{}]]></{tag}>",
+        v.replace("]]>", "]]]]><![CDATA[>")
+    );
+}
+
+/// 加密后的响应包
+pub fn encrypt_reply(
+    key: &[u8],
+    corp_id: &str,
+    token: &str,
+    plaintext: &str,
+    timestamp: i64,
+    nonce: i64,
+) -> Result<String> {
+    let encrypted =
+        base64::engine::general_purpose::STANDARD.encode(encrypt(key, plaintext, corp_id)?);
+    let ts = timestamp.to_string();
+    let nonce = nonce.to_string();
+    let signature = calc_signature(token, &ts, &nonce, &encrypted);
+    let mut s = String::from("<xml>");
+    cdata(&mut s, "Encrypt", &encrypted);
+    cdata(&mut s, "MsgSignature", &signature);
+    let _ = write!(s, "<TimeStamp>{}</TimeStamp>", ts);
+    cdata(&mut s, "Nonce", &nonce);
+    s.push_str("</xml>");
+    Ok(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wechat_crypto::{decode_aes_key, decrypt, parse_plain_text};
+
+    #[test]
+    fn test_to_xml() {
+        assert_eq!(
+            PassiveReply::text("a]]>b").to_xml("u", "corp", 1),
+            "<xml><ToUserName><![CDATA[u]]></ToUserName><FromUserName><![CDATA[corp]]></FromUserName>\
+             <CreateTime>1</CreateTime><MsgType><![CDATA[text]]></MsgType>\
+             <Content><![CDATA[a]]]]><![CDATA[>b]]></Content></xml>"
+        );
+        assert_eq!(
+            PassiveReply::UpdateButton {
+                replace_name: "已确认".to_string()
+            }
+            .to_xml("u", "corp", 1),
+            "<xml><ToUserName><![CDATA[u]]></ToUserName><FromUserName><![CDATA[corp]]></FromUserName>\
+             <CreateTime>1</CreateTime><MsgType><![CDATA[update_button]]></MsgType>\
+             <Button><ReplaceName><![CDATA[已确认]]></ReplaceName></Button></xml>"
+        );
+        let news = PassiveReply::News(vec![NewsArticle::new("title", "https://example.com")
+            .description("desc")
+            .pic_url("https://example.com/a.png")])
+        .to_xml("u", "corp", 1);
+        assert!(news.contains("<ArticleCount>1</ArticleCount>"));
+        assert!(news.contains(
+            "<item><Title><![CDATA[title]]></Title><Description><![CDATA[desc]]></Description>\
+             <PicUrl><![CDATA[https://example.com/a.png]]></PicUrl>\
+             <Url><![CDATA[https://example.com]]></Url></item>"
+        ));
+    }
+
+    #[test]
+    fn test_encrypt_reply() -> Result<()> {
+        let key = decode_aes_key("kWxPEV2UEDyxWpmPdKC3F4dgPDmOvfKX1HGnEUDS1aQ")?;
+        let plaintext = PassiveReply::text("hello").to_xml("u", "wx49f0ab532d5d035a", 1);
+        let envelope = encrypt_reply(
+            &key,
+            "wx49f0ab532d5d035a",
+            "123456",
+            &plaintext,
+            1411525903,
+            461056294,
+        )?;
+
+        #[derive(serde::Deserialize)]
+        struct Envelope {
+            #[serde(rename = "Encrypt")]
+            encrypted: String,
+            #[serde(rename = "MsgSignature")]
+            signature: String,
+        }
+        let env: Envelope = quick_xml::de::from_str(&envelope)?;
+        assert_eq!(
+            env.signature,
+            calc_signature("123456", "1411525903", "461056294", &env.encrypted)
+        );
+        let b = base64::engine::general_purpose::STANDARD.decode(env.encrypted)?;
+        let (msg, corp_id) = parse_plain_text(&decrypt(&key, &b)?)?;
+        assert_eq!(msg, plaintext);
+        assert_eq!(corp_id, "wx49f0ab532d5d035a");
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use crate::backend::mp::msg::{SendMsgReq, TextContent};
+
+/// 企业微信文本消息内容的最大字节数
+pub const TEXT_LIMIT: usize = 2048;
+/// markdown 消息内容的最大字节数
+pub const MARKDOWN_LIMIT: usize = 4096;
+/// 为 `(12/34)` 这样的编号预留的字节数
+const NUMBER_RESERVE: usize = 16;
+
+/// 按段落切分，每部分不超过 `limit` 字节。代码块尽量保持完整，
+/// 超长的代码块按行切分后每部分重新加上围栏。切分出多个部分时在开头加上编号
+pub fn split(content: &str, limit: usize) -> Vec<String> {
+    if content.len() <= limit {
+        return vec![content.to_string()];
+    }
+    let budget = limit - NUMBER_RESERVE;
+    let mut parts = vec![];
+    let mut cur = String::new();
+    for block in blocks(content) {
+        for piece in fit(&block, budget) {
+            if !cur.is_empty() && cur.len() + 2 + piece.len() > budget {
+                parts.push(std::mem::take(&mut cur));
+            }
+            if !cur.is_empty() {
+                cur.push_str("\n\n");
+            }
+            cur.push_str(&piece);
+        }
+    }
+    if !cur.is_empty() {
+        parts.push(cur);
+    }
+    let n = parts.len();
+    if n <= 1 {
+        return parts;
+    }
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| format!("({}/{})\n{}", i + 1, n, p))
+        .collect()
+}
+
+/// 以空行分隔的段落，代码块中的空行不分隔
+fn blocks(content: &str) -> Vec<String> {
+    let mut r = vec![];
+    let mut cur: Vec<&str> = vec![];
+    let mut fenced = false;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            fenced = !fenced;
+        }
+        if !fenced && line.trim().is_empty() {
+            if !cur.is_empty() {
+                r.push(cur.join("\n"));
+                cur.clear();
+            }
+            continue;
+        }
+        cur.push(line);
+    }
+    if !cur.is_empty() {
+        r.push(cur.join("\n"));
+    }
+    r
+}
+
+/// 把一个段落切成不超过 `budget` 字节的部分
+fn fit(block: &str, budget: usize) -> Vec<String> {
+    if block.len() <= budget {
+        return vec![block.to_string()];
+    }
+    let lines = block.lines().collect::<Vec<_>>();
+    if !lines[0].trim_start().starts_with("```") || lines.len() < 2 {
+        return hard_split(block, budget);
+    }
+    let open = lines[0];
+    let inner = if lines.len() > 2 && lines[lines.len() - 1].trim_start().starts_with("```") {
+        &lines[1..lines.len() - 1]
+    } else {
+        // 没有结束围栏，比如回答被截断
+        &lines[1..]
+    };
+    // 每部分加上开始围栏、结束围栏和两个换行
+    let inner_budget = budget.saturating_sub(open.len() + 5).max(1);
+    hard_split(&inner.join("\n"), inner_budget)
+        .into_iter()
+        .map(|p| format!("{}\n{}\n```", open, p))
+        .collect()
+}
+
+/// 按行切分，单行超长时按字符切分，不会切断 UTF-8 字符
+fn hard_split(s: &str, budget: usize) -> Vec<String> {
+    let mut r = vec![];
+    let mut cur = String::new();
+    for line in s.lines() {
+        if !cur.is_empty() && cur.len() + 1 + line.len() > budget {
+            r.push(std::mem::take(&mut cur));
+        }
+        if line.len() > budget {
+            for c in line.chars() {
+                if cur.len() + c.len_utf8() > budget {
+                    r.push(std::mem::take(&mut cur));
+                }
+                cur.push(c);
+            }
+            continue;
+        }
+        if !cur.is_empty() {
+            cur.push('\n');
+        }
+        cur.push_str(line);
+    }
+    if !cur.is_empty() {
+        r.push(cur);
+    }
+    r
+}
+
+impl SendMsgReq {
+    /// 文本和 markdown 消息超出长度限制时拆成多条，其他消息原样返回
+    pub fn split(self) -> Vec<SendMsgReq> {
+        match self {
+            SendMsgReq::Text(m) => split(&m.text.content, TEXT_LIMIT)
+                .into_iter()
+                .map(|content| {
+                    let mut m = m.clone();
+                    m.text = TextContent { content };
+                    SendMsgReq::Text(m)
+                })
+                .collect(),
+            SendMsgReq::Markdown(m) => split(&m.markdown.content, MARKDOWN_LIMIT)
+                .into_iter()
+                .map(|content| {
+                    let mut m = m.clone();
+                    m.markdown = TextContent { content };
+                    SendMsgReq::Markdown(m)
+                })
+                .collect(),
+            m => vec![m],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("短消息", 100), vec!["短消息"]);
+
+        let para = "中".repeat(20);
+        let code = format!("```rust\n{}\n```", ["let a = 1;"; 12].join("\n"));
+        let content = format!("{}\n\n{}\n\n{}", para, code, para);
+        let parts = split(&content, 100);
+        assert!(parts.iter().all(|p| p.len() <= 100), "{:?}", parts);
+        assert!(parts[0].starts_with(&format!("(1/{})\n", parts.len())));
+        // 切开的代码块每部分都有完整的围栏
+        for p in parts.iter().filter(|p| p.contains("let a")) {
+            let fences = p.matches("```").count();
+            assert_eq!(fences % 2, 0, "{}", p);
+            assert!(p.contains("```rust\nlet a"), "{}", p);
+        }
+        let joined = parts.join("\n");
+        assert_eq!(joined.matches("let a = 1;").count(), 12);
+        assert_eq!(joined.matches('中').count(), 40);
+
+        // 单行超长时按字符切分
+        let parts = split(&"字".repeat(100), 64);
+        assert!(parts.iter().all(|p| p.len() <= 64));
+        assert_eq!(parts.join("").matches('字').count(), 100);
+    }
+
+    #[test]
+    fn test_split_message() {
+        let m = SendMsgReq::markdown("a\n\n".repeat(3000)).to_users(["u"]);
+        let parts = m.split();
+        assert_eq!(parts.len(), 3);
+        for p in parts {
+            match p {
+                SendMsgReq::Markdown(m) => {
+                    assert!(m.markdown.content.len() <= MARKDOWN_LIMIT);
+                    assert_eq!(m.common.to_user.as_deref(), Some("u"));
+                }
+                _ => panic!("unexpected message type"),
+            }
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use serde::{Deserialize, Serialize};
+
+// https://developer.work.weixin.qq.com/document/path/90236#模板卡片消息
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CardType {
+    TextNotice,
+    NewsNotice,
+    ButtonInteraction,
+    VoteInteraction,
+    MultipleInteraction,
+}
+
+impl CardType {
+    /// 按钮交互型、投票选择型和多项选择型卡片发送后会返回 response_code
+    pub fn is_interactive(&self) -> bool {
+        matches!(
+            self,
+            CardType::ButtonInteraction | CardType::VoteInteraction | CardType::MultipleInteraction
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TemplateCard {
+    pub card_type: CardType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<CardSource>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action_menu: Option<ActionMenu>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_title: Option<MainTitle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_area: Option<QuoteArea>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emphasis_content: Option<EmphasisContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub_title_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub horizontal_content_list: Option<Vec<HorizontalContent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jump_list: Option<Vec<JumpItem>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_action: Option<CardAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card_image: Option<CardImage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_text_area: Option<ImageTextArea>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vertical_content_list: Option<Vec<VerticalContent>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub button_selection: Option<Selection>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub button_list: Option<Vec<Button>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkbox: Option<Checkbox>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub select_list: Option<Vec<Selection>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submit_button: Option<SubmitButton>,
+    /// 仅更新卡片时有效，卡片变为不可交互状态后展示的文案
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace_text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CardSource {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    /// 0(默认) 灰色，1 黑色，2 红色，3 绿色
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc_color: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActionMenu {
+    pub desc: String,
+    pub action_list: Vec<ActionItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ActionItem {
+    pub text: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MainTitle {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuoteArea {
+    /// 0 或不填没有点击事件，1 跳转 url，2 跳转小程序
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub quote_type: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagepath: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EmphasisContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HorizontalContent {
+    /// 0 或不填普通文本，1 跳转 url，2 下载附件，3 点击跳转成员详情
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<i32>,
+    pub keyname: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JumpItem {
+    /// 0 或不填没有跳转，1 跳转 url，2 跳转小程序
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub jump_type: Option<i32>,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagepath: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CardAction {
+    /// 0 或不填没有跳转（仅交互型卡片），1 跳转 url，2 打开小程序
+    #[serde(rename = "type")]
+    pub action_type: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagepath: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CardImage {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aspect_ratio: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageTextArea {
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub area_type: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub appid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pagepath: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    pub image_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VerticalContent {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+}
+
+/// 下拉式的选择器，用于 button_selection 和 select_list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub question_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+    pub option_list: Vec<SelectOption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selected_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SelectOption {
+    pub id: String,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Button {
+    /// 0 或不填回调点击事件，1 跳转 url
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub button_type: Option<i32>,
+    pub text: String,
+    /// 1 蓝色(默认)，2 灰色，3 红色，4 绿色
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Checkbox {
+    pub question_key: String,
+    pub option_list: Vec<CheckboxOption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable: Option<bool>,
+    /// 0 单选(默认)，1 多选
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CheckboxOption {
+    pub id: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_checked: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubmitButton {
+    pub text: String,
+    pub key: String,
+}
+
+/// 收到模板卡片事件后对卡片的更新，按钮交互型卡片可以只替换按钮文案
+#[derive(Debug, Clone, PartialEq)]
+pub enum CardUpdate {
+    Button { replace_name: String },
+    Card(Box<TemplateCard>),
+}
+
+impl TemplateCard {
+    pub fn new(card_type: CardType) -> Self {
+        Self {
+            card_type,
+            source: None,
+            action_menu: None,
+            task_id: None,
+            main_title: None,
+            quote_area: None,
+            emphasis_content: None,
+            sub_title_text: None,
+            horizontal_content_list: None,
+            jump_list: None,
+            card_action: None,
+            card_image: None,
+            image_text_area: None,
+            vertical_content_list: None,
+            button_selection: None,
+            button_list: None,
+            checkbox: None,
+            select_list: None,
+            submit_button: None,
+            replace_text: None,
+        }
+    }
+    pub fn main_title(mut self, title: impl Into<String>, desc: impl Into<String>) -> Self {
+        self.main_title = Some(MainTitle {
+            title: Some(title.into()),
+            desc: Some(desc.into()),
+        });
+        self
+    }
+    /// 交互型卡片必填，同一个应用内不可重复
+    pub fn task_id(mut self, task_id: impl Into<String>) -> Self {
+        self.task_id = Some(task_id.into());
+        self
+    }
+    pub fn sub_title_text(mut self, text: impl Into<String>) -> Self {
+        self.sub_title_text = Some(text.into());
+        self
+    }
+    pub fn horizontal_content(
+        mut self,
+        keyname: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.horizontal_content_list
+            .get_or_insert_with(Vec::new)
+            .push(HorizontalContent {
+                content_type: None,
+                keyname: keyname.into(),
+                value: Some(value.into()),
+                url: None,
+                media_id: None,
+                userid: None,
+            });
+        self
+    }
+    /// 点击卡片跳转的链接
+    pub fn card_action_url(mut self, url: impl Into<String>) -> Self {
+        self.card_action = Some(CardAction {
+            action_type: 1,
+            url: Some(url.into()),
+            appid: None,
+            pagepath: None,
+        });
+        self
+    }
+    /// 更新卡片时使用，卡片变为不可交互状态后展示的文案
+    pub fn replace_text(mut self, text: impl Into<String>) -> Self {
+        self.replace_text = Some(text.into());
+        self
+    }
+    /// 回调点击事件的按钮，仅按钮交互型卡片有效
+    pub fn button(mut self, text: impl Into<String>, key: impl Into<String>, style: i32) -> Self {
+        self.button_list.get_or_insert_with(Vec::new).push(Button {
+            button_type: None,
+            text: text.into(),
+            style: Some(style),
+            key: Some(key.into()),
+            url: None,
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assert_json_diff::assert_json_eq;
+    use serde_json::json;
+
+    #[test]
+    fn test_button_interaction() {
+        let card = TemplateCard::new(CardType::ButtonInteraction)
+            .main_title("告警", "磁盘空间不足")
+            .task_id("task_1")
+            .horizontal_content("主机", "db-1")
+            .button("确认", "ack", 1)
+            .button("忽略", "ignore", 2);
+        assert!(card.card_type.is_interactive());
+        assert_json_eq!(
+            serde_json::to_value(&card).unwrap(),
+            json!({
+                "card_type": "button_interaction",
+                "task_id": "task_1",
+                "main_title": {"title": "告警", "desc": "磁盘空间不足"},
+                "horizontal_content_list": [{"keyname": "主机", "value": "db-1"}],
+                "button_list": [
+                    {"text": "确认", "style": 1, "key": "ack"},
+                    {"text": "忽略", "style": 2, "key": "ignore"}
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_unknown_card_type() {
+        assert!(serde_json::from_value::<TemplateCard>(json!({"card_type": "unknown"})).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+//! 测试用的企业微信接口
+use crate::backend::mp::capture::Capture;
+use crate::backend::mp::MP;
+use std::sync::Arc;
+
+pub const CORP_ID: &str = "wx49f0ab532d5d035a";
+pub const AES_KEY: &str = "kWxPEV2UEDyxWpmPdKC3F4dgPDmOvfKX1HGnEUDS1aQ";
+pub const TOKEN: &str = "123456";
+
+fn mp(api_base: &str) -> MP {
+    MP::new(CORP_ID, "secret", 1, AES_KEY, TOKEN).with_api_base(api_base)
+}
+
+/// 接口地址无法连接，调用都会失败
+pub fn unreachable_mp() -> Arc<MP> {
+    Arc::new(mp("http://127.0.0.1:1"))
+}
+
+/// 启动一个本地的企业微信接口，返回指向它的 MP，`Capture` 丢弃后接口不再可用
+pub fn fake_wecom() -> (Arc<MP>, Capture) {
+    let capture = Capture::start().unwrap();
+    (Arc::new(mp(capture.api_base())), capture)
+}
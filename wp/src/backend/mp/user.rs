@@ -0,0 +1,63 @@
+use crate::backend::mp::WecomError;
+use anyhow::Result;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct GetUserResp {
+    errcode: i64,
+    errmsg: String,
+    #[serde(default)]
+    department: Vec<i64>,
+}
+
+/// 读取成员所属的部门 id，需要应用对该成员有可见范围
+// https://developer.work.weixin.qq.com/document/path/90196
+pub async fn get_user_departments(
+    client: &reqwest::Client,
+    api_base: &str,
+    token: &str,
+    user_id: &str,
+) -> Result<Vec<i64>> {
+    let r = client
+        .get(format!("{api_base}/cgi-bin/user/get"))
+        .query(&[("access_token", token), ("userid", user_id)])
+        .send()
+        .await?
+        .json::<GetUserResp>()
+        .await?;
+    if r.errcode != 0 {
+        return Err(WecomError::new("读取成员失败", r.errcode, &r.errmsg).into());
+    }
+    Ok(r.department)
+}
+
+#[derive(Debug, Deserialize)]
+struct GetOAuthUserResp {
+    errcode: i64,
+    errmsg: String,
+    /// 企业成员才有 userid，非企业成员只有 openid
+    #[serde(default)]
+    userid: Option<String>,
+}
+
+/// 用网页授权的 code 换取成员 userid
+// https://developer.work.weixin.qq.com/document/path/91023
+pub async fn get_oauth_user(
+    client: &reqwest::Client,
+    api_base: &str,
+    token: &str,
+    code: &str,
+) -> Result<String> {
+    let r = client
+        .get(format!("{api_base}/cgi-bin/auth/getuserinfo"))
+        .query(&[("access_token", token), ("code", code)])
+        .send()
+        .await?
+        .json::<GetOAuthUserResp>()
+        .await?;
+    if r.errcode != 0 {
+        return Err(WecomError::new("网页授权失败", r.errcode, &r.errmsg).into());
+    }
+    r.userid
+        .ok_or_else(|| WecomError::new("网页授权失败", -1, "不是企业成员").into())
+}
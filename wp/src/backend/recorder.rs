@@ -0,0 +1,393 @@
+use crate::backend::auth::check_token;
+use crate::backend::chatglm::GLM;
+use crate::backend::dispatch::CallbackState;
+use crate::backend::error::{Error, Result};
+use crate::backend::handler::{Handlers, Router};
+use crate::backend::llm::Mock;
+use crate::backend::mp::capture::{Call, Capture};
+use crate::backend::mp::MP;
+use crate::backend::Config;
+use axum::extract::rejection::{QueryRejection, StringRejection};
+use axum::extract::{Extension, Query};
+use axum::http::HeaderMap;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct RecorderConfig {
+    /// 追加写入原始回调请求的文件，未配置时不录制
+    #[serde(default)]
+    pub path: Option<String>,
+    /// 回放接口的访问令牌，未配置时不开放回放
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// 一次原始的加密回调请求，每行一个 JSON
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub ts: i64,
+    pub query: String,
+    pub body: String,
+}
+
+pub struct Recorder {
+    path: Option<Mutex<String>>,
+}
+
+impl Recorder {
+    pub fn new(conf: &RecorderConfig) -> Self {
+        Self {
+            path: conf.path.clone().map(Mutex::new),
+        }
+    }
+
+    pub async fn record(&self, query: &str, body: &str) {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return,
+        };
+        let req = RecordedRequest {
+            ts: time::OffsetDateTime::now_utc().unix_timestamp(),
+            query: query.to_string(),
+            body: body.to_string(),
+        };
+        if let Err(e) = append(path, &req).await {
+            warn!("record callback failed: {:?}", e);
+        }
+    }
+
+    pub async fn load(&self) -> anyhow::Result<Vec<RecordedRequest>> {
+        let path = match &self.path {
+            Some(p) => p.lock().await,
+            None => return Err(anyhow::anyhow!("未配置 recorder.path")),
+        };
+        let s = tokio::fs::read_to_string(path.as_str()).await?;
+        parse_records(&s)
+    }
+}
+
+async fn append(path: &Mutex<String>, req: &RecordedRequest) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    let path = path.lock().await;
+    let mut f = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path.as_str())
+        .await?;
+    f.write_all(line.as_bytes()).await?;
+    f.flush().await?;
+    Ok(())
+}
+
+pub fn parse_records(s: &str) -> anyhow::Result<Vec<RecordedRequest>> {
+    s.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            serde_json::from_str(l).map_err(|e| anyhow::anyhow!("第 {} 行格式不正确: {}", i + 1, e))
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ReplayQuery {
+    #[serde(default)]
+    token: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplayFailure {
+    pub index: usize,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplayReply {
+    pub index: usize,
+    pub msg_type: String,
+    /// 加密前的被动回复
+    pub xml: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplayResult {
+    pub errcode: i64,
+    pub errmsg: String,
+    pub total: usize,
+    /// 成功解密并交给处理器的消息类型
+    pub replayed: Vec<String>,
+    pub failed: Vec<ReplayFailure>,
+    /// 处理器返回的被动回复
+    pub replies: Vec<ReplayReply>,
+    /// 处理器调用的企业微信接口，没有真正发出
+    pub calls: Vec<Call>,
+}
+
+/// 回放结束后等待后台任务发送消息，超过这个时间没有新的调用就返回
+const REPLAY_QUIET: Duration = Duration::from_millis(200);
+/// 回放结束后最多等待的时间
+const REPLAY_SETTLE: Duration = Duration::from_secs(5);
+
+/// 回放使用的处理器：企业微信接口指向 `capture`，大模型换成 Mock，
+/// 对话上下文和统计也不和线上共用
+fn dry_run(handlers: &Handlers, conf: &Config, capture: &Capture) -> anyhow::Result<Router> {
+    let mp = MP::new(
+        &conf.corp_id,
+        &conf.corp_secret,
+        conf.agent_id,
+        &conf.encoded_aes_key,
+        &conf.token,
+    )
+    .with_api_base(capture.api_base());
+    Handlers {
+        mp: Arc::new(mp),
+        glm: Arc::new(GLM::new(Arc::new(Mock::new(None)), &conf.chat)),
+        chat_mgr: Default::default(),
+        metrics: Default::default(),
+        ..handlers.clone()
+    }
+    .router(conf)
+}
+
+/// 回放录制的回调，请求体为录制文件的内容，为空时回放整个录制文件。
+/// 不校验签名和时间戳，跳过去重和推送。处理器在模拟的企业微信接口和大模型上执行，
+/// 返回被动回复和处理器本来会调用的接口
+pub async fn replay(
+    Extension(state): Extension<CallbackState>,
+    Extension(recorder): Extension<Arc<Recorder>>,
+    Extension(handlers): Extension<Handlers>,
+    Extension(conf): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    q: std::result::Result<Query<ReplayQuery>, QueryRejection>,
+    body: std::result::Result<String, StringRejection>,
+) -> Result<Json<ReplayResult>> {
+    let (Query(q), body) = (q?, body?);
+    check_token(
+        conf.recorder.token.as_deref(),
+        "recorder.token",
+        &headers,
+        q.token.as_deref(),
+    )?;
+    let records = if body.trim().is_empty() {
+        recorder
+            .load()
+            .await
+            .map_err(|e| Error::BadRequest(format!("{:#}", e)))?
+    } else {
+        parse_records(&body).map_err(|e| Error::BadRequest(e.to_string()))?
+    };
+    let capture = Capture::start()?;
+    let router = dry_run(&handlers, &conf, &capture)?;
+    let mut r = ReplayResult {
+        errcode: 0,
+        errmsg: "ok".to_string(),
+        total: records.len(),
+        replayed: vec![],
+        failed: vec![],
+        replies: vec![],
+        calls: vec![],
+    };
+    for (index, req) in records.iter().enumerate() {
+        match state.mp.handle_msg_unverified(&req.body) {
+            Ok(msg) => {
+                r.replayed.push(msg.msg_type().to_string());
+                if let Some(reply) = router.route(&msg, true).await {
+                    let to_user = msg.from_user_name().unwrap_or_default();
+                    r.replies.push(ReplayReply {
+                        index,
+                        msg_type: reply.msg_type().to_string(),
+                        xml: reply.to_xml(to_user, &conf.corp_id, req.ts),
+                    });
+                }
+            }
+            Err(e) => r.failed.push(ReplayFailure {
+                index,
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+    capture.settle(REPLAY_QUIET, REPLAY_SETTLE).await;
+    r.calls = capture.take();
+    info!(
+        total = r.total,
+        failed = r.failed.len(),
+        calls = r.calls.len(),
+        "replay recorded callbacks"
+    );
+    Ok(Json(r))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::card::ReplaceNameHandler;
+    use crate::backend::dedupe::Dedupe;
+    use crate::backend::forward::Forwarder;
+    use crate::backend::llm::{ChatMessage, LlmBackend};
+    use crate::backend::metrics::Metrics;
+    use crate::backend::mp::test_util::{fake_wecom, AES_KEY, CORP_ID};
+    use crate::backend::rules::RuleSet;
+    use crate::backend::stream::StreamHub;
+    use base64::Engine;
+    use wechat_crypto::{decode_aes_key, encrypt};
+
+    /// 线上的大模型，回放时不应该被调用
+    struct Pending;
+
+    #[async_trait::async_trait]
+    impl LlmBackend for Pending {
+        fn name(&self) -> &'static str {
+            "pending"
+        }
+        async fn chat(&self, _messages: &[ChatMessage]) -> anyhow::Result<String> {
+            std::future::pending().await
+        }
+    }
+
+    fn record(plaintext: &str) -> String {
+        let key = decode_aes_key(AES_KEY).unwrap();
+        let encrypted = base64::engine::general_purpose::STANDARD
+            .encode(encrypt(&key, plaintext, CORP_ID).unwrap());
+        let body = format!(
+            "<xml><ToUserName><![CDATA[wx49f0ab532d5d035a]]></ToUserName>\
+             <Encrypt><![CDATA[{}]]></Encrypt><AgentID><![CDATA[1]]></AgentID></xml>",
+            encrypted
+        );
+        serde_json::to_string(&RecordedRequest {
+            ts: 0,
+            query: String::new(),
+            body,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_replay_dry_run() -> anyhow::Result<()> {
+        // 线上使用的企业微信接口，回放时不应该被调用
+        let (mp, live) = fake_wecom();
+        let conf: Config = toml::from_str(
+            r#"
+corp_id = "wx49f0ab532d5d035a"
+corp_secret = "secret"
+agent_id = 1
+encoded_aes_key = "kWxPEV2UEDyxWpmPdKC3F4dgPDmOvfKX1HGnEUDS1aQ"
+token = "123456"
+
+[recorder]
+token = "replay"
+
+[[rules.items]]
+match = "exact"
+pattern = "帮助"
+reply = { type = "markdown", content = "**帮助**" }
+"#,
+        )?;
+        let metrics = Arc::new(Metrics::default());
+        let handlers = Handlers {
+            mp: mp.clone(),
+            glm: Arc::new(GLM::new(Arc::new(Pending), &Default::default())),
+            chat_mgr: Default::default(),
+            metrics: metrics.clone(),
+            forwarder: Arc::new(Forwarder::new(&Default::default(), metrics.clone())?),
+            rules: Arc::new(RuleSet::new(&conf.rules)?),
+            card_handler: Arc::new(ReplaceNameHandler::new(&Default::default())),
+        };
+        let state = CallbackState {
+            mp,
+            dedupe: Arc::new(Dedupe::default()),
+            metrics,
+            hub: Arc::new(StreamHub::new(10)),
+            router: Arc::new(handlers.clone().router(&conf)?),
+        };
+        let text = |content: &str| {
+            record(&format!("<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[u]]></FromUserName><CreateTime>1348831860</CreateTime><MsgType><![CDATA[text]]></MsgType><Content><![CDATA[{}]]></Content><MsgId>1234567890123456</MsgId><AgentID>1</AgentID></xml>", content))
+        };
+        let body = [
+            text("/clean"),
+            text("帮助"),
+            text("你好"),
+            record("<xml><ToUserName><![CDATA[toUser]]></ToUserName><FromUserName><![CDATA[u]]></FromUserName><CreateTime>1408091189</CreateTime><MsgType><![CDATA[event]]></MsgType><Event><![CDATA[template_card_event]]></Event><EventKey><![CDATA[ack]]></EventKey><TaskId><![CDATA[taskid111]]></TaskId><CardType><![CDATA[button_interaction]]></CardType><ResponseCode><![CDATA[ResponseCode]]></ResponseCode><AgentID>1</AgentID></xml>"),
+        ]
+        .join("\n");
+        let Json(r) = replay(
+            Extension(state),
+            Extension(Arc::new(Recorder::new(&Default::default()))),
+            Extension(handlers),
+            Extension(Arc::new(conf)),
+            HeaderMap::new(),
+            Ok(Query(ReplayQuery {
+                token: Some("replay".to_string()),
+            })),
+            Ok(body),
+        )
+        .await?;
+        assert_eq!(r.replayed, vec!["text", "text", "text", "event"]);
+        assert!(r.failed.is_empty());
+
+        // 斜杠命令的被动回复
+        assert_eq!(r.replies.len(), 1);
+        assert_eq!(r.replies[0].index, 0);
+        assert!(
+            r.replies[0].xml.contains("让我们开始新的对话吧"),
+            "{}",
+            r.replies[0].xml
+        );
+        // 自动回复和 Mock 大模型的回答发到了模拟的接口，卡片的 response_code 没有使用
+        let sent = r
+            .calls
+            .iter()
+            .map(|c| {
+                (
+                    c.path.as_str(),
+                    c.body["msgtype"].as_str().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            vec![
+                ("/cgi-bin/message/send", "markdown"),
+                ("/cgi-bin/message/send", "markdown"),
+            ]
+        );
+        assert_eq!(r.calls[0].body["markdown"]["content"], "**帮助**");
+        let answer = r.calls[1].body["markdown"]["content"].as_str().unwrap();
+        assert!(answer.starts_with("你好\n"), "{}", answer);
+        assert!(live.take().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_record_and_load() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("wp_recorder_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let r = Recorder::new(&RecorderConfig {
+            path: Some(path.to_string_lossy().to_string()),
+            token: None,
+        });
+        r.record("msg_signature=a&timestamp=1&nonce=2", "<xml>1</xml>")
+            .await;
+        r.record("msg_signature=b&timestamp=3&nonce=4", "<xml>2</xml>")
+            .await;
+        let records = r.load().await?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].query, "msg_signature=b&timestamp=3&nonce=4");
+        assert_eq!(records[1].body, "<xml>2</xml>");
+        std::fs::remove_file(&path)?;
+
+        assert!(Recorder::new(&RecorderConfig::default())
+            .load()
+            .await
+            .is_err());
+        assert!(parse_records("{}\n").is_err());
+        assert!(parse_records("\n").unwrap().is_empty());
+        Ok(())
+    }
+}
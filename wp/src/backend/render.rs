@@ -0,0 +1,227 @@
+use crate::backend::mp::Message;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+// 企业微信应用消息的 markdown 只支持标题、加粗、链接、行内代码、引用和
+// <font color> 标签，表格、列表、代码块都会原样显示。微信插件中的成员
+// 收不到 markdown 消息，只能发送文本
+lazy_static! {
+    static ref LIST_ITEM: Regex = Regex::new(r"^(\s*)([-*+]|\d+[.)])\s+(.*)$").unwrap();
+    static ref HR: Regex = Regex::new(r"^\s*([-*_])(\s*[-*_]){2,}\s*$").unwrap();
+    static ref HEADING: Regex = Regex::new(r"^#{1,6}\s+(.*)$").unwrap();
+    static ref IMAGE: Regex = Regex::new(r"!\[([^\]]*)\]\(([^)\s]+)\)").unwrap();
+    static ref LINK: Regex = Regex::new(r"\[([^\]]+)\]\(([^)\s]+)\)").unwrap();
+    static ref BOLD: Regex = Regex::new(r"\*\*(.+?)\*\*|__(.+?)__").unwrap();
+    static ref STRIKE: Regex = Regex::new(r"~~(.+?)~~").unwrap();
+    static ref FONT: Regex = Regex::new(r"<font[^>]*>(.*?)</font>").unwrap();
+    static ref CODE: Regex = Regex::new(r"`([^`]+)`").unwrap();
+}
+
+const BULLETS: [&str; 3] = ["•", "◦", "▪"];
+
+/// 把大模型输出的 markdown 转成企业微信支持的子集
+pub fn wecom_markdown(s: &str) -> String {
+    render(s, false)
+}
+
+/// 去掉 markdown 标记，用于文本消息
+pub fn plain_text(s: &str) -> String {
+    render(s, true)
+}
+
+/// 转换后的 markdown 消息，`plain` 时为文本消息
+pub fn message(s: &str, plain: bool) -> Message {
+    if plain {
+        Message::text(plain_text(s))
+    } else {
+        Message::markdown(wecom_markdown(s))
+    }
+}
+
+/// 最多保留 `n` 个字符，超出时加上省略号
+pub fn truncate(s: &str, n: usize) -> String {
+    let mut r = s.chars().take(n).collect::<String>();
+    if s.chars().count() > n {
+        r.push('…');
+    }
+    r
+}
+
+fn render(s: &str, plain: bool) -> String {
+    let lines = s.lines().collect::<Vec<_>>();
+    let mut out = Vec::with_capacity(lines.len());
+    let mut fenced = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if line.trim_start().starts_with("```") {
+            fenced = !fenced;
+            continue;
+        }
+        if fenced {
+            out.push(code_line(line, plain));
+            continue;
+        }
+        if i < lines.len() && is_table_row(line) && is_table_sep(lines[i]) {
+            let header = cells(line);
+            i += 1;
+            while i < lines.len() && is_table_row(lines[i]) {
+                out.push(inline(&table_row(&header, &cells(lines[i]), plain), plain));
+                i += 1;
+            }
+            continue;
+        }
+        out.push(inline(&block_line(line, plain), plain));
+    }
+    out.join("\n")
+}
+
+/// 代码块中的每行作为行内代码，保留缩进
+fn code_line(line: &str, plain: bool) -> String {
+    if plain || line.trim().is_empty() || line.contains('`') {
+        return line.to_string();
+    }
+    let indent = line.len() - line.trim_start().len();
+    format!("{}`{}`", "\u{3000}".repeat(indent / 2), line.trim_start())
+}
+
+fn is_table_row(line: &str) -> bool {
+    let t = line.trim();
+    t.starts_with('|') && t.len() > 1
+}
+
+fn is_table_sep(line: &str) -> bool {
+    is_table_row(line)
+        && cells(line)
+            .iter()
+            .all(|c| !c.is_empty() && c.chars().all(|c| matches!(c, '-' | ':' | ' ')))
+}
+
+fn cells(line: &str) -> Vec<String> {
+    let t = line.trim().trim_start_matches('|').trim_end_matches('|');
+    t.split('|').map(|c| c.trim().to_string()).collect()
+}
+
+/// 表格的每行转成“列名：值”
+fn table_row(header: &[String], row: &[String], plain: bool) -> String {
+    row.iter()
+        .enumerate()
+        .map(|(i, c)| match header.get(i).filter(|h| !h.is_empty()) {
+            Some(h) if plain => format!("{}：{}", h, c),
+            Some(h) => format!("**{}**：{}", h, c),
+            None => c.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("，")
+}
+
+fn block_line(line: &str, plain: bool) -> String {
+    if HR.is_match(line) {
+        return "──────────".to_string();
+    }
+    if let Some(c) = LIST_ITEM.captures(line) {
+        let depth = c[1].replace('\t', "  ").len() / 2;
+        let marker = &c[2];
+        let marker = if marker.chars().next().unwrap().is_ascii_digit() {
+            marker.to_string()
+        } else {
+            BULLETS[depth % BULLETS.len()].to_string()
+        };
+        return format!("{}{} {}", "\u{3000}".repeat(depth), marker, &c[3]);
+    }
+    if plain {
+        if let Some(c) = HEADING.captures(line) {
+            return c[1].to_string();
+        }
+        if let Some(q) = line.trim_start().strip_prefix('>') {
+            return q.trim_start().to_string();
+        }
+    }
+    line.to_string()
+}
+
+fn inline(s: &str, plain: bool) -> String {
+    let s = IMAGE.replace_all(s, |c: &Captures| {
+        let alt = if c[1].is_empty() { "图片" } else { &c[1] };
+        if plain {
+            format!("{}（{}）", alt, &c[2])
+        } else {
+            format!("[{}]({})", alt, &c[2])
+        }
+    });
+    let s = STRIKE.replace_all(&s, "$1");
+    if !plain {
+        return BOLD
+            .replace_all(&s, |c: &Captures| {
+                format!("**{}**", c.get(1).or(c.get(2)).unwrap().as_str())
+            })
+            .into_owned();
+    }
+    let s = LINK.replace_all(&s, "$1（$2）");
+    let s = BOLD.replace_all(&s, |c: &Captures| {
+        c.get(1).or(c.get(2)).unwrap().as_str().to_string()
+    });
+    let s = FONT.replace_all(&s, "$1");
+    CODE.replace_all(&s, "$1").into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ANSWER: &str = "## 结论
+__注意__：见[文档](https://a.com)
+| 名称 | 价格 |
+| --- | ---: |
+| 苹果 | 5 |
+
+- 水果
+  - 苹果
+1. 第一步
+---
+```rust
+fn main() {
+    println!();
+}
+```
+> 引用 `code`";
+
+    #[test]
+    fn test_wecom_markdown() {
+        assert_eq!(
+            wecom_markdown(ANSWER),
+            "## 结论
+**注意**：见[文档](https://a.com)
+**名称**：苹果，**价格**：5
+
+• 水果
+\u{3000}◦ 苹果
+1. 第一步
+──────────
+`fn main() {`
+\u{3000}\u{3000}`println!();`
+`}`
+> 引用 `code`"
+        );
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(
+            plain_text(ANSWER),
+            "结论
+注意：见文档（https://a.com）
+名称：苹果，价格：5
+
+• 水果
+\u{3000}◦ 苹果
+1. 第一步
+──────────
+fn main() {
+    println!();
+}
+引用 code"
+        );
+    }
+}
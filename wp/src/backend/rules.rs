@@ -0,0 +1,425 @@
+use crate::backend::mp::{Message, NewsArticle, PassiveReply, MP};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+/// 成员所属部门的缓存时间
+const DEPARTMENT_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RulesConfig {
+    /// 检查配置文件是否修改的间隔，默认 0 不热加载
+    #[serde(default)]
+    pub reload_secs: u64,
+    #[serde(default)]
+    pub items: Vec<RuleConfig>,
+}
+
+/// 匹配方式，声明顺序即优先级
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    Exact,
+    Prefix,
+    Regex,
+}
+
+/// 一条自动回复规则，例如
+///
+/// ```toml
+/// [[rules.items]]
+/// match = "prefix"
+/// pattern = "报销"
+/// departments = [2]
+/// reply = { type = "text", content = "报销流程见 https://example.com" }
+/// ```
+#[derive(Debug, Deserialize, Clone)]
+pub struct RuleConfig {
+    /// 用于日志和指标，默认为 pattern
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(rename = "match")]
+    pub kind: MatchKind,
+    pub pattern: String,
+    /// 只对这些成员生效
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// 只对直接属于这些部门的成员生效，与 users 同时配置时满足其一即可
+    #[serde(default)]
+    pub departments: Vec<i64>,
+    pub reply: RuleReply,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RuleReply {
+    Text {
+        content: String,
+    },
+    Markdown {
+        content: String,
+    },
+    News {
+        articles: Vec<NewsArticle>,
+    },
+    Textcard {
+        title: String,
+        description: String,
+        url: String,
+        #[serde(default)]
+        btn_txt: Option<String>,
+    },
+    Image {
+        media_id: String,
+    },
+}
+
+impl RuleReply {
+    /// 文本、图片和图文可以被动回复，其他类型需要调用发送消息接口
+    pub fn passive(&self) -> Option<PassiveReply> {
+        match self {
+            RuleReply::Text { content } => Some(PassiveReply::text(content.clone())),
+            RuleReply::Image { media_id } => Some(PassiveReply::Image {
+                media_id: media_id.clone(),
+            }),
+            RuleReply::News { articles } => Some(PassiveReply::News(articles.clone())),
+            RuleReply::Markdown { .. } | RuleReply::Textcard { .. } => None,
+        }
+    }
+
+    pub fn message(&self, to_user: &str) -> Message {
+        let msg = match self {
+            RuleReply::Text { content } => Message::text(content.clone()),
+            RuleReply::Markdown { content } => Message::markdown(content.clone()),
+            RuleReply::News { articles } => Message::news(articles.clone()),
+            RuleReply::Textcard {
+                title,
+                description,
+                url,
+                btn_txt,
+            } => {
+                let msg = Message::textcard(title.clone(), description.clone(), url.clone());
+                match btn_txt {
+                    Some(b) => msg.btn_txt(b.clone()),
+                    None => msg,
+                }
+            }
+            RuleReply::Image { media_id } => Message::image(media_id.clone()),
+        };
+        msg.to_users([to_user])
+    }
+}
+
+/// 生效范围，声明顺序即优先级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Scope {
+    User,
+    Department,
+    Global,
+}
+
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    kind: MatchKind,
+    pattern: String,
+    regex: Option<Regex>,
+    users: Vec<String>,
+    departments: Vec<i64>,
+    reply: RuleReply,
+}
+
+impl Rule {
+    fn compile(c: &RuleConfig) -> Result<Self> {
+        let regex = match c.kind {
+            MatchKind::Regex => Some(
+                Regex::new(&c.pattern)
+                    .with_context(|| format!("规则 {} 的正则表达式不正确", c.pattern))?,
+            ),
+            _ => None,
+        };
+        Ok(Self {
+            name: c.name.clone().unwrap_or_else(|| c.pattern.clone()),
+            kind: c.kind,
+            pattern: c.pattern.clone(),
+            regex,
+            users: c.users.clone(),
+            departments: c.departments.clone(),
+            reply: c.reply.clone(),
+        })
+    }
+
+    fn matches(&self, content: &str) -> bool {
+        match (&self.kind, &self.regex) {
+            (MatchKind::Exact, _) => content == self.pattern,
+            (MatchKind::Prefix, _) => content.starts_with(&self.pattern),
+            (MatchKind::Regex, Some(re)) => re.is_match(content),
+            (MatchKind::Regex, None) => false,
+        }
+    }
+
+    /// 成员不在生效范围内时返回 None
+    fn scope(&self, user: &str, departments: &[i64]) -> Option<Scope> {
+        if self.users.is_empty() && self.departments.is_empty() {
+            return Some(Scope::Global);
+        }
+        if self.users.iter().any(|u| u == user) {
+            return Some(Scope::User);
+        }
+        if self.departments.iter().any(|d| departments.contains(d)) {
+            return Some(Scope::Department);
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matched {
+    pub name: String,
+    pub reply: RuleReply,
+}
+
+/// 在调用大模型之前按配置自动回复。
+/// 多条规则同时命中时，精确匹配 > 前缀匹配 > 正则匹配，匹配方式相同时成员 > 部门 > 全局，
+/// 仍然相同时取配置中靠前的规则
+pub struct RuleSet {
+    rules: RwLock<Vec<Rule>>,
+    departments: Mutex<HashMap<String, (Vec<i64>, Instant)>>,
+}
+
+impl RuleSet {
+    pub fn new(conf: &RulesConfig) -> Result<Self> {
+        Ok(Self {
+            rules: RwLock::new(compile(conf)?),
+            departments: Default::default(),
+        })
+    }
+
+    /// 替换全部规则，配置有误时保留原有规则
+    pub fn reload(&self, conf: &RulesConfig) -> Result<usize> {
+        let rules = compile(conf)?;
+        let n = rules.len();
+        *self.rules.write().unwrap() = rules;
+        Ok(n)
+    }
+
+    /// 从配置文件的 `[rules]` 重新加载
+    pub fn reload_file(&self, path: &str) -> Result<usize> {
+        #[derive(Deserialize)]
+        struct RulesFile {
+            #[serde(default)]
+            rules: RulesConfig,
+        }
+        let s = std::fs::read_to_string(path).with_context(|| format!("读取 {} 失败", path))?;
+        let f: RulesFile = toml::from_str(&s).context("解析规则失败")?;
+        self.reload(&f.rules)
+    }
+
+    /// 定时检查配置文件的修改时间，变化后重新加载规则
+    pub fn watch(self: &Arc<Self>, path: String, interval: Duration) {
+        let rules = self.clone();
+        tokio::spawn(async move {
+            let mut modified = mtime(&path);
+            loop {
+                tokio::time::sleep(interval).await;
+                let m = mtime(&path);
+                if m.is_none() || m == modified {
+                    continue;
+                }
+                modified = m;
+                match rules.reload_file(&path) {
+                    Ok(n) => info!(path, rules = n, "reload rules"),
+                    Err(e) => warn!(path, "reload rules failed, keep previous rules: {:#}", e),
+                }
+            }
+        });
+    }
+
+    pub fn find(&self, user: &str, content: &str, departments: &[i64]) -> Option<Matched> {
+        let content = content.trim();
+        let rules = self.rules.read().unwrap();
+        rules
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.matches(content))
+            .filter_map(|(i, r)| r.scope(user, departments).map(|s| ((r.kind, s, i), r)))
+            .min_by_key(|(k, _)| *k)
+            .map(|(_, r)| Matched {
+                name: r.name.clone(),
+                reply: r.reply.clone(),
+            })
+    }
+
+    /// 只有命中的规则限定了部门时才查询成员所属部门
+    pub async fn resolve(&self, mp: &MP, user: &str, content: &str) -> Option<Matched> {
+        let need_departments = {
+            let rules = self.rules.read().unwrap();
+            rules
+                .iter()
+                .any(|r| !r.departments.is_empty() && r.matches(content.trim()))
+        };
+        let departments = if need_departments {
+            self.departments(mp, user).await
+        } else {
+            vec![]
+        };
+        self.find(user, content, &departments)
+    }
+
+    async fn departments(&self, mp: &MP, user: &str) -> Vec<i64> {
+        if let Some((d, at)) = self.departments.lock().unwrap().get(user) {
+            if at.elapsed() < DEPARTMENT_TTL {
+                return d.clone();
+            }
+        }
+        match mp.user_departments(user).await {
+            Ok(d) => {
+                self.departments
+                    .lock()
+                    .unwrap()
+                    .insert(user.to_string(), (d.clone(), Instant::now()));
+                d
+            }
+            Err(e) => {
+                warn!(u = user, "get user departments failed: {:?}", e);
+                vec![]
+            }
+        }
+    }
+}
+
+fn compile(conf: &RulesConfig) -> Result<Vec<Rule>> {
+    conf.items.iter().map(Rule::compile).collect()
+}
+
+fn mtime(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(kind: MatchKind, pattern: &str, reply: &str) -> RuleConfig {
+        RuleConfig {
+            name: Some(reply.to_string()),
+            kind,
+            pattern: pattern.to_string(),
+            users: vec![],
+            departments: vec![],
+            reply: RuleReply::Text {
+                content: reply.to_string(),
+            },
+        }
+    }
+
+    fn matched(rules: &RuleSet, user: &str, content: &str, departments: &[i64]) -> Option<String> {
+        rules.find(user, content, departments).map(|m| m.name)
+    }
+
+    #[test]
+    fn test_precedence() -> Result<()> {
+        let mut by_user = rule(MatchKind::Prefix, "报销", "prefix-user");
+        by_user.users = vec!["zhangsan".to_string()];
+        let mut by_dept = rule(MatchKind::Prefix, "报销", "prefix-dept");
+        by_dept.departments = vec![2];
+        let rules = RuleSet::new(&RulesConfig {
+            reload_secs: 0,
+            items: vec![
+                rule(MatchKind::Regex, "^报销.*流程$", "regex"),
+                rule(MatchKind::Prefix, "报销", "prefix-global"),
+                by_dept,
+                by_user,
+                rule(MatchKind::Prefix, "报销", "prefix-global-2"),
+                rule(MatchKind::Exact, "报销流程", "exact"),
+            ],
+        })?;
+
+        // 精确匹配优先于任何范围的前缀匹配
+        assert_eq!(
+            matched(&rules, "zhangsan", " 报销流程 ", &[2]).as_deref(),
+            Some("exact")
+        );
+        // 匹配方式相同时成员 > 部门 > 全局，再按配置顺序
+        assert_eq!(
+            matched(&rules, "zhangsan", "报销差旅流程", &[2]).as_deref(),
+            Some("prefix-user")
+        );
+        assert_eq!(
+            matched(&rules, "lisi", "报销差旅流程", &[2]).as_deref(),
+            Some("prefix-dept")
+        );
+        assert_eq!(
+            matched(&rules, "lisi", "报销差旅流程", &[3]).as_deref(),
+            Some("prefix-global")
+        );
+        assert_eq!(
+            matched(&rules, "lisi", "差旅报销流程", &[]).as_deref(),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_scope() -> Result<()> {
+        let mut r = rule(MatchKind::Regex, "(?i)^vpn", "vpn");
+        r.users = vec!["zhangsan".to_string()];
+        r.departments = vec![2];
+        let rules = RuleSet::new(&RulesConfig {
+            reload_secs: 0,
+            items: vec![r],
+        })?;
+        assert!(rules.find("zhangsan", "VPN 连不上", &[]).is_some());
+        assert!(rules.find("lisi", "VPN 连不上", &[2]).is_some());
+        assert!(rules.find("lisi", "VPN 连不上", &[3]).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("wp_rules_{}.toml", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        std::fs::write(
+            &path,
+            r#"
+corp_id = "ignored"
+
+[[rules.items]]
+match = "exact"
+pattern = "帮助"
+reply = { type = "markdown", content = "**帮助**" }
+
+[[rules.items]]
+match = "prefix"
+pattern = "新闻"
+reply = { type = "news", articles = [{ title = "t", url = "https://example.com" }] }
+"#,
+        )?;
+        let rules = RuleSet::new(&RulesConfig::default())?;
+        assert_eq!(rules.reload_file(&path)?, 2);
+        let m = rules.find("u", "帮助", &[]).unwrap();
+        assert_eq!(m.reply.passive(), None);
+        let m = rules.find("u", "新闻速递", &[]).unwrap();
+        assert_eq!(
+            m.reply.passive(),
+            Some(PassiveReply::News(vec![NewsArticle::new(
+                "t",
+                "https://example.com"
+            )]))
+        );
+
+        // 配置有误时保留原有规则
+        std::fs::write(
+            &path,
+            "[[rules.items]]\nmatch = \"regex\"\npattern = \"(\"\nreply = { type = \"text\", content = \"x\" }\n",
+        )?;
+        assert!(rules.reload_file(&path).is_err());
+        assert!(rules.find("u", "帮助", &[]).is_some());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+use crate::backend::auth::check_token;
+use crate::backend::error::Result;
+use crate::backend::forward::ForwardPayload;
+use crate::backend::mp::callback::CallbackMessage;
+use crate::backend::Config;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::rejection::WebSocketUpgradeRejection;
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{Extension, Query};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StreamConfig {
+    /// 访问令牌，通过 `Authorization: Bearer` 或 `?token=` 传入，未配置时不开放推送接口
+    #[serde(default)]
+    pub token: Option<String>,
+    /// 保留最近的消息条数，用于断线重连后按 Last-Event-ID 补发
+    #[serde(default = "default_buffer")]
+    pub buffer: usize,
+}
+
+fn default_buffer() -> usize {
+    1000
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            buffer: default_buffer(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamEvent {
+    pub id: u64,
+    msg_type: String,
+    event: Option<String>,
+    from_user: Option<String>,
+    /// 推送给客户端的 JSON
+    pub data: String,
+}
+
+#[derive(Serialize)]
+struct StreamPayload<'a> {
+    id: u64,
+    #[serde(flatten)]
+    inner: ForwardPayload<'a>,
+}
+
+struct Ring {
+    next_id: u64,
+    events: VecDeque<Arc<StreamEvent>>,
+}
+
+/// 把解密后的回调实时推送给 SSE / WebSocket 客户端，并保留最近的消息用于补发
+pub struct StreamHub {
+    tx: broadcast::Sender<Arc<StreamEvent>>,
+    capacity: usize,
+    ring: Mutex<Ring>,
+}
+
+impl StreamHub {
+    /// id 从启动时的毫秒时间戳开始，重启后客户端带来的旧 Last-Event-ID 不会大于新的 id
+    pub fn new(capacity: usize) -> Self {
+        let now = time::OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
+        Self::with_first_id(capacity, now as u64)
+    }
+
+    fn with_first_id(capacity: usize, first_id: u64) -> Self {
+        let capacity = capacity.max(1);
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            capacity,
+            ring: Mutex::new(Ring {
+                next_id: first_id.max(1),
+                events: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    pub fn publish(&self, msg: &CallbackMessage) {
+        // 持有锁直到发送完成，保证订阅时补发的消息和实时消息既不重复也不遗漏
+        let mut ring = self.ring.lock().unwrap();
+        let id = ring.next_id;
+        let data = match serde_json::to_string(&StreamPayload {
+            id,
+            inner: ForwardPayload::new(msg),
+        }) {
+            Ok(d) => d,
+            Err(e) => {
+                warn!("serialize stream payload failed: {:?}", e);
+                return;
+            }
+        };
+        ring.next_id += 1;
+        let e = Arc::new(StreamEvent {
+            id,
+            msg_type: msg.msg_type().to_string(),
+            event: msg.event().map(|e| e.to_string()),
+            from_user: msg.from_user_name().map(|u| u.to_string()),
+            data,
+        });
+        if ring.events.len() >= self.capacity {
+            ring.events.pop_front();
+        }
+        ring.events.push_back(e.clone());
+        // 没有订阅者时返回错误，忽略即可
+        let _ = self.tx.send(e);
+    }
+
+    /// 订阅新消息。`last_id` 不为空时先补发缓冲区中比它新的消息，
+    /// 比最新的 id 还大时说明不是本次启动发出的，补发整个缓冲区
+    pub fn subscribe(self: &Arc<Self>, last_id: Option<u64>, filter: StreamFilter) -> Subscription {
+        let ring = self.ring.lock().unwrap();
+        let rx = self.tx.subscribe();
+        let (pending, last_id) = match last_id {
+            Some(id) if id >= ring.next_id => {
+                warn!(
+                    last_id = id,
+                    next_id = ring.next_id,
+                    "stale stream last event id"
+                );
+                (ring.events.clone(), 0)
+            }
+            Some(id) => (Self::after(&ring, id), id),
+            None => (VecDeque::new(), ring.next_id - 1),
+        };
+        Subscription {
+            hub: self.clone(),
+            rx,
+            pending,
+            last_id,
+            filter,
+        }
+    }
+
+    fn after(ring: &Ring, id: u64) -> VecDeque<Arc<StreamEvent>> {
+        ring.events.iter().filter(|e| e.id > id).cloned().collect()
+    }
+}
+
+pub struct Subscription {
+    hub: Arc<StreamHub>,
+    rx: broadcast::Receiver<Arc<StreamEvent>>,
+    pending: VecDeque<Arc<StreamEvent>>,
+    last_id: u64,
+    filter: StreamFilter,
+}
+
+impl Subscription {
+    pub async fn next(&mut self) -> Option<Arc<StreamEvent>> {
+        loop {
+            let e = match self.pending.pop_front() {
+                Some(e) => e,
+                None => match self.rx.recv().await {
+                    Ok(e) => e,
+                    Err(RecvError::Lagged(n)) => {
+                        // 客户端消费太慢，从缓冲区补发仍然保留的消息
+                        warn!(skipped = n, "stream subscriber lagged");
+                        let ring = self.hub.ring.lock().unwrap();
+                        self.pending = StreamHub::after(&ring, self.last_id);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            if e.id <= self.last_id {
+                continue;
+            }
+            self.last_id = e.id;
+            if self.filter.matches(&e) {
+                return Some(e);
+            }
+        }
+    }
+}
+
+/// 逗号分隔的多个值，未填写的条件不限制
+#[derive(Debug, Deserialize, Default)]
+pub struct StreamQuery {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    msg_type: Option<String>,
+    #[serde(default)]
+    event: Option<String>,
+    #[serde(default)]
+    from_user: Option<String>,
+    #[serde(default)]
+    last_event_id: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct StreamFilter {
+    msg_types: Vec<String>,
+    events: Vec<String>,
+    from_users: Vec<String>,
+}
+
+impl StreamFilter {
+    fn matches(&self, e: &StreamEvent) -> bool {
+        fn allow(list: &[String], v: Option<&str>) -> bool {
+            list.is_empty() || v.map(|v| list.iter().any(|x| x == v)).unwrap_or(false)
+        }
+        allow(&self.msg_types, Some(&e.msg_type))
+            && allow(&self.events, e.event.as_deref())
+            && allow(&self.from_users, e.from_user.as_deref())
+    }
+}
+
+impl From<&StreamQuery> for StreamFilter {
+    fn from(q: &StreamQuery) -> Self {
+        fn split(s: &Option<String>) -> Vec<String> {
+            s.iter()
+                .flat_map(|s| s.split(','))
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect()
+        }
+        Self {
+            msg_types: split(&q.msg_type),
+            events: split(&q.event),
+            from_users: split(&q.from_user),
+        }
+    }
+}
+
+fn authorize(conf: &StreamConfig, headers: &HeaderMap, q: &StreamQuery) -> Result<()> {
+    check_token(
+        conf.token.as_deref(),
+        "stream.token",
+        headers,
+        q.token.as_deref(),
+    )
+}
+
+fn last_event_id(headers: &HeaderMap, q: &StreamQuery) -> Option<u64> {
+    headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .or(q.last_event_id)
+}
+
+pub async fn sse(
+    Extension(hub): Extension<Arc<StreamHub>>,
+    Extension(conf): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    q: std::result::Result<Query<StreamQuery>, QueryRejection>,
+) -> Result<impl IntoResponse> {
+    let Query(q) = q?;
+    authorize(&conf.stream, &headers, &q)?;
+    let sub = hub.subscribe(last_event_id(&headers, &q), StreamFilter::from(&q));
+    let s = stream::unfold(sub, |mut sub| async move {
+        let e = sub.next().await?;
+        let ev = Event::default().id(e.id.to_string()).data(&e.data);
+        Some((Ok::<_, Infallible>(ev), sub))
+    });
+    Ok(Sse::new(s).keep_alive(KeepAlive::default()))
+}
+
+pub async fn ws(
+    Extension(hub): Extension<Arc<StreamHub>>,
+    Extension(conf): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    q: std::result::Result<Query<StreamQuery>, QueryRejection>,
+    upgrade: std::result::Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<impl IntoResponse> {
+    let (Query(q), upgrade) = (q?, upgrade?);
+    authorize(&conf.stream, &headers, &q)?;
+    let sub = hub.subscribe(last_event_id(&headers, &q), StreamFilter::from(&q));
+    Ok(upgrade.on_upgrade(move |socket| ws_loop(socket, sub)))
+}
+
+async fn ws_loop(mut socket: WebSocket, mut sub: Subscription) {
+    info!("stream websocket connected");
+    loop {
+        tokio::select! {
+            e = sub.next() => match e {
+                Some(e) => {
+                    if socket.send(WsMessage::Text(e.data.clone())).await.is_err() {
+                        break;
+                    }
+                }
+                None => break,
+            },
+            m = socket.recv() => match m {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                _ => {}
+            },
+        }
+    }
+    info!("stream websocket closed");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::mp::callback::TextCallbackMessage;
+
+    fn text(from: &str) -> CallbackMessage {
+        CallbackMessage::Text(TextCallbackMessage {
+            to_user_name: "toUser".to_string(),
+            from_user_name: from.to_string(),
+            create_time: 1348831860,
+            msg_type: "text".to_string(),
+            content: "hi".to_string(),
+            msg_id: "1234567890123456".to_string(),
+            agent_id: "1".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_filter() {
+        let hub = Arc::new(StreamHub::with_first_id(2, 1));
+        hub.publish(&text("a"));
+        hub.publish(&text("b"));
+        hub.publish(&text("c"));
+
+        // 缓冲区只保留最近两条
+        let mut sub = hub.subscribe(Some(0), StreamFilter::default());
+        assert_eq!(sub.next().await.unwrap().id, 2);
+        assert_eq!(sub.next().await.unwrap().id, 3);
+
+        let q = StreamQuery {
+            from_user: Some("x, z".to_string()),
+            ..Default::default()
+        };
+        let mut only_x = hub.subscribe(None, StreamFilter::from(&q));
+        hub.publish(&text("y"));
+        hub.publish(&text("x"));
+        let e = only_x.next().await.unwrap();
+        assert_eq!(e.id, 5);
+        let v: serde_json::Value = serde_json::from_str(&e.data).unwrap();
+        assert_eq!(v["id"], 5);
+        assert_eq!(v["msg_type"], "text");
+        assert_eq!(v["message"]["FromUserName"], "x");
+
+        assert_eq!(sub.next().await.unwrap().id, 4);
+    }
+
+    #[tokio::test]
+    async fn test_lagged() {
+        let hub = Arc::new(StreamHub::with_first_id(4, 1));
+        let mut sub = hub.subscribe(None, StreamFilter::default());
+        for _ in 0..6 {
+            hub.publish(&text("a"));
+        }
+        // 落后超过通道容量后从缓冲区补发，id 保持连续
+        let mut ids = vec![];
+        for _ in 0..4 {
+            ids.push(sub.next().await.unwrap().id);
+        }
+        assert_eq!(ids, vec![3, 4, 5, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_stale_last_id() {
+        let hub = Arc::new(StreamHub::new(4));
+        hub.publish(&text("a"));
+        let first = hub
+            .subscribe(Some(0), StreamFilter::default())
+            .next()
+            .await
+            .unwrap()
+            .id;
+        assert!(first > 1_600_000_000_000);
+
+        // 上次启动时的 id 比现在的还大，补发缓冲区中的全部消息
+        let mut sub = hub.subscribe(Some(first + 100), StreamFilter::default());
+        assert_eq!(sub.next().await.unwrap().id, first);
+        hub.publish(&text("b"));
+        assert_eq!(sub.next().await.unwrap().id, first + 1);
+    }
+
+    #[test]
+    fn test_authorize() {
+        let q = StreamQuery::default();
+        let mut h = HeaderMap::new();
+        let mut conf = StreamConfig::default();
+        assert!(authorize(&conf, &h, &q).is_err());
+
+        conf.token = Some("secret".to_string());
+        assert!(authorize(&conf, &h, &q).is_err());
+        h.insert(
+            http::header::AUTHORIZATION,
+            "Bearer secret".parse().unwrap(),
+        );
+        assert!(authorize(&conf, &h, &q).is_ok());
+
+        let q = StreamQuery {
+            token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        assert!(authorize(&conf, &HeaderMap::new(), &q).is_ok());
+    }
+}
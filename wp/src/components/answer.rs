@@ -0,0 +1,78 @@
+use leptos::*;
+use leptos_meta::*;
+use leptos_router::*;
+use serde::{Deserialize, Serialize};
+
+/// 网页中展示的回答
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnswerView {
+    pub question: String,
+    /// 服务端转换好的 HTML
+    pub html: String,
+    pub created: String,
+}
+
+#[server(GetAnswer, "/api")]
+pub async fn get_answer(cx: Scope, id: String) -> Result<AnswerView, ServerFnError> {
+    use crate::backend::answer::{to_html, AnswerStore};
+    use std::sync::Arc;
+    use time::macros::{format_description, offset};
+
+    let store = use_context::<Arc<AnswerStore>>(cx)
+        .ok_or_else(|| ServerFnError::ServerError("未初始化回答存储".to_string()))?;
+    let now = time::OffsetDateTime::now_utc();
+    let answer = store
+        .get(&id, now.unix_timestamp())
+        .ok_or_else(|| ServerFnError::ServerError("回答不存在或已过期".to_string()))?;
+    let headers = use_context::<leptos_axum::RequestParts>(cx)
+        .map(|r| r.headers)
+        .unwrap_or_default();
+    if !store.allowed(&id, &answer, &headers) {
+        return Err(ServerFnError::ServerError(
+            "只有提问的成员可以查看，请从企业微信中的消息打开".to_string(),
+        ));
+    }
+    let created = time::OffsetDateTime::from_unix_timestamp(answer.created)
+        .unwrap_or(now)
+        .to_offset(offset!(+8))
+        .format(format_description!("[year]-[month]-[day] [hour]:[minute]"))
+        .unwrap_or_default();
+    Ok(AnswerView {
+        question: answer.question,
+        html: to_html(&answer.content),
+        created,
+    })
+}
+
+#[allow(non_snake_case)]
+#[component]
+pub fn AnswerPage(cx: Scope) -> impl IntoView {
+    let params = use_params_map(cx);
+    let answer = create_resource(
+        cx,
+        move || params.with(|p| p.get("id").cloned().unwrap_or_default()),
+        move |id| get_answer(cx, id),
+    );
+
+    view! {
+        cx,
+        <Title text="小秘书的回答"/>
+        <Meta name="viewport" content="width=device-width, initial-scale=1"/>
+        <Stylesheet href="https://cdn.jsdelivr.net/npm/highlight.js@11.8.0/styles/github.min.css"/>
+        <Suspense fallback=move || view! { cx, <p>"加载中…"</p> }>
+            {move || answer.read(cx).map(|r| match r {
+                Ok(a) => view! {
+                    cx,
+                    <article class="answer">
+                        <h2>{a.question}</h2>
+                        <p class="meta">{a.created}</p>
+                        <div class="markdown" inner_html=a.html></div>
+                    </article>
+                }.into_view(cx),
+                Err(e) => view! { cx, <p class="error">{e.to_string()}</p> }.into_view(cx),
+            })}
+        </Suspense>
+        <script src="https://cdn.jsdelivr.net/npm/highlight.js@11.8.0/highlight.min.js"></script>
+        <script>"hljs.highlightAll();"</script>
+    }
+}
@@ -1,3 +1,4 @@
+use crate::components::answer::AnswerPage;
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
@@ -6,7 +7,7 @@ use leptos_router::*;
 #[component]
 pub fn App(cx: Scope) -> impl IntoView {
     provide_meta_context(cx);
-    let formatter = |text| format!("{text}");
+    let formatter = |text: String| text;
 
     view! {
         cx,
@@ -17,8 +18,15 @@ pub fn App(cx: Scope) -> impl IntoView {
       // applies the `formatter` function to the `text` value
       formatter=formatter
     />
-    <div>
-        <h1>"Wechat Proxy"</h1>
-    </div>
+    <Router>
+        <Routes>
+            <Route path="" view=|cx| view! { cx,
+                <div>
+                    <h1>"Wechat Proxy"</h1>
+                </div>
+            }/>
+            <Route path="/answer/:id" view=AnswerPage ssr=SsrMode::Async/>
+        </Routes>
+    </Router>
     }
 }
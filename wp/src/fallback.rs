@@ -1,4 +1,4 @@
-use crate::error_template::{ErrorTemplate, ErrorTemplateProps};
+use crate::error_template::ErrorTemplate;
 use crate::errors::TodoAppError;
 use axum::response::Response as AxumResponse;
 use axum::{
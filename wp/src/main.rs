@@ -3,6 +3,6 @@ mod serv;
 
 #[cfg(feature = "ssr")]
 #[tokio::main]
-async fn main() {
-    serv::serv().await;
+async fn main() -> anyhow::Result<()> {
+    serv::serv().await
 }
@@ -1,3 +1,4 @@
+use anyhow::{Context, Result};
 use axum::extract::{Path, RawQuery};
 use axum::response::IntoResponse;
 use axum::{
@@ -12,14 +13,26 @@ use leptos::*;
 use leptos_axum::{generate_route_list, handle_server_fns_with_context, LeptosRoutes};
 use std::fs;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tower::ServiceBuilder;
 use tower_http::compression::CompressionLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{info, Level};
+use wp::backend::answer::{self, AnswerStore};
+use wp::backend::card::{ReplaceNameHandler, TemplateCardHandler};
 use wp::backend::chatglm::GLM;
 use wp::backend::context::ChatMgr;
+use wp::backend::dedupe::Dedupe;
+use wp::backend::dispatch::CallbackState;
+use wp::backend::forward::Forwarder;
+use wp::backend::handler::Handlers;
+use wp::backend::llm::LlmConfig;
+use wp::backend::metrics::Metrics;
 use wp::backend::mp::MP;
+use wp::backend::recorder::{self, Recorder};
+use wp::backend::rules::RuleSet;
+use wp::backend::stream::{self, StreamHub};
 use wp::components::home::*;
 use wp::fallback::file_and_error_handler;
 use wp::{api, backend};
@@ -34,7 +47,7 @@ struct Args {
     log: String,
 }
 
-pub async fn serv() {
+pub async fn serv() -> Result<()> {
     let args = Args::parse();
     // a builder for `FmtSubscriber`.
     let subscriber = tracing_subscriber::fmt::Subscriber::builder()
@@ -45,31 +58,86 @@ pub async fn serv() {
         // completes the builder.
         .finish();
 
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    tracing::subscriber::set_global_default(subscriber)
+        .context("setting default subscriber failed")?;
     // get pwd
-    let pwd = std::env::current_dir().unwrap();
+    let pwd = std::env::current_dir()?;
     info!(conf_path = &args.config, cwd = ?pwd, "Starting up",);
     info!("Version: {}", env!("COMMIT_ID"));
-    let contents =
-        fs::read_to_string(&args.config).expect("Should have been able to read the file");
-    let serv_conf: backend::Config = toml::from_str(contents.as_str()).unwrap();
-    let glm = GLM::new(&serv_conf.glm_api);
-    let chat_mgr = Arc::new(Mutex::new(ChatMgr::default()));
+    let contents = fs::read_to_string(&args.config)
+        .with_context(|| format!("读取配置文件 {} 失败", &args.config))?;
+    let serv_conf: backend::Config =
+        toml::from_str(contents.as_str()).context("解析配置文件失败")?;
+    let llm = match &serv_conf.llm {
+        Some(llm) => llm.clone(),
+        None if !serv_conf.glm_api.is_empty() => LlmConfig::chatglm(&serv_conf.glm_api),
+        None => return Err(anyhow::anyhow!("未配置 [llm] 或 glm_api")),
+    };
+    let answers = Arc::new(AnswerStore::new(&serv_conf.answer));
+    let models = serv_conf
+        .models
+        .iter()
+        .map(|(name, conf)| (name.clone(), conf.build()))
+        .collect();
+    let glm = Arc::new(
+        GLM::new(llm.build(), &serv_conf.chat)
+            .with_answers(answers.clone())
+            .with_progress(&serv_conf.progress)
+            .with_models(models),
+    );
+    let chat_mgr = Arc::new(Mutex::new(ChatMgr::new(&serv_conf.chat)));
+    let metrics = Arc::new(Metrics::default());
+    let forwarder = Arc::new(
+        Forwarder::new(&serv_conf.forward, metrics.clone()).context("初始化回调转发失败")?,
+    );
+    let hub = Arc::new(StreamHub::new(serv_conf.stream.buffer));
+    let rules = Arc::new(RuleSet::new(&serv_conf.rules).context("加载自动回复规则失败")?);
+    if serv_conf.rules.reload_secs > 0 {
+        rules.watch(
+            args.config.clone(),
+            Duration::from_secs(serv_conf.rules.reload_secs),
+        );
+    }
+    let card_handler: Arc<dyn TemplateCardHandler> =
+        Arc::new(ReplaceNameHandler::new(&serv_conf.card));
 
     let mp = MP::new(
         &serv_conf.corp_id,
         &serv_conf.corp_secret,
-        serv_conf.agent_id.clone(),
+        serv_conf.agent_id,
         &serv_conf.encoded_aes_key,
         &serv_conf.token,
     );
+    let mp = match &serv_conf.api_base {
+        Some(api_base) => mp.with_api_base(api_base),
+        None => mp,
+    };
     let amp = Arc::new(mp);
     let mp_l = amp.clone();
+    let answers_l = answers.clone();
+    let handlers = Handlers {
+        mp: amp.clone(),
+        glm: glm.clone(),
+        chat_mgr,
+        metrics: metrics.clone(),
+        forwarder,
+        rules,
+        card_handler,
+    };
+    let router = handlers.clone().router(&serv_conf)?;
+    let callback_state = CallbackState {
+        mp: amp.clone(),
+        dedupe: Arc::new(Dedupe::default()),
+        metrics: metrics.clone(),
+        hub: hub.clone(),
+        router: Arc::new(router),
+    };
+    let recorder = Arc::new(Recorder::new(&serv_conf.recorder));
 
     api::register_server_functions();
 
     // Setting this to None means we'll be using cargo-leptos and its env vars
-    let conf = get_configuration(None).await.unwrap();
+    let conf = get_configuration(None).await?;
     let leptos_options = conf.leptos_options.clone();
     let addr = leptos_options.site_addr;
     let routes = generate_route_list(|cx| view! { cx, <App/> }).await;
@@ -79,6 +147,7 @@ pub async fn serv() {
         .layer(CompressionLayer::new())
         .route("/liveness", get(|| async { "I'm alive!" }))
         .route("/readiness", get(|| async { "I'm ready!" }))
+        .route("/metrics", get(backend::api::metrics))
         .route(
             "/api/*fn_name",
             get(server_fn_handler).post(server_fn_handler),
@@ -88,6 +157,10 @@ pub async fn serv() {
             get(backend::api::validate_url).post(backend::api::on_message),
         )
         .route("/xx", get(backend::xx::xx_app_caller))
+        .route("/answer/auth", get(answer::oauth_callback))
+        .route("/stream/sse", get(stream::sse))
+        .route("/stream/ws", get(stream::ws))
+        .route("/replay", post(recorder::replay))
         .route("/cgi-bin/message/send", post(backend::api::message_send))
         .route("/cgi-bin/media/upload", post(backend::api::media_upload))
         .route(
@@ -108,16 +181,24 @@ pub async fn serv() {
         .leptos_routes_with_context(
             &leptos_options,
             routes,
-            move |cx| provide_context(cx, mp_l.clone()),
+            move |cx| {
+                provide_context(cx, mp_l.clone());
+                provide_context(cx, answers_l.clone());
+            },
             |cx| view! { cx, <App/> },
         )
         .fallback(file_and_error_handler)
         .with_state(leptos_options)
         // .layer(Extension(Arc::new(leptos_options)))
-        .layer(Extension(chat_mgr))
+        .layer(Extension(callback_state))
+        .layer(Extension(recorder))
+        .layer(Extension(handlers))
+        .layer(Extension(metrics))
+        .layer(Extension(hub))
         .layer(Extension(Arc::new(serv_conf)))
         .layer(Extension(amp))
-        .layer(Extension(Arc::new(glm)))
+        .layer(Extension(glm))
+        .layer(Extension(answers))
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http())
@@ -129,8 +210,8 @@ pub async fn serv() {
     info!("listening on http://{}", &addr);
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
-        .await
-        .unwrap();
+        .await?;
+    Ok(())
 }
 
 async fn server_fn_handler(
@@ -139,6 +220,7 @@ async fn server_fn_handler(
     raw_query: RawQuery,
     Extension(mp): Extension<Arc<MP>>,
     Extension(glm): Extension<Arc<GLM>>,
+    Extension(answers): Extension<Arc<AnswerStore>>,
     request: Request<AxumBody>,
 ) -> impl IntoResponse {
     handle_server_fns_with_context(
@@ -148,6 +230,7 @@ async fn server_fn_handler(
         move |cx| {
             provide_context(cx, mp.clone());
             provide_context(cx, glm.clone());
+            provide_context(cx, answers.clone());
         },
         request,
     )